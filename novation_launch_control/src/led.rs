@@ -0,0 +1,202 @@
+//! Building the midi messages that control the LEDs on the device.
+//!
+//! All messages are returned as fixed-size arrays so they can be built without allocating (e.g.
+//! in a realtime audio thread).
+
+/// The prefix of all Novation system exclusive messages for the LaunchControl XL.
+const SYSEX_HEADER: [u8; 6] = [0xf0, 0x00, 0x20, 0x29, 0x02, 0x11];
+const SYSEX_END: u8 = 0xf7;
+
+/// An LED colour, made by mixing red and green LEDs at brightnesses between 0 (off) and 3 (full).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Color {
+    red: u8,
+    green: u8,
+}
+
+impl Color {
+    pub const OFF: Color = Color::new(0, 0);
+    pub const RED: Color = Color::new(3, 0);
+    pub const GREEN: Color = Color::new(0, 3);
+    pub const AMBER: Color = Color::new(3, 3);
+    pub const YELLOW: Color = Color::new(2, 3);
+
+    /// Brightnesses greater than 3 are treated as 3.
+    pub const fn new(red: u8, green: u8) -> Self {
+        Color {
+            red: if red > 3 { 3 } else { red },
+            green: if green > 3 { 3 } else { green },
+        }
+    }
+
+    pub fn red(self) -> u8 {
+        self.red
+    }
+
+    pub fn green(self) -> u8 {
+        self.green
+    }
+}
+
+/// How an LED update interacts with the flashing and double-buffering features of the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Write to both buffers, so the LED shows the colour straight away.
+    Normal,
+    /// Write to the update buffer only, so the LED flashes when flashing is turned on (see
+    /// `buffer_control`).
+    Flash,
+    /// Write to the update buffer only, to be shown when the buffers are swapped.
+    DoubleBuffered,
+}
+
+impl Mode {
+    fn flags(self) -> u8 {
+        const COPY: u8 = 0b0000_0100;
+        const CLEAR: u8 = 0b0000_1000;
+        match self {
+            Mode::Normal => COPY | CLEAR,
+            Mode::Flash => CLEAR,
+            Mode::DoubleBuffered => 0,
+        }
+    }
+}
+
+/// The value to send for an LED, combining its colour and mode.
+pub fn velocity(color: Color, mode: Mode) -> u8 {
+    (color.green << 4) | mode.flags() | color.red
+}
+
+/// An LED on the device.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Led {
+    /// A knob, by row (0-2) and column (0-7).
+    Knob(u8, u8),
+    /// A button in the top row (track focus), by column.
+    Focus(u8),
+    /// A button in the bottom row (track control), by column.
+    Control(u8),
+    Device,
+    Mute,
+    Solo,
+    RecordArm,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Led {
+    /// The index the device uses for this LED.
+    pub fn index(self) -> u8 {
+        match self {
+            Led::Knob(row, column) => (row.min(2) << 3) | (column & 0x07),
+            Led::Focus(column) => 0x18 | (column & 0x07),
+            Led::Control(column) => 0x20 | (column & 0x07),
+            Led::Device => 0x28,
+            Led::Mute => 0x29,
+            Led::Solo => 0x2a,
+            Led::RecordArm => 0x2b,
+            Led::Up => 0x2c,
+            Led::Down => 0x2d,
+            Led::Left => 0x2e,
+            Led::Right => 0x2f,
+        }
+    }
+}
+
+/// Set the colour of an LED on the given template.
+pub fn set(template: u8, led: Led, color: Color, mode: Mode) -> [u8; 11] {
+    let h = SYSEX_HEADER;
+    [
+        h[0],
+        h[1],
+        h[2],
+        h[3],
+        h[4],
+        h[5],
+        0x78,
+        template & 0x0f,
+        led.index(),
+        velocity(color, mode),
+        SYSEX_END,
+    ]
+}
+
+/// Turn off all the LEDs on the given template, and reset flashing and double-buffering.
+pub fn reset(template: u8) -> [u8; 3] {
+    [0xb0 | (template & 0x0f), 0x00, 0x00]
+}
+
+/// Switch the device to the given template.
+pub fn select_template(template: u8) -> [u8; 9] {
+    let h = SYSEX_HEADER;
+    [
+        h[0],
+        h[1],
+        h[2],
+        h[3],
+        h[4],
+        h[5],
+        0x77,
+        template & 0x0f,
+        SYSEX_END,
+    ]
+}
+
+/// Control flashing and double-buffering on the given template.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct BufferControl {
+    /// The buffer (0 or 1) to show.
+    pub display: u8,
+    /// The buffer (0 or 1) that LED updates are written to.
+    pub update: u8,
+    /// Flash LEDs that were set with `Mode::Flash`.
+    pub flash: bool,
+    /// Copy the displayed buffer to the update buffer.
+    pub copy: bool,
+}
+
+/// Build the message for a `BufferControl`.
+pub fn buffer_control(template: u8, control: BufferControl) -> [u8; 3] {
+    let value = 0x20
+        | (control.display & 0x01)
+        | ((control.update & 0x01) << 2)
+        | ((control.flash as u8) << 3)
+        | ((control.copy as u8) << 4);
+    [0xb0 | (template & 0x0f), 0x00, value]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn velocity() {
+        assert_eq!(super::velocity(Color::GREEN, Mode::Normal), 0b0011_1100);
+        assert_eq!(super::velocity(Color::RED, Mode::Normal), 0b0000_1111);
+        assert_eq!(super::velocity(Color::OFF, Mode::Normal), 0b0000_1100);
+        assert_eq!(super::velocity(Color::AMBER, Mode::Flash), 0b0011_1011);
+    }
+
+    #[test]
+    fn set() {
+        assert_eq!(
+            super::set(8, Led::Focus(2), Color::RED, Mode::Normal),
+            [0xf0, 0x00, 0x20, 0x29, 0x02, 0x11, 0x78, 0x08, 0x1a, 0x0f, 0xf7]
+        );
+        assert_eq!(Led::Knob(2, 7).index(), 0x17);
+        assert_eq!(Led::Right.index(), 0x2f);
+    }
+
+    #[test]
+    fn buffer_control() {
+        let control = BufferControl {
+            display: 1,
+            update: 0,
+            flash: true,
+            copy: false,
+        };
+        assert_eq!(super::buffer_control(0, control), [0xb0, 0x00, 0x29]);
+    }
+}
@@ -1,4 +1,13 @@
 #![no_std]
+//! Conversion between raw midi messages and descriptive events for the Novation LaunchControl XL.
+//!
+//! The device has 16 templates, each of which sends on its own midi channel: 0-7 are the user
+//! templates and 8-15 are the factory templates. The controls send the same messages in every
+//! template (the factory layout), so this crate assumes the user templates haven't been edited.
+//!
+//! The `led` module builds the messages that set the LEDs on the device.
+
+pub mod led;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum Event {
@@ -56,17 +65,51 @@ pub enum Event {
     Button2_6(bool),
     Button2_7(bool),
     Button2_8(bool),
+    // side buttons
+    Device(bool),
+    Mute(bool),
+    Solo(bool),
+    RecordArm(bool),
+    Up(bool),
+    Down(bool),
+    Left(bool),
+    Right(bool),
+}
+
+/// The rows of controls on the device, from top to bottom.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Row {
+    Knobs1,
+    Knobs2,
+    Knobs3,
+    Faders,
+    Buttons1,
+    Buttons2,
+}
+
+/// The value sent by a control.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Value {
+    /// A knob or fader position, between 0 and 1.
+    Continuous(f32),
+    /// Whether a button is pressed.
+    Button(bool),
 }
 
 impl Event {
-    pub fn parse(raw: &[u8]) -> Option<Self> {
+    /// Parse a raw midi message from the device, returning the template it was sent from and the
+    /// event.
+    pub fn parse(raw: &[u8]) -> Option<(u8, Self)> {
         use Event::*;
 
-        Some(match raw.get(0)? {
-            // fader
-            0xb0 | 0xb8 => {
-                let val = (*raw.get(2)? as f32) / 127.0;
-                debug_assert_eq!(val.max(0.0).min(1.0), val);
+        let status = *raw.first()?;
+        let template = status & 0x0f;
+        let event = match status & 0xf0 {
+            // knob, fader or arrow button
+            0xb0 => {
+                let value = *raw.get(2)?;
+                let val = (value as f32) / 127.0;
+                debug_assert_eq!(val.clamp(0.0, 1.0), val);
                 match raw.get(1)? {
                     0x0d => Fader1_1(val),
                     0x0e => Fader1_2(val),
@@ -104,60 +147,242 @@ impl Event {
                     0x53 => Fader4_7(val),
                     0x54 => Fader4_8(val),
 
+                    0x68 => Up(value > 0),
+                    0x69 => Down(value > 0),
+                    0x6a => Left(value > 0),
+                    0x6b => Right(value > 0),
+
                     _ => return None,
                 }
             }
 
-            // button on
-            0x90 | 0x98 => match raw.get(1)? {
-                0x29 => Button1_1(true),
-                0x30 => Button1_2(true),
-                0x31 => Button1_3(true),
-                0x32 => Button1_4(true),
+            // button on (a velocity of 0 means off)
+            0x90 => button(*raw.get(1)?, *raw.get(2)? > 0)?,
+
+            // button off
+            0x80 => button(*raw.get(1)?, false)?,
+
+            _ => return None,
+        };
+        Some((template, event))
+    }
+
+    /// Turn the event back into the raw midi message the device would send from `template`.
+    pub fn encode(&self, template: u8) -> [u8; 3] {
+        use Event::*;
+
+        let channel = template & 0x0f;
+        let (controller, value) = match *self {
+            Fader1_1(v) => (0x0d, continuous(v)),
+            Fader1_2(v) => (0x0e, continuous(v)),
+            Fader1_3(v) => (0x0f, continuous(v)),
+            Fader1_4(v) => (0x10, continuous(v)),
+            Fader1_5(v) => (0x11, continuous(v)),
+            Fader1_6(v) => (0x12, continuous(v)),
+            Fader1_7(v) => (0x13, continuous(v)),
+            Fader1_8(v) => (0x14, continuous(v)),
 
-                0x39 => Button1_5(true),
-                0x40 => Button1_6(true),
-                0x41 => Button1_7(true),
-                0x42 => Button1_8(true),
+            Fader2_1(v) => (0x1d, continuous(v)),
+            Fader2_2(v) => (0x1e, continuous(v)),
+            Fader2_3(v) => (0x1f, continuous(v)),
+            Fader2_4(v) => (0x20, continuous(v)),
+            Fader2_5(v) => (0x21, continuous(v)),
+            Fader2_6(v) => (0x22, continuous(v)),
+            Fader2_7(v) => (0x23, continuous(v)),
+            Fader2_8(v) => (0x24, continuous(v)),
 
-                0x49 => Button2_1(true),
-                0x50 => Button2_2(true),
-                0x51 => Button2_3(true),
-                0x52 => Button2_4(true),
+            Fader3_1(v) => (0x31, continuous(v)),
+            Fader3_2(v) => (0x32, continuous(v)),
+            Fader3_3(v) => (0x33, continuous(v)),
+            Fader3_4(v) => (0x34, continuous(v)),
+            Fader3_5(v) => (0x35, continuous(v)),
+            Fader3_6(v) => (0x36, continuous(v)),
+            Fader3_7(v) => (0x37, continuous(v)),
+            Fader3_8(v) => (0x38, continuous(v)),
 
-                0x59 => Button2_5(true),
-                0x60 => Button2_6(true),
-                0x61 => Button2_7(true),
-                0x62 => Button2_8(true),
+            Fader4_1(v) => (0x4d, continuous(v)),
+            Fader4_2(v) => (0x4e, continuous(v)),
+            Fader4_3(v) => (0x4f, continuous(v)),
+            Fader4_4(v) => (0x50, continuous(v)),
+            Fader4_5(v) => (0x51, continuous(v)),
+            Fader4_6(v) => (0x52, continuous(v)),
+            Fader4_7(v) => (0x53, continuous(v)),
+            Fader4_8(v) => (0x54, continuous(v)),
 
-                _ => return None,
-            },
+            Up(v) => (0x68, if v { 0x7f } else { 0x00 }),
+            Down(v) => (0x69, if v { 0x7f } else { 0x00 }),
+            Left(v) => (0x6a, if v { 0x7f } else { 0x00 }),
+            Right(v) => (0x6b, if v { 0x7f } else { 0x00 }),
 
-            // button off
-            0x80 | 0x88 => match raw.get(1)? {
-                0x29 => Button1_1(false),
-                0x30 => Button1_2(false),
-                0x31 => Button1_3(false),
-                0x32 => Button1_4(false),
-
-                0x39 => Button1_5(false),
-                0x40 => Button1_6(false),
-                0x41 => Button1_7(false),
-                0x42 => Button1_8(false),
-
-                0x49 => Button2_1(false),
-                0x50 => Button2_2(false),
-                0x51 => Button2_3(false),
-                0x52 => Button2_4(false),
-
-                0x59 => Button2_5(false),
-                0x60 => Button2_6(false),
-                0x61 => Button2_7(false),
-                0x62 => Button2_8(false),
-
-                _ => return None,
-            },
-            _ => return None,
+            // notes
+            Button1_1(v) => return note(channel, 0x29, v),
+            Button1_2(v) => return note(channel, 0x2a, v),
+            Button1_3(v) => return note(channel, 0x2b, v),
+            Button1_4(v) => return note(channel, 0x2c, v),
+            Button1_5(v) => return note(channel, 0x39, v),
+            Button1_6(v) => return note(channel, 0x3a, v),
+            Button1_7(v) => return note(channel, 0x3b, v),
+            Button1_8(v) => return note(channel, 0x3c, v),
+
+            Button2_1(v) => return note(channel, 0x49, v),
+            Button2_2(v) => return note(channel, 0x4a, v),
+            Button2_3(v) => return note(channel, 0x4b, v),
+            Button2_4(v) => return note(channel, 0x4c, v),
+            Button2_5(v) => return note(channel, 0x59, v),
+            Button2_6(v) => return note(channel, 0x5a, v),
+            Button2_7(v) => return note(channel, 0x5b, v),
+            Button2_8(v) => return note(channel, 0x5c, v),
+
+            Device(v) => return note(channel, 0x69, v),
+            Mute(v) => return note(channel, 0x6a, v),
+            Solo(v) => return note(channel, 0x6b, v),
+            RecordArm(v) => return note(channel, 0x6c, v),
+        };
+        [0xb0 | channel, controller, value]
+    }
+
+    /// Split the event into the row and column (0 to 7) of the control that sent it, and the value
+    /// it sent.
+    ///
+    /// Returns `None` for the buttons at the side of the device, which aren't part of a row.
+    pub fn split(&self) -> Option<(Row, u8, Value)> {
+        use Event::*;
+
+        Some(match *self {
+            Fader1_1(v) => (Row::Knobs1, 0, Value::Continuous(v)),
+            Fader1_2(v) => (Row::Knobs1, 1, Value::Continuous(v)),
+            Fader1_3(v) => (Row::Knobs1, 2, Value::Continuous(v)),
+            Fader1_4(v) => (Row::Knobs1, 3, Value::Continuous(v)),
+            Fader1_5(v) => (Row::Knobs1, 4, Value::Continuous(v)),
+            Fader1_6(v) => (Row::Knobs1, 5, Value::Continuous(v)),
+            Fader1_7(v) => (Row::Knobs1, 6, Value::Continuous(v)),
+            Fader1_8(v) => (Row::Knobs1, 7, Value::Continuous(v)),
+            Fader2_1(v) => (Row::Knobs2, 0, Value::Continuous(v)),
+            Fader2_2(v) => (Row::Knobs2, 1, Value::Continuous(v)),
+            Fader2_3(v) => (Row::Knobs2, 2, Value::Continuous(v)),
+            Fader2_4(v) => (Row::Knobs2, 3, Value::Continuous(v)),
+            Fader2_5(v) => (Row::Knobs2, 4, Value::Continuous(v)),
+            Fader2_6(v) => (Row::Knobs2, 5, Value::Continuous(v)),
+            Fader2_7(v) => (Row::Knobs2, 6, Value::Continuous(v)),
+            Fader2_8(v) => (Row::Knobs2, 7, Value::Continuous(v)),
+            Fader3_1(v) => (Row::Knobs3, 0, Value::Continuous(v)),
+            Fader3_2(v) => (Row::Knobs3, 1, Value::Continuous(v)),
+            Fader3_3(v) => (Row::Knobs3, 2, Value::Continuous(v)),
+            Fader3_4(v) => (Row::Knobs3, 3, Value::Continuous(v)),
+            Fader3_5(v) => (Row::Knobs3, 4, Value::Continuous(v)),
+            Fader3_6(v) => (Row::Knobs3, 5, Value::Continuous(v)),
+            Fader3_7(v) => (Row::Knobs3, 6, Value::Continuous(v)),
+            Fader3_8(v) => (Row::Knobs3, 7, Value::Continuous(v)),
+            Fader4_1(v) => (Row::Faders, 0, Value::Continuous(v)),
+            Fader4_2(v) => (Row::Faders, 1, Value::Continuous(v)),
+            Fader4_3(v) => (Row::Faders, 2, Value::Continuous(v)),
+            Fader4_4(v) => (Row::Faders, 3, Value::Continuous(v)),
+            Fader4_5(v) => (Row::Faders, 4, Value::Continuous(v)),
+            Fader4_6(v) => (Row::Faders, 5, Value::Continuous(v)),
+            Fader4_7(v) => (Row::Faders, 6, Value::Continuous(v)),
+            Fader4_8(v) => (Row::Faders, 7, Value::Continuous(v)),
+            Button1_1(v) => (Row::Buttons1, 0, Value::Button(v)),
+            Button1_2(v) => (Row::Buttons1, 1, Value::Button(v)),
+            Button1_3(v) => (Row::Buttons1, 2, Value::Button(v)),
+            Button1_4(v) => (Row::Buttons1, 3, Value::Button(v)),
+            Button1_5(v) => (Row::Buttons1, 4, Value::Button(v)),
+            Button1_6(v) => (Row::Buttons1, 5, Value::Button(v)),
+            Button1_7(v) => (Row::Buttons1, 6, Value::Button(v)),
+            Button1_8(v) => (Row::Buttons1, 7, Value::Button(v)),
+            Button2_1(v) => (Row::Buttons2, 0, Value::Button(v)),
+            Button2_2(v) => (Row::Buttons2, 1, Value::Button(v)),
+            Button2_3(v) => (Row::Buttons2, 2, Value::Button(v)),
+            Button2_4(v) => (Row::Buttons2, 3, Value::Button(v)),
+            Button2_5(v) => (Row::Buttons2, 4, Value::Button(v)),
+            Button2_6(v) => (Row::Buttons2, 5, Value::Button(v)),
+            Button2_7(v) => (Row::Buttons2, 6, Value::Button(v)),
+            Button2_8(v) => (Row::Buttons2, 7, Value::Button(v)),
+            Device(_) | Mute(_) | Solo(_) | RecordArm(_) | Up(_) | Down(_) | Left(_) | Right(_) => {
+                return None
+            }
         })
     }
 }
+
+/// Parse a note message.
+fn button(note: u8, pressed: bool) -> Option<Event> {
+    use Event::*;
+
+    Some(match note {
+        0x29 => Button1_1(pressed),
+        0x2a => Button1_2(pressed),
+        0x2b => Button1_3(pressed),
+        0x2c => Button1_4(pressed),
+        0x39 => Button1_5(pressed),
+        0x3a => Button1_6(pressed),
+        0x3b => Button1_7(pressed),
+        0x3c => Button1_8(pressed),
+
+        0x49 => Button2_1(pressed),
+        0x4a => Button2_2(pressed),
+        0x4b => Button2_3(pressed),
+        0x4c => Button2_4(pressed),
+        0x59 => Button2_5(pressed),
+        0x5a => Button2_6(pressed),
+        0x5b => Button2_7(pressed),
+        0x5c => Button2_8(pressed),
+
+        0x69 => Device(pressed),
+        0x6a => Mute(pressed),
+        0x6b => Solo(pressed),
+        0x6c => RecordArm(pressed),
+
+        _ => return None,
+    })
+}
+
+/// Build a note on (pressed) or note off (released) message.
+fn note(channel: u8, note: u8, pressed: bool) -> [u8; 3] {
+    if pressed {
+        [0x90 | channel, note, 0x7f]
+    } else {
+        [0x80 | channel, note, 0x00]
+    }
+}
+
+/// Convert a value between 0 and 1 to a 7-bit midi value.
+fn continuous(val: f32) -> u8 {
+    let val = val.clamp(0.0, 1.0) * 127.0;
+    // round to nearest (no_std has no `f32::round`)
+    (val + 0.5) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::Event;
+
+    #[test]
+    fn roundtrip() {
+        use Event::*;
+        for event in &[
+            Fader1_1(0.0),
+            Fader2_8(1.0),
+            Fader4_3(64.0 / 127.0),
+            Button1_2(true),
+            Button1_6(false),
+            Button2_8(true),
+            Device(true),
+            RecordArm(false),
+            Up(true),
+            Right(false),
+        ] {
+            for template in 0..16 {
+                let raw = event.encode(template);
+                assert_eq!(Event::parse(&raw), Some((template, *event)));
+            }
+        }
+    }
+
+    #[test]
+    fn note_on_zero_velocity() {
+        assert_eq!(
+            Event::parse(&[0x98, 0x2a, 0x00]),
+            Some((8, Event::Button1_2(false)))
+        );
+    }
+}
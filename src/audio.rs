@@ -1,24 +1,34 @@
 use crate::{
-    cli::{Config, MidiEffect, MidiEffectKind, MidiKey, MidiLookup},
+    cli::Config,
     data::{ChannelMode, Metering},
-    effects::{MonitorSpectrum, SpectralEngine},
+    effects::{Band, Equalizer, MonitorSpectrum, SpectralEngine, EQ_BANDS},
     gui::{Level, UiMsg},
+    metering::{Loudness, LoudnessMeter, StereoMeter, StereoScope, TruePeakMeter},
+    monitor_data::{Monitor, MonitorData},
     Result,
 };
+use anyhow::format_err;
 use crossbeam_channel as channel;
 use dasp::ring_buffer::Bounded;
 use itertools::izip;
 use jack::{
-    AudioIn, AudioOut, Client, Control, Frames, LatencyType, MidiIn, MidiOut, MidiWriter,
-    NotificationHandler, Port, ProcessHandler, ProcessScope,
+    AudioIn, AudioOut, Client, Control, Frames, LatencyType, MidiIn, MidiOut, NotificationHandler,
+    Port, ProcessHandler, ProcessScope,
 };
 use midi_event::{Event, MidiEvent, MidiEventType, Note, Parse};
 use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, convert::TryFrom, sync::Arc};
 
+mod driver;
+mod feedback;
 mod info;
+mod midi;
+mod state_out;
 
+use feedback::Feedback;
 pub use info::Info;
+use midi::MidiControl;
+use state_out::StateOut;
 
 macro_rules! handle_error {
     ($inner:expr, $shutdown:expr, $err_msg:expr) => {
@@ -41,8 +51,6 @@ macro_rules! opt_continue {
     };
 }
 
-const FFI_LEN: usize = 512;
-
 /// This structure holds all the info we need to process the audio/midi signals in the realtime
 /// thread.
 pub struct Audio {
@@ -50,9 +58,10 @@ pub struct Audio {
     ports_in: Vec<Port<AudioIn>>,
     // audio out ports
     ports_out: Vec<Port<AudioOut>>,
-    // midi ports
-    control_in: Port<MidiIn>,
-    control_out: Port<MidiOut>,
+    // midi controllers
+    controllers: Vec<ControllerPorts>,
+    // for recording automation
+    state_out: Option<StateOut>,
 
     // Because working in the frequency domain necessitates windowing and therefore latency, we use
     // single-threaded ringbuffers to store incoming/outgoing audio data between frames, as
@@ -60,33 +69,65 @@ pub struct Audio {
     in_bufs: Vec<Bounded<Vec<f32>>>,
     out_bufs: Vec<Bounded<Vec<f32>>>,
     specs: Vec<SpectralEngine>,
+    eqs: Vec<Equalizer>,
 
     // Channels for communicating with UI.
     ui_in: channel::Receiver<AudioMsg>,
     ui_out: channel::Sender<UiMsg>,
+    // Channels for communicating with the remote servers.
+    remote_in: channel::Receiver<AudioMsg>,
+    remote_out: channel::Sender<UiMsg>,
     // application state
     state: State,
+    // the state the ui shows, or `None` if we haven't told it yet. Changes made in the ui are
+    // already shown, so we don't send them back to it (while a control is being dragged, they
+    // would be out of date by the time they got there).
+    shown: Vec<Option<ChannelState>>,
+    // the state we last told the remote servers about, or `None` if we haven't yet.
+    announced: Vec<Option<ChannelState>>,
 
     frame_len: usize,
     // We want to accumulate metering info so we only send it once every 1/60 second.
     frames_in_meter_frame: usize,
     frames_acc: usize,
     meter_accs: Vec<MeterAcc>,
+    // the loudness of each channel's output, since the meters were last reset.
+    loudness: Vec<LoudnessMeter>,
+    stereo: Vec<StereoPair>,
+
+    // whether we need to reset the controllers
+    first_iter: bool,
+}
 
-    //first_iter: bool,
-    //novation_out: NovationOut,
-    midi_lookup: MidiLookup,
+/// A stereo pair of channels, measured after the gain, and where we publish the readings.
+struct StereoPair {
+    left: usize,
+    right: usize,
+    meter: StereoMeter,
+    scope: MonitorData<StereoScope>,
+}
+
+/// The ports for a midi controller, and how to talk to it.
+struct ControllerPorts {
+    input: Port<MidiIn>,
+    output: Port<MidiOut>,
+    control: MidiControl,
+    feedback: Feedback,
 }
 
 impl Audio {
     /// Our constructor. Here we setup the ports we want and store them in our jack state object.
+    ///
+    /// `tx` and `rx` talk to the ui, and `tx_remote` and `rx_remote` to the remote servers. Every
+    /// change to the mixer is sent to the remote servers, and every change not made in the ui is
+    /// sent to the ui.
     pub fn setup(
         config: &Config,
         client: &Client,
         tx: channel::Sender<UiMsg>,
         rx: channel::Receiver<AudioMsg>,
-        low_mid_freq: f32,
-        mid_high_freq: f32,
+        tx_remote: channel::Sender<UiMsg>,
+        rx_remote: channel::Receiver<AudioMsg>,
     ) -> Result<Audio> {
         let sample_rate = client.sample_rate() as f32;
         let frame_len = usize::try_from(client.buffer_size()).unwrap();
@@ -105,11 +146,59 @@ impl Audio {
             let out_buf = Bounded::from(vec![0.0f32; (frame_len * 2).max(1024)]);
             in_bufs.push(in_buf);
             out_bufs.push(out_buf);
-            specs.push(SpectralEngine::new(sample_rate, FFI_LEN, tx.clone()));
+            let spec = SpectralEngine::new(sample_rate, config.fft_size);
+            // the engine produces output a step at a time, and we need a whole buffer every cycle.
+            if spec.step_size() > frame_len {
+                return Err(format_err!(
+                    "`fft_size` {} is too big for a jack buffer size of {} (the most is {})",
+                    config.fft_size,
+                    frame_len,
+                    config.fft_size / spec.step_size() * frame_len
+                ));
+            }
+            specs.push(spec);
+        }
+        // every channel has the same filter, so the ui only needs it once.
+        if let Some(spec) = specs.first() {
+            tx.send(UiMsg::LowPassSpectrum(spec.low_pass_spectrum()))?;
         }
 
-        let control_in = client.register_port("control_in", MidiIn)?;
-        let control_out = client.register_port("control_out", MidiOut)?;
+        let mut controllers = Vec::with_capacity(config.controllers.len());
+        for controller in config.controllers.iter() {
+            controllers.push(ControllerPorts {
+                input: client.register_port(&format!("{}_in", controller.name), MidiIn)?,
+                output: client.register_port(&format!("{}_out", controller.name), MidiOut)?,
+                control: MidiControl::new(config, controller),
+                feedback: Feedback::new(controller, config.channels.len()),
+            });
+        }
+
+        let state = State::new(config);
+        let eqs = state
+            .channels
+            .iter()
+            .map(|channel| Equalizer::new(sample_rate as f64, &channel.eq))
+            .collect();
+
+        let state_out = match config.state_out.as_ref() {
+            Some(state_out) => Some(StateOut::new(
+                client.register_port("state_out", MidiOut)?,
+                config,
+                state_out,
+            )),
+            None => None,
+        };
+
+        let stereo = config
+            .stereo_pairs()
+            .into_iter()
+            .map(|(left, right)| StereoPair {
+                left,
+                right,
+                meter: StereoMeter::new(sample_rate as f64),
+                scope: MonitorData::new(StereoScope::default()),
+            })
+            .collect();
 
         // frames in a second / 60
         let frames_in_meter_frame =
@@ -118,21 +207,26 @@ impl Audio {
         Ok(Audio {
             ports_in,
             ports_out,
-            control_in,
-            control_out,
+            controllers,
+            state_out,
             in_bufs,
             out_bufs,
             specs,
+            eqs,
             ui_out: tx,
             ui_in: rx,
-            state: State::new(config),
+            remote_in: rx_remote,
+            remote_out: tx_remote,
+            state,
+            shown: vec![None; config.channels.len()],
+            announced: vec![None; config.channels.len()],
             frame_len,
             frames_in_meter_frame,
             frames_acc: 0,
             meter_accs: vec![MeterAcc::new(); config.channels.len()],
-            //first_iter: true,
-            //novation_out: NovationOut::new(),
-            midi_lookup: config.midi_lookup(),
+            loudness: vec![LoudnessMeter::new(sample_rate as f64); config.channels.len()],
+            stereo,
+            first_iter: true,
         })
     }
 }
@@ -141,6 +235,22 @@ impl Audio {
     pub fn monitor_spectra(&self) -> Vec<(MonitorSpectrum, MonitorSpectrum)> {
         self.specs.iter().map(|s| s.monitor_spectra()).collect()
     }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Get handles on the readings of each stereo pair, in the order of `Config::stereo`.
+    pub fn monitor_stereo(&self) -> Vec<Monitor<StereoScope>> {
+        self.stereo
+            .iter()
+            .map(|pair| pair.scope.monitor())
+            .collect()
+    }
 }
 
 impl ProcessHandler for Audio {
@@ -149,70 +259,89 @@ impl ProcessHandler for Audio {
 
         let mut shutdown = false;
 
-        // reset the controller on the first cycle
-        let mut control_out = self.control_out.writer(ps);
-        /*
-        if self.first_iter {
-            handle_error!(
-                self.novation_out.reset(&mut control_out),
-                shutdown,
-                "error resetting LCXL state"
-            );
-            self.first_iter = false;
+        // process midi events, from each controller in turn
+        for controller in self.controllers.iter_mut() {
+            for raw_midi in controller.input.iter(ps) {
+                let msg = opt_continue!(controller.control.handle(raw_midi.bytes, &self.state));
+                self.state.update(msg);
+            }
         }
-        */
-
-        // process midi events
-        for raw_midi in self.control_in.iter(ps) {
-            if let Some(evt) = MidiEvent::parse(raw_midi.bytes) {
-                #[inline]
-                fn get_value(evt: &MidiEvent) -> u8 {
-                    use midi_event::MidiEventType::{Controller, NoteOn};
-                    match evt.event {
-                        Controller(_, gain) => gain,
-                        NoteOn(_, gain) => gain,
-                        _ => unreachable!(),
-                    }
-                }
-                let key = opt_continue!(MidiKey::from_opt(evt));
-                let effect = opt_continue!(self.midi_lookup.get(&key));
-                match effect.kind {
-                    MidiEffectKind::Gain => {
-                        let gain = (get_value(&evt) as f64) / 127.0;
-                        self.state.channels[effect.channel].gain = gain;
+
+        // process events from the ui and the remote servers
+        for (rx, from_ui) in &[(&self.ui_in, true), (&self.remote_in, false)] {
+            loop {
+                match rx.try_recv() {
+                    Ok(AudioMsg {
+                        channel,
+                        kind: AudioMsgKind::ResetMeters,
+                    }) => {
+                        self.loudness[channel].reset();
+                        self.meter_accs[channel].reset();
                         handle_error!(
-                            self.ui_out.send(UiMsg::Levels {
-                                channel: effect.channel,
-                                level: Level::Gain(gain)
-                            }),
+                            self.ui_out.send(UiMsg::MetersReset { channel }),
                             shutdown,
                             "error communicating with ui"
                         );
+                        handle_error!(
+                            self.remote_out.send(UiMsg::MetersReset { channel }),
+                            shutdown,
+                            "error communicating with remote servers"
+                        );
+                    }
+                    Ok(msg) => {
+                        self.state.update(msg);
+                        if *from_ui {
+                            if let Some(shown) = &mut self.shown[msg.channel] {
+                                shown.update(msg.kind);
+                            }
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        shutdown = true;
+                        break;
                     }
                 }
             }
         }
 
-        // process events from ui
-        loop {
-            match self.ui_in.try_recv() {
-                Ok(msg) => {
-                    /*
-                    handle_error!(
-                        self.novation_out
-                            .handle_msg(&self.state, msg, &mut control_out),
-                        shutdown,
-                        "Error updating LCXL state"
-                    );
-                    */
-                    self.state.update(msg);
-                }
-                Err(TryRecvError::Empty) => break,
-                Err(TryRecvError::Disconnected) => {
-                    shutdown = true;
-                    break;
-                }
-            }
+        // tell the ui about every change it didn't make, and the remote servers about every change.
+        for (idx, (channel, shown, announced)) in
+            izip!(&self.state.channels, &mut self.shown, &mut self.announced).enumerate()
+        {
+            handle_error!(
+                announce(idx, channel, shown, &self.ui_out),
+                shutdown,
+                "error communicating with ui"
+            );
+            handle_error!(
+                announce(idx, channel, announced, &self.remote_out),
+                shutdown,
+                "error communicating with remote servers"
+            );
+        }
+
+        // show the new state on every controller (including changes made on the other ones). We
+        // reset the controllers on the first cycle.
+        for controller in self.controllers.iter_mut() {
+            let mut out = controller.output.writer(ps);
+            let result = if self.first_iter {
+                controller.feedback.reset(&self.state, &mut out)
+            } else {
+                controller.feedback.show(&self.state, &mut out)
+            };
+            handle_error!(result, shutdown, "error updating controller state");
+        }
+        self.first_iter = false;
+
+        // publish every change, whether it came from a controller or the ui.
+        if let Some(state_out) = self.state_out.as_mut() {
+            let mut out = state_out.port.writer(ps);
+            handle_error!(
+                state_out.send(&self.state, &mut out),
+                shutdown,
+                "error publishing mixer state"
+            );
         }
 
         // process audio
@@ -226,8 +355,10 @@ impl ProcessHandler for Audio {
                 mut in_buf,
                 mut out_buf,
                 mut engine,
+                mut eq,
                 mut chan_info,
                 mut meter_acc,
+                mut loudness,
             ),
         ) in izip!(
             &self.ports_in,
@@ -235,8 +366,10 @@ impl ProcessHandler for Audio {
             &mut self.in_bufs,
             &mut self.out_bufs,
             &mut self.specs,
+            &mut self.eqs,
             &self.state.channels,
-            &mut self.meter_accs
+            &mut self.meter_accs,
+            &mut self.loudness
         )
         .enumerate()
         {
@@ -253,6 +386,7 @@ impl ProcessHandler for Audio {
                     for v in chan_out.as_mut_slice(ps) {
                         *v = 0.;
                     }
+                    loudness.process(chan_out.as_mut_slice(ps));
                     continue;
                 }
                 ChannelMode::Bypass => {
@@ -264,6 +398,7 @@ impl ProcessHandler for Audio {
                     for in_s in chan_in.as_slice(ps).iter() {
                         meter_acc.sample_out(*in_s);
                     }
+                    loudness.process(chan_in.as_slice(ps));
                     continue;
                 }
                 // fall thru
@@ -272,7 +407,7 @@ impl ProcessHandler for Audio {
             // copy input to ring buffer
             in_buf.extend(chan_in.as_slice(ps));
 
-            engine.process(in_buf, out_buf, idx == 0);
+            engine.process(in_buf, out_buf);
 
             let data_out = chan_out.as_mut_slice(ps);
             let mut idx = 0;
@@ -284,28 +419,43 @@ impl ProcessHandler for Audio {
                     break;
                 }
             }
+            eq.set_bands(&chan_info.eq);
+            eq.process(data_out);
 
             // meter input
             for out_s in chan_out.as_mut_slice(ps).iter() {
                 meter_acc.sample_out(*out_s);
             }
+            loudness.process(chan_out.as_mut_slice(ps));
+        }
+
+        for pair in self.stereo.iter_mut() {
+            let (left, right) = two_mut(&mut self.ports_out, pair.left, pair.right);
+            pair.meter
+                .process(left.as_mut_slice(ps), right.as_mut_slice(ps));
         }
 
         // process info for UI (metering)
         self.frames_acc += 1;
         if self.frames_acc >= self.frames_in_meter_frame {
             // Report metering
-            for (idx, mut meter_acc) in self.meter_accs.iter_mut().enumerate() {
+            for (idx, (meter_acc, loudness)) in
+                izip!(&mut self.meter_accs, &self.loudness).enumerate()
+            {
                 handle_error!(
                     self.ui_out.send(UiMsg::Metering {
                         channel: idx,
-                        metering: meter_acc.as_metering(self.frames_acc * self.frame_len),
+                        metering: meter_acc
+                            .as_metering(self.frames_acc * self.frame_len, loudness.loudness()),
                     }),
                     shutdown,
                     "error communicating with ui"
                 );
                 meter_acc.clear()
             }
+            for StereoPair { meter, scope, .. } in self.stereo.iter_mut() {
+                scope.update(|scope| meter.write_scope(scope));
+            }
             self.frames_acc = 0;
         }
 
@@ -350,154 +500,6 @@ impl NotificationHandler for Audio {
     }
 }
 
-// utils
-
-/*
-pub struct NovationOut {
-    buf: [u8; 11],
-}
-
-impl NovationOut {
-    fn new() -> Self {
-        NovationOut {
-            buf: [
-                0xf0, 0x00, 0x20, 0x29, 0x02, 0x11, 0x78, 0x00, 0x00, 0x00, 0xf7,
-            ],
-        }
-    }
-
-    fn handle_msg(
-        &mut self,
-        _state: &State,
-        msg: StateChange,
-        out: &mut MidiWriter<'_>,
-    ) -> Result<(), jack::Error> {
-        self.set_template(0x08);
-        match msg {
-            StateChange::filter_passthru_1(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x00, out)?;
-            }
-            StateChange::filter_passthru_2(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x01, out)?;
-            }
-            StateChange::filter_passthru_3(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x02, out)?;
-            }
-            StateChange::filter_passthru_4(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x03, out)?;
-            }
-            StateChange::filter_passthru_5(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x04, out)?;
-            }
-            StateChange::filter_passthru_6(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x05, out)?;
-            }
-            StateChange::filter_passthru_7(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x06, out)?;
-            }
-            StateChange::filter_passthru_8(v) => {
-                if v {
-                    self.set_off_led();
-                } else {
-                    self.set_red_led();
-                };
-                self.write_strip(0x07, out)?;
-            }
-            _ => (),
-        }
-        Ok(())
-    }
-
-    #[inline(always)]
-    fn set_green_led(&mut self) {
-        self.buf[9] = 0b0011_1100
-    }
-
-    #[inline(always)]
-    fn set_red_led(&mut self) {
-        self.buf[9] = 0b0000_1111
-    }
-
-    #[inline(always)]
-    fn set_off_led(&mut self) {
-        self.buf[9] = 0b0000_1100
-    }
-
-    #[inline(always)]
-    fn set_template(&mut self, template: u8) {
-        self.buf[7] = template;
-    }
-
-    #[inline(always)]
-    fn set_index(&mut self, index: u8) {
-        self.buf[8] = index;
-    }
-
-    #[inline]
-    fn write_strip(&mut self, strip: u8, writer: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
-        self.set_index(strip);
-        self.write_current(writer)?;
-        self.set_index(strip + 0x8);
-        self.write_current(writer)?;
-        self.set_index(strip + 0x10);
-        self.write_current(writer)?;
-        self.set_index(strip + 0x18);
-        self.write_current(writer)?;
-        Ok(())
-    }
-
-    fn reset(&mut self, writer: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
-        writer.write(&jack::RawMidi {
-            time: 0,
-            bytes: &[0xb8, 0x00, 0x00],
-        })
-    }
-
-    #[inline(always)]
-    fn write_current(&self, writer: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
-        writer.write(&jack::RawMidi {
-            time: 0,
-            bytes: &self.buf,
-        })
-    }
-}
-*/
-
 // State
 
 #[derive(Debug, Clone, PartialEq)]
@@ -509,6 +511,8 @@ pub struct State {
 pub struct ChannelState {
     pub gain: f64,
     pub mode: ChannelMode,
+    /// A fixed number of bands, so cloning the state doesn't allocate.
+    pub eq: [Band; EQ_BANDS],
 }
 
 impl State {
@@ -518,20 +522,74 @@ impl State {
             channels.push(ChannelState {
                 gain: 0.0,
                 mode: ChannelMode::default(),
+                eq: Band::DEFAULTS,
             });
         }
         State { channels }
     }
 
     pub fn update(&mut self, msg: AudioMsg) {
-        let channel = &mut self.channels[msg.channel];
-        match msg.kind {
-            AudioMsgKind::Gain(gain) => channel.gain = gain,
-            AudioMsgKind::Mode(mode) => channel.mode = mode,
+        self.channels[msg.channel].update(msg.kind);
+    }
+}
+
+impl ChannelState {
+    fn update(&mut self, kind: AudioMsgKind) {
+        match kind {
+            AudioMsgKind::Gain(gain) => self.gain = gain,
+            AudioMsgKind::Mode(mode) => self.mode = mode,
+            AudioMsgKind::Eq(band, settings) => {
+                if let Some(old) = self.eq.get_mut(band) {
+                    *old = settings.clamped(band);
+                }
+            }
+            // the meters aren't part of the state.
+            AudioMsgKind::ResetMeters => (),
         }
     }
 }
 
+/// Send `tx` a message for every part of the channel at `idx` that has changed since `known`
+/// (everything if it is `None`), then remember the channel as known.
+fn announce(
+    idx: usize,
+    channel: &ChannelState,
+    known: &mut Option<ChannelState>,
+    tx: &channel::Sender<UiMsg>,
+) -> Result<(), channel::SendError<UiMsg>> {
+    let (gain_changed, mode_changed) = match known {
+        Some(known) => (known.gain != channel.gain, known.mode != channel.mode),
+        None => (true, true),
+    };
+    for (band, settings) in channel.eq.iter().enumerate() {
+        let changed = match known {
+            Some(known) => known.eq[band] != *settings,
+            None => true,
+        };
+        if changed {
+            tx.send(UiMsg::Eq {
+                channel: idx,
+                band,
+                settings: *settings,
+            })?;
+        }
+    }
+    if gain_changed {
+        tx.send(UiMsg::Levels {
+            channel: idx,
+            level: Level::Gain(channel.gain),
+        })?;
+    }
+    if mode_changed {
+        tx.send(UiMsg::Mode {
+            channel: idx,
+            mode: channel.mode,
+        })?;
+    }
+    *known = Some(channel.clone());
+    Ok(())
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct AudioMsg {
     pub channel: usize,
@@ -543,15 +601,33 @@ pub struct AudioMsg {
 pub enum AudioMsgKind {
     Gain(f64),
     Mode(ChannelMode),
+    /// Change a band of the channel's EQ, by its index.
+    Eq(usize, Band),
+    /// Start measuring loudness again, and forget the overs and highest true peaks.
+    ResetMeters,
+}
+
+/// Borrow two different items of `items` at once.
+fn two_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    if a < b {
+        let (start, end) = items.split_at_mut(b);
+        (&mut start[a], &mut end[0])
+    } else {
+        let (start, end) = items.split_at_mut(a);
+        (&mut end[0], &mut start[b])
+    }
 }
 
 /// A struct that accumulates metering info during a frame.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Clone)]
 pub struct MeterAcc {
     sum_squares_in: f32,
     max_in: f32,
     sum_squares_out: f32,
     max_out: f32,
+    // these also count overs until they are reset.
+    true_peak_in: TruePeakMeter,
+    true_peak_out: TruePeakMeter,
 }
 
 impl MeterAcc {
@@ -561,6 +637,8 @@ impl MeterAcc {
             max_in: 0.0,
             sum_squares_out: 0.0,
             max_out: 0.0,
+            true_peak_in: TruePeakMeter::new(),
+            true_peak_out: TruePeakMeter::new(),
         }
     }
 
@@ -569,6 +647,7 @@ impl MeterAcc {
             self.max_in = val.abs();
         }
         self.sum_squares_in += val * val;
+        self.true_peak_in.sample(val);
     }
 
     fn sample_out(&mut self, val: f32) {
@@ -576,14 +655,18 @@ impl MeterAcc {
             self.max_out = val.abs();
         }
         self.sum_squares_out += val * val;
+        self.true_peak_out.sample(val);
     }
 
-    fn as_metering(&self, count: usize) -> Metering {
+    fn as_metering(&self, count: usize, loudness: Loudness) -> Metering {
         Metering {
             max_in: self.max_in as f64,
             rms_in: (self.sum_squares_in as f64 / count as f64).sqrt(),
             max_out: self.max_out as f64,
             rms_out: (self.sum_squares_out as f64 / count as f64).sqrt(),
+            true_peak_in: self.true_peak_in.true_peak(),
+            true_peak_out: self.true_peak_out.true_peak(),
+            loudness,
         }
     }
 
@@ -592,5 +675,13 @@ impl MeterAcc {
         self.sum_squares_in = 0.0;
         self.max_out = 0.0;
         self.sum_squares_out = 0.0;
+        self.true_peak_in.clear_peak();
+        self.true_peak_out.clear_peak();
+    }
+
+    /// Forget the overs and highest true peaks.
+    fn reset(&mut self) {
+        self.true_peak_in.reset();
+        self.true_peak_out.reset();
     }
 }
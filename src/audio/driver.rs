@@ -0,0 +1,111 @@
+//! Built-in layouts for devices we know about, so they work without any mappings in the config.
+use super::{midi::mode_change, AudioMsg, AudioMsgKind, State};
+use crate::{
+    cli::{Button, DriverProfile},
+    data::ChannelMode,
+    effects::EqParam,
+};
+use novation_launch_control::{Event, Row, Value};
+
+pub enum Driver {
+    None,
+    LaunchControlXl,
+}
+
+impl Driver {
+    pub fn new(profile: DriverProfile) -> Self {
+        match profile {
+            DriverProfile::None => Driver::None,
+            DriverProfile::LaunchControlXl => Driver::LaunchControlXl,
+        }
+    }
+
+    /// Turn raw midi from the device into a change to the mixer, if the control does anything in
+    /// our layout.
+    pub fn translate(&self, raw: &[u8], state: &State) -> Option<AudioMsg> {
+        match self {
+            Driver::None => None,
+            Driver::LaunchControlXl => launch_control_xl(raw, state),
+        }
+    }
+}
+
+/// The default LaunchControl XL layout. Each of the 8 strips controls the channel with the same
+/// index:
+///
+///  - the top, middle and bottom knobs set the gain of EQ bands 3, 1 and 0 (the high shelf, the
+///    lower peak and the low shelf),
+///  - the fader sets the gain,
+///  - the top button toggles bypass,
+///  - the bottom button toggles mute.
+fn launch_control_xl(raw: &[u8], state: &State) -> Option<AudioMsg> {
+    let (template, event) = Event::parse(raw)?;
+    // only user template 1 and factory template 1
+    if template % 8 != 0 {
+        return None;
+    }
+    let (row, column, value) = event.split()?;
+    let channel = column as usize;
+    let current = state.channels.get(channel)?;
+    let eq_gain = |band: usize, position: f32| {
+        let current = *current.eq.get(band)?;
+        Some(AudioMsgKind::Eq(
+            band,
+            EqParam::Gain.with_position(current, position as f64),
+        ))
+    };
+    let kind = match (row, value) {
+        (Row::Knobs1, Value::Continuous(position)) => eq_gain(3, position)?,
+        (Row::Knobs2, Value::Continuous(position)) => eq_gain(1, position)?,
+        (Row::Knobs3, Value::Continuous(position)) => eq_gain(0, position)?,
+        (Row::Faders, Value::Continuous(gain)) => AudioMsgKind::Gain(gain as f64),
+        (Row::Buttons1, Value::Button(pressed)) => {
+            mode_change(current.mode, ChannelMode::Bypass, Button::Toggle, pressed)?
+        }
+        (Row::Buttons2, Value::Button(pressed)) => {
+            mode_change(current.mode, ChannelMode::Mute, Button::Toggle, pressed)?
+        }
+        _ => return None,
+    };
+    Some(AudioMsg { channel, kind })
+}
+
+#[cfg(test)]
+mod test {
+    use super::Driver;
+    use crate::{
+        audio::{AudioMsgKind, State},
+        cli::{Config, FeedbackProfile},
+        data::ChannelMode,
+    };
+    use novation_launch_control::Event;
+
+    #[test]
+    fn without_config() {
+        let config = Config::default();
+        let controller = &config.controllers[0];
+        assert!(matches!(
+            controller.feedback,
+            Some(FeedbackProfile::LaunchControlXl { .. })
+        ));
+        let driver = Driver::new(controller.driver);
+        let state = State::new(&config);
+
+        let msg = driver
+            .translate(&Event::Fader4_2(1.0).encode(0), &state)
+            .unwrap();
+        assert_eq!(msg.channel, 1);
+        assert!(matches!(msg.kind, AudioMsgKind::Gain(gain) if (gain - 1.0).abs() < 1e-6));
+        let msg = driver
+            .translate(&Event::Button1_1(true).encode(0), &state)
+            .unwrap();
+        assert_eq!(msg.channel, 0);
+        assert!(matches!(msg.kind, AudioMsgKind::Mode(ChannelMode::Bypass)));
+        // channels start muted, so the mute button unmutes. Factory template 1 works too.
+        let msg = driver
+            .translate(&Event::Button2_2(true).encode(8), &state)
+            .unwrap();
+        assert_eq!(msg.channel, 1);
+        assert!(matches!(msg.kind, AudioMsgKind::Mode(ChannelMode::Normal)));
+    }
+}
@@ -0,0 +1,205 @@
+//! Showing mixer state on a controller, by sending midi to its output port.
+use super::{ChannelState, State};
+use crate::{
+    cli::{Controller, FeedbackProfile},
+    data::ChannelMode,
+    profile::{Profile, Strip},
+};
+use jack::{MidiWriter, RawMidi};
+use novation_launch_control::led::{self, Color, Led, Mode};
+
+/// Sends LED (and in future motor fader) updates to a controller.
+///
+/// Everything here runs in the RT thread, so nothing may allocate.
+pub enum Feedback {
+    /// There is no device to send feedback to.
+    None,
+    LaunchControlXl(NovationOut),
+    /// Feedback described by a controller profile.
+    Profile(ProfileOut),
+}
+
+impl Feedback {
+    /// Use the feedback profile from the config if there is one, otherwise the controller profile.
+    pub fn new(controller: &Controller, channels: usize) -> Self {
+        match (controller.feedback, controller.device.as_ref()) {
+            (Some(FeedbackProfile::LaunchControlXl { template }), _) => {
+                Feedback::LaunchControlXl(NovationOut::new(template))
+            }
+            (None, Some(device)) if device.has_leds() => {
+                Feedback::Profile(ProfileOut::new(device, channels))
+            }
+            (None, _) => Feedback::None,
+        }
+    }
+
+    /// Put the device in a known state, then show the whole of `state` on it.
+    pub fn reset(&mut self, state: &State, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+        match self {
+            Feedback::None => Ok(()),
+            Feedback::LaunchControlXl(novation_out) => novation_out.reset(out),
+            Feedback::Profile(profile_out) => profile_out.reset(out),
+        }?;
+        self.show(state, out)
+    }
+
+    /// Update the device to show `state`. Only the parts that have changed since the last update
+    /// are sent.
+    pub fn show(&mut self, state: &State, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+        for (idx, channel) in state.channels.iter().enumerate() {
+            self.channel_changed(idx, channel, out)?;
+        }
+        Ok(())
+    }
+
+    fn channel_changed(
+        &mut self,
+        idx: usize,
+        channel: &ChannelState,
+        out: &mut MidiWriter<'_>,
+    ) -> Result<(), jack::Error> {
+        match self {
+            Feedback::None => Ok(()),
+            Feedback::LaunchControlXl(novation_out) => {
+                novation_out.channel_changed(idx, channel, out)
+            }
+            Feedback::Profile(profile_out) => profile_out.channel_changed(idx, channel, out),
+        }
+    }
+}
+
+/// LED feedback for the Novation LaunchControl XL.
+///
+/// Each mixer channel maps to a strip on the device. The 3 knobs of the strip show the channel
+/// mode: green for normal, red for mute, and off for bypass. The buttons match what they do in the
+/// built-in layout: the top (focus) button is amber while the channel is bypassed, and the bottom
+/// (control) button is red while it is muted.
+pub struct NovationOut {
+    template: u8,
+    /// The mode currently shown on each strip, so we only send changes.
+    shown: [Option<ChannelMode>; NovationOut::STRIPS],
+}
+
+impl NovationOut {
+    /// The number of strips on the device.
+    const STRIPS: usize = 8;
+
+    fn new(template: u8) -> Self {
+        NovationOut {
+            template,
+            shown: [None; Self::STRIPS],
+        }
+    }
+
+    fn channel_changed(
+        &mut self,
+        idx: usize,
+        channel: &ChannelState,
+        out: &mut MidiWriter<'_>,
+    ) -> Result<(), jack::Error> {
+        if idx >= Self::STRIPS || self.shown[idx] == Some(channel.mode) {
+            return Ok(());
+        }
+        self.shown[idx] = Some(channel.mode);
+        let knobs = match channel.mode {
+            ChannelMode::Normal => Color::GREEN,
+            ChannelMode::Mute => Color::RED,
+            ChannelMode::Bypass => Color::OFF,
+        };
+        // the buttons light up when the mode they toggle (see `driver`) is on.
+        let lit = |mode, color| {
+            if channel.mode == mode {
+                color
+            } else {
+                Color::OFF
+            }
+        };
+        let strip = idx as u8;
+        for (led, color) in &[
+            (Led::Knob(0, strip), knobs),
+            (Led::Knob(1, strip), knobs),
+            (Led::Knob(2, strip), knobs),
+            (Led::Focus(strip), lit(ChannelMode::Bypass, Color::AMBER)),
+            (Led::Control(strip), lit(ChannelMode::Mute, Color::RED)),
+        ] {
+            out.write(&RawMidi {
+                time: 0,
+                bytes: &led::set(self.template, *led, *color, Mode::Normal),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Turn off all LEDs on our template.
+    fn reset(&mut self, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+        self.shown = [None; Self::STRIPS];
+        out.write(&RawMidi {
+            time: 0,
+            bytes: &led::reset(self.template),
+        })
+    }
+}
+
+/// LED feedback using the messages in a controller profile.
+///
+/// Each strip lights its mute LED when the channel is muted, and its bypass LED when the channel
+/// is bypassed.
+pub struct ProfileOut {
+    init: Vec<Vec<u8>>,
+    strips: Vec<Strip>,
+    /// The mode currently shown on each strip, so we only send changes.
+    shown: Vec<Option<ChannelMode>>,
+}
+
+impl ProfileOut {
+    fn new(profile: &Profile, channels: usize) -> Self {
+        let strips: Vec<_> = profile.strips.iter().take(channels).cloned().collect();
+        ProfileOut {
+            init: profile.init.clone(),
+            shown: vec![None; strips.len()],
+            strips,
+        }
+    }
+
+    fn channel_changed(
+        &mut self,
+        idx: usize,
+        channel: &ChannelState,
+        out: &mut MidiWriter<'_>,
+    ) -> Result<(), jack::Error> {
+        let strip = match self.strips.get(idx) {
+            Some(strip) => strip,
+            None => return Ok(()),
+        };
+        if self.shown[idx] == Some(channel.mode) {
+            return Ok(());
+        }
+        self.shown[idx] = Some(channel.mode);
+        for (led, mode) in &[
+            (strip.mute_led.as_ref(), ChannelMode::Mute),
+            (strip.bypass_led.as_ref(), ChannelMode::Bypass),
+        ] {
+            if let Some(led) = led {
+                out.write(&RawMidi {
+                    time: 0,
+                    bytes: led.message(channel.mode == *mode),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the init messages from the profile.
+    fn reset(&mut self, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+        for shown in self.shown.iter_mut() {
+            *shown = None;
+        }
+        for msg in self.init.iter() {
+            out.write(&RawMidi {
+                time: 0,
+                bytes: msg,
+            })?;
+        }
+        Ok(())
+    }
+}
@@ -16,6 +16,10 @@ impl Info {
         }
     }
 
+    pub fn sample_rate(&self) -> usize {
+        self.sample_rate
+    }
+
     pub fn log(&self) {
         log::info!("\"{}\" jack audio client", self.name);
         log::info!("  sample rate: {}", self.sample_rate);
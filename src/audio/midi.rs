@@ -0,0 +1,308 @@
+//! Turning midi events into changes to the mixer, including controls that span several messages
+//! (14-bit controllers and NRPNs).
+use super::{driver::Driver, AudioMsg, AudioMsgKind, State};
+use crate::{
+    cli::{
+        Button, Config, Controller, Encoding, MidiEffect, MidiEffectKind, MidiKey, MidiKeyKind,
+        MidiLookup,
+    },
+    data::ChannelMode,
+};
+use fnv::FnvHashMap as HashMap;
+use midi_event::{MidiEvent, MidiEventType, Parse};
+
+// controller numbers with special meaning
+pub(super) const DATA_ENTRY_MSB: u8 = 0x06;
+pub(super) const DATA_ENTRY_LSB: u8 = 0x26;
+pub(super) const NRPN_LSB: u8 = 0x62;
+pub(super) const NRPN_MSB: u8 = 0x63;
+const RPN_LSB: u8 = 0x64;
+const RPN_MSB: u8 = 0x65;
+
+/// Everything we need to turn midi from a controller into changes to the mixer.
+pub struct MidiControl {
+    lookup: MidiLookup,
+    decoder: MidiDecoder,
+    // The last position we saw for each mapped absolute control, for soft takeover.
+    positions: HashMap<MidiKey, Option<f64>>,
+    driver: Driver,
+}
+
+impl MidiControl {
+    pub fn new(config: &Config, controller: &Controller) -> Self {
+        let lookup = config.midi_lookup(controller);
+        let decoder = MidiDecoder::new(&lookup);
+        // pre-fill so we never allocate in the RT thread.
+        let positions = lookup.keys().map(|key| (*key, None)).collect();
+        MidiControl {
+            lookup,
+            decoder,
+            positions,
+            driver: Driver::new(controller.driver),
+        }
+    }
+
+    /// Work out how the mixer should change in response to a midi message, if at all.
+    ///
+    /// Mappings in the config take priority over the layout of the device driver.
+    pub fn handle(&mut self, raw: &[u8], state: &State) -> Option<AudioMsg> {
+        let decoder = &mut self.decoder;
+        let lookup = &self.lookup;
+        let mapped = MidiEvent::parse(raw)
+            .and_then(|evt| decoder.decode(evt))
+            .and_then(|(key, value)| Some((*lookup.get(&key)?, key, value)));
+        match mapped {
+            Some((effect, key, value)) => self.mapped(effect, key, value, state),
+            None => self.driver.translate(raw, state),
+        }
+    }
+
+    fn mapped(
+        &mut self,
+        effect: MidiEffect,
+        key: MidiKey,
+        value: u16,
+        state: &State,
+    ) -> Option<AudioMsg> {
+        let channel = state.channels.get(effect.channel)?;
+        let kind = match effect.kind {
+            MidiEffectKind::Gain => {
+                AudioMsgKind::Gain(self.position(effect, key, value, channel.gain)?)
+            }
+            MidiEffectKind::Eq(band, param) => {
+                let current = *channel.eq.get(band)?;
+                let position = self.position(effect, key, value, param.position(&current))?;
+                AudioMsgKind::Eq(band, param.with_position(current, position))
+            }
+            MidiEffectKind::Mode(mode) => {
+                mode_change(channel.mode, mode, effect.button, value > 0)?
+            }
+        };
+        Some(AudioMsg {
+            channel: effect.channel,
+            kind,
+        })
+    }
+
+    /// Where a continuous control moves a parameter at `current` (between 0 and 1) to, if it moves
+    /// it at all.
+    fn position(
+        &mut self,
+        effect: MidiEffect,
+        key: MidiKey,
+        value: u16,
+        current: f64,
+    ) -> Option<f64> {
+        match effect.encoding {
+            Encoding::Absolute => {
+                let next = key.kind.normalize(value);
+                let prev = self
+                    .positions
+                    .get_mut(&key)
+                    .and_then(|pos| pos.replace(next));
+                effect.takeover.apply(current, prev, next)
+            }
+            Encoding::Relative(relative) => {
+                Some(relative.apply(current, value, key.kind.max_value()))
+            }
+        }
+    }
+}
+
+/// Use a button to switch a channel in `current` mode into or out of `mode`. Returns `None` if
+/// the mode doesn't change.
+pub(super) fn mode_change(
+    current: ChannelMode,
+    mode: ChannelMode,
+    button: Button,
+    pressed: bool,
+) -> Option<AudioMsgKind> {
+    let on = button.apply(current == mode, pressed)?;
+    let next = match (on, current == mode) {
+        (true, false) => mode,
+        (false, true) => ChannelMode::Normal,
+        // turning off a mode we aren't in shouldn't leave the mode we are in.
+        _ => return None,
+    };
+    Some(AudioMsgKind::Mode(next))
+}
+
+/// Keeps track of the state we need to decode multi-message controls.
+///
+/// Only the controls that are mapped in the lookup are decoded as 14-bit, everything else is
+/// passed through as plain 7-bit controllers.
+pub struct MidiDecoder {
+    channels: [ChannelDecoder; 16],
+}
+
+impl MidiDecoder {
+    pub fn new(lookup: &MidiLookup) -> Self {
+        let mut channels = [ChannelDecoder::default(); 16];
+        for key in lookup.keys() {
+            let channel = &mut channels[key.channel as usize];
+            match key.kind {
+                MidiKeyKind::Controller14(ctrl) => channel.hi_res |= 1 << ctrl,
+                MidiKeyKind::Nrpn(_) => channel.nrpn = true,
+                _ => (),
+            }
+        }
+        MidiDecoder { channels }
+    }
+
+    /// Get the key and raw value for an event, if it is one we understand.
+    ///
+    /// Use `MidiKeyKind::normalize` to get the value in the range 0 to 1.
+    pub fn decode(&mut self, evt: MidiEvent) -> Option<(MidiKey, u16)> {
+        use MidiEventType::*;
+        let channel = evt.channel;
+        let decoder = self.channels.get_mut(channel as usize)?;
+        let (kind, value) = match evt.event {
+            Controller(ctrl, value) => decoder.controller(ctrl, value)?,
+            NoteOn(note, velocity) => (MidiKeyKind::Note(note.into()), velocity as u16),
+            // a note off is the same as a note on with 0 velocity.
+            NoteOff(note, _) => (MidiKeyKind::Note(note.into()), 0),
+            PitchBend(lsb, msb) => (MidiKeyKind::PitchBend, join(msb, lsb)),
+            _ => return None,
+        };
+        Some((MidiKey { channel, kind }, value))
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct ChannelDecoder {
+    /// Bit `n` is set if controllers `n` and `n + 32` are a 14-bit pair.
+    hi_res: u32,
+    /// Whether we should interpret NRPN messages on this channel.
+    nrpn: bool,
+    /// The last MSB we saw for each 14-bit controller.
+    msb: [u8; 32],
+    /// The MSB of the NRPN being selected.
+    param_msb: u8,
+    /// The currently selected NRPN.
+    param: Option<u16>,
+    /// The last data entry MSB for the current NRPN.
+    data_msb: u8,
+}
+
+impl ChannelDecoder {
+    fn is_hi_res(&self, ctrl: u8) -> bool {
+        ctrl < 32 && self.hi_res & (1 << ctrl) != 0
+    }
+
+    /// Handle a controller message. When the MSB of a 14-bit value arrives we report it
+    /// straight away with an LSB of 0 (as the midi spec requires), then report it again with the
+    /// correct LSB if one follows.
+    fn controller(&mut self, ctrl: u8, value: u8) -> Option<(MidiKeyKind, u16)> {
+        if self.nrpn {
+            match ctrl {
+                NRPN_MSB => {
+                    self.param_msb = value;
+                    self.param = None;
+                    return None;
+                }
+                NRPN_LSB => {
+                    self.param = Some(join(self.param_msb, value));
+                    return None;
+                }
+                RPN_MSB | RPN_LSB => {
+                    self.param = None;
+                    return None;
+                }
+                DATA_ENTRY_MSB if self.param.is_some() => {
+                    self.data_msb = value;
+                    return Some((MidiKeyKind::Nrpn(self.param?), join(value, 0)));
+                }
+                DATA_ENTRY_LSB if self.param.is_some() => {
+                    return Some((MidiKeyKind::Nrpn(self.param?), join(self.data_msb, value)));
+                }
+                _ => (),
+            }
+        }
+        if self.is_hi_res(ctrl) {
+            self.msb[ctrl as usize] = value;
+            Some((MidiKeyKind::Controller14(ctrl), join(value, 0)))
+        } else if ctrl >= 32 && self.is_hi_res(ctrl - 32) {
+            let ctrl = ctrl - 32;
+            let msb = self.msb[ctrl as usize];
+            Some((MidiKeyKind::Controller14(ctrl), join(msb, value)))
+        } else {
+            Some((MidiKeyKind::Controller(ctrl), value as u16))
+        }
+    }
+}
+
+/// Make a 14-bit value from two 7-bit halves.
+#[inline]
+fn join(msb: u8, lsb: u8) -> u16 {
+    ((msb as u16 & 0x7f) << 7) | (lsb as u16 & 0x7f)
+}
+
+/// Split a 14-bit value into its 7-bit halves, the opposite of `join`.
+#[inline]
+pub(super) fn split(value: u16) -> (u8, u8) {
+    (((value >> 7) & 0x7f) as u8, (value & 0x7f) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{join, split, ChannelDecoder};
+    use crate::cli::MidiKeyKind;
+
+    #[test]
+    fn split_join() {
+        for value in &[0, 1, 0x7f, 0x80, 0x2001, 0x3fff] {
+            let (msb, lsb) = split(*value);
+            assert_eq!(join(msb, lsb), *value);
+        }
+    }
+
+    #[test]
+    fn controller14() {
+        let mut decoder = ChannelDecoder {
+            hi_res: 1 << 7,
+            ..ChannelDecoder::default()
+        };
+        assert_eq!(
+            decoder.controller(7, 0x40),
+            Some((MidiKeyKind::Controller14(7), 0x2000))
+        );
+        assert_eq!(
+            decoder.controller(39, 0x01),
+            Some((MidiKeyKind::Controller14(7), 0x2001))
+        );
+        // not a hi-res pair
+        assert_eq!(
+            decoder.controller(40, 0x01),
+            Some((MidiKeyKind::Controller(40), 0x01))
+        );
+    }
+
+    #[test]
+    fn nrpn() {
+        let mut decoder = ChannelDecoder {
+            nrpn: true,
+            ..ChannelDecoder::default()
+        };
+        // data entry without a parameter is just a controller
+        assert_eq!(
+            decoder.controller(6, 0x10),
+            Some((MidiKeyKind::Controller(6), 0x10))
+        );
+        assert_eq!(decoder.controller(99, 0x01), None);
+        assert_eq!(decoder.controller(98, 0x02), None);
+        assert_eq!(
+            decoder.controller(6, 0x7f),
+            Some((MidiKeyKind::Nrpn(0x82), 0x3f80))
+        );
+        assert_eq!(
+            decoder.controller(38, 0x7f),
+            Some((MidiKeyKind::Nrpn(0x82), 0x3fff))
+        );
+        // selecting an RPN deselects our NRPN
+        assert_eq!(decoder.controller(101, 0x00), None);
+        assert_eq!(
+            decoder.controller(38, 0x7f),
+            Some((MidiKeyKind::Controller(38), 0x7f))
+        );
+    }
+}
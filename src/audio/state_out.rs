@@ -0,0 +1,167 @@
+//! Publishing changes to the mixer as midi on `state_out`, so a DAW can record them as automation.
+use super::{
+    midi::{split, DATA_ENTRY_LSB, DATA_ENTRY_MSB, NRPN_LSB, NRPN_MSB},
+    ChannelState, State,
+};
+use crate::{
+    cli::{self, Config, MidiKey, MidiKeyKind, StateMapping},
+    data::ChannelMode,
+};
+use itertools::izip;
+use jack::{MidiOut, MidiWriter, Port, RawMidi};
+
+/// The most messages we need to send one value (an NRPN).
+const MAX_MESSAGES: usize = 4;
+
+/// Sends a message for every parameter that has changed since the last cycle, whatever changed it.
+/// That's the gain, the mode, and the frequency, gain and Q of each EQ band.
+///
+/// Everything here runs in the RT thread, so nothing may allocate.
+pub struct StateOut {
+    pub port: Port<MidiOut>,
+    mappings: Vec<StateMapping>,
+    /// The state we last sent for each channel, or `None` if we haven't sent it yet.
+    sent: Vec<Option<ChannelState>>,
+}
+
+impl StateOut {
+    pub fn new(port: Port<MidiOut>, config: &Config, state_out: &cli::StateOut) -> Self {
+        StateOut {
+            port,
+            mappings: state_out.mappings(config),
+            sent: vec![None; config.channels.len()],
+        }
+    }
+
+    /// Send anything in `state` that has changed since we last sent it.
+    pub fn send(&mut self, state: &State, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+        for (mapping, sent, channel) in izip!(&self.mappings, &mut self.sent, &state.channels) {
+            let (gain_changed, mode_changed) = match sent {
+                Some(sent) => (sent.gain != channel.gain, sent.mode != channel.mode),
+                None => (true, true),
+            };
+            if gain_changed {
+                if let Some(key) = mapping.volume {
+                    let value = (channel.gain * key.kind.max_value() as f64).round() as u16;
+                    write(key, value, out)?;
+                }
+            }
+            if mode_changed {
+                for (key, mode) in &[
+                    (mapping.mute, ChannelMode::Mute),
+                    (mapping.bypass, ChannelMode::Bypass),
+                ] {
+                    if let Some(key) = key {
+                        let value = if channel.mode == *mode {
+                            key.kind.max_value()
+                        } else {
+                            0
+                        };
+                        write(*key, value, out)?;
+                    }
+                }
+            }
+            for (idx, (keys, band)) in mapping.eq.iter().zip(channel.eq.iter()).enumerate() {
+                for (param, key) in keys.keys().iter() {
+                    let key = match key {
+                        Some(key) => *key,
+                        None => continue,
+                    };
+                    let position = param.position(band);
+                    let changed = match sent {
+                        Some(sent) => param.position(&sent.eq[idx]) != position,
+                        None => true,
+                    };
+                    if changed {
+                        let value = (position * key.kind.max_value() as f64).round() as u16;
+                        write(key, value, out)?;
+                    }
+                }
+            }
+            *sent = Some(channel.clone());
+        }
+        Ok(())
+    }
+}
+
+fn write(key: MidiKey, value: u16, out: &mut MidiWriter<'_>) -> Result<(), jack::Error> {
+    let (messages, len) = encode(key, value);
+    for bytes in messages[..len].iter() {
+        out.write(&RawMidi { time: 0, bytes })?;
+    }
+    Ok(())
+}
+
+/// The midi messages that set the control `key` to `value`. Only the first `len` messages are
+/// used.
+fn encode(key: MidiKey, value: u16) -> ([[u8; 3]; MAX_MESSAGES], usize) {
+    let mut messages = [[0; 3]; MAX_MESSAGES];
+    let channel = key.channel & 0x0f;
+    let cc = 0xb0 | channel;
+    let (msb, lsb) = split(value);
+    let len = match key.kind {
+        MidiKeyKind::Controller(ctrl) => {
+            messages[0] = [cc, ctrl, lsb];
+            1
+        }
+        MidiKeyKind::Note(note) if value > 0 => {
+            messages[0] = [0x90 | channel, note, lsb];
+            1
+        }
+        MidiKeyKind::Note(note) => {
+            messages[0] = [0x80 | channel, note, 0];
+            1
+        }
+        MidiKeyKind::Controller14(ctrl) => {
+            messages[0] = [cc, ctrl, msb];
+            messages[1] = [cc, ctrl + 32, lsb];
+            2
+        }
+        MidiKeyKind::Nrpn(param) => {
+            let (param_msb, param_lsb) = split(param);
+            messages[0] = [cc, NRPN_MSB, param_msb];
+            messages[1] = [cc, NRPN_LSB, param_lsb];
+            messages[2] = [cc, DATA_ENTRY_MSB, msb];
+            messages[3] = [cc, DATA_ENTRY_LSB, lsb];
+            4
+        }
+        MidiKeyKind::PitchBend => {
+            messages[0] = [0xe0 | channel, lsb, msb];
+            1
+        }
+    };
+    (messages, len)
+}
+
+#[cfg(test)]
+mod test {
+    use super::encode;
+    use crate::cli::{MidiKey, MidiKeyKind};
+
+    fn encoded(kind: MidiKeyKind, value: u16) -> Vec<[u8; 3]> {
+        let (messages, len) = encode(MidiKey { channel: 2, kind }, value);
+        messages[..len].to_vec()
+    }
+
+    #[test]
+    fn encode_keys() {
+        use MidiKeyKind::*;
+        assert_eq!(encoded(Controller(20), 0x7f), vec![[0xb2, 20, 0x7f]]);
+        assert_eq!(encoded(Note(41), 0x40), vec![[0x92, 41, 0x40]]);
+        assert_eq!(encoded(Note(41), 0), vec![[0x82, 41, 0]]);
+        assert_eq!(
+            encoded(Controller14(7), 0x2001),
+            vec![[0xb2, 7, 0x40], [0xb2, 39, 0x01]]
+        );
+        assert_eq!(
+            encoded(Nrpn(0x82), 0x3fff),
+            vec![
+                [0xb2, 99, 0x01],
+                [0xb2, 98, 0x02],
+                [0xb2, 6, 0x7f],
+                [0xb2, 38, 0x7f]
+            ]
+        );
+        assert_eq!(encoded(PitchBend, 0x2000), vec![[0xe2, 0x00, 0x40]]);
+    }
+}
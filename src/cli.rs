@@ -1,14 +1,19 @@
-use crate::Result;
+use crate::{
+    data::ChannelMode,
+    effects::{EqParam, EQ_BANDS},
+    profile::Profile,
+    Result,
+};
 use anyhow::format_err;
 use directories::ProjectDirs;
 use fnv::FnvHashMap as HashMap;
 use im::{ordmap, OrdMap};
 use itertools::izip;
-use midi_event::MidiEvent;
 use serde::Deserialize;
 use std::{
     convert::TryFrom,
     env, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Deref,
     path::{Path, PathBuf},
 };
@@ -36,12 +41,56 @@ pub struct Opt {
     /// If this flag is passed, the program will print the filter window to stdout and exit.
     #[structopt(long = "print-window")]
     pub print_window: bool,
+    /// Run without the gui, controlled by midi (and osc or http if they are configured). Stop
+    /// with SIGINT or SIGTERM.
+    #[structopt(long = "headless")]
+    pub headless: bool,
+    /// Use a terminal ui instead of the gui.
+    #[structopt(long = "tui", raw(conflicts_with = r#""headless""#))]
+    pub tui: bool,
 }
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Config {
     // the order of channels matters.
     pub channels: OrdMap<String, Channel>,
+    /// The device to send LED feedback to on `control_out`, if any.
+    #[serde(default)]
+    pub feedback: Option<FeedbackProfile>,
+    /// The device whose built-in layout we use for any controls not mapped in `channels`. This is
+    /// the LaunchControl XL when there is no config file, and none if a config file doesn't ask
+    /// for one.
+    #[serde(default)]
+    pub driver: DriverProfile,
+    /// The location of a controller profile, relative to the config file.
+    #[serde(default)]
+    pub profile: Option<PathBuf>,
+    /// The midi controllers to create ports for. If there are none in the config file, we use a
+    /// single controller called "control" with the top-level `feedback`, `driver` and `profile`
+    /// settings and the mappings in `channels`.
+    #[serde(default, rename = "controller")]
+    pub controllers: Vec<Controller>,
+    /// If present, we publish every change to the mixer on a `state_out` port.
+    #[serde(default)]
+    pub state_out: Option<StateOut>,
+    /// If present, we run an OSC server so the mixer can be controlled over the network.
+    #[serde(default)]
+    pub osc: Option<OscConfig>,
+    /// If present, we run an http server with a JSON api for controlling the mixer.
+    #[serde(default)]
+    pub http: Option<HttpConfig>,
+    /// The size of the FFT each channel is processed (and analysed) with. Bigger sizes give a
+    /// finer spectrum, but more latency.
+    #[serde(default = "default_fft_size")]
+    pub fft_size: usize,
+    /// Pairs of channels (left, right) that carry a stereo signal, which we measure the phase
+    /// correlation of and show on a goniometer.
+    #[serde(default)]
+    pub stereo: Vec<(String, String)>,
+    /// If present, the gain, mode and EQ of every channel are saved to this file (relative to
+    /// the config file) when we stop, and restored from it when we start.
+    #[serde(default)]
+    pub session: Option<PathBuf>,
 }
 
 impl Default for Config {
@@ -50,7 +99,23 @@ impl Default for Config {
             "left".into() => Channel::empty(),
             "right".into() => Channel::empty()
         };
-        Config { channels }
+        Config {
+            channels,
+            // with no config, a LaunchControl XL works out of the box.
+            feedback: Some(FeedbackProfile::LaunchControlXl {
+                template: default_lcxl_template(),
+            }),
+            driver: DriverProfile::LaunchControlXl,
+            profile: None,
+            controllers: Vec::new(),
+            state_out: None,
+            osc: None,
+            http: None,
+            fft_size: default_fft_size(),
+            stereo: vec![("left".into(), "right".into())],
+            session: None,
+        }
+        .with_default_controller()
     }
 }
 
@@ -70,7 +135,7 @@ impl Config {
                 // Break on all errors, including not found
                 log::info!("using config at \"{}\"", loc.display());
                 let conf_raw = fs::read(loc)?;
-                return Ok(toml::from_slice(&conf_raw)?);
+                return Config::from_raw(&conf_raw, loc);
             }
             None => (), // continue
         }
@@ -89,7 +154,7 @@ impl Config {
                 Err(e) => return Err(e.into()),
             };
             log::info!("using config at \"{}\"", current_dir_path.display());
-            Ok(Some(toml::from_slice(&conf_raw)?))
+            Ok(Some(Config::from_raw(&conf_raw, &current_dir_path)?))
         }
         match load_from_current()? {
             Some(conf) => return Ok(conf),
@@ -101,24 +166,320 @@ impl Config {
             .ok_or(format_err!("could not load project directories"))?;
         let config_path = dirs.config_dir().join(CONFIG_FILE_NAME);
         log::info!("using config at \"{}\"", config_path.display());
-        match fs::read(config_path) {
-            Ok(conf_raw) => Ok(toml::from_slice(&conf_raw)?),
+        match fs::read(&config_path) {
+            Ok(conf_raw) => Config::from_raw(&conf_raw, &config_path),
             Err(e) if matches!(e.kind(), io::ErrorKind::NotFound) => Ok(Config::default()),
             Err(e) => Err(e.into()),
         }
     }
 
-    pub fn midi_lookup(&self) -> MidiLookup {
-        MidiLookup::construct(self)
+    /// Parse the config file at `path`, and load the controller profiles it refers to.
+    fn from_raw(conf_raw: &[u8], path: &Path) -> Result<Self> {
+        let config: Config = toml::from_slice(conf_raw)?;
+        let mut config = config.with_default_controller();
+        if !config.fft_size.is_power_of_two() || !(64..=16384).contains(&config.fft_size) {
+            return Err(format_err!(
+                "`fft_size` must be a power of 2 between 64 and 16384, found {}",
+                config.fft_size
+            ));
+        }
+        let channels = &config.channels;
+        if let Some(state_out) = config.state_out.as_ref() {
+            if let Some(name) = state_out
+                .channels
+                .keys()
+                .find(|name| !channels.contains_key(*name))
+            {
+                return Err(format_err!(
+                    "`state_out` has mappings for unknown channel \"{}\"",
+                    name
+                ));
+            }
+            if let Some((name, _)) = state_out
+                .channels
+                .iter()
+                .find(|(_, mapping)| mapping.eq.len() > EQ_BANDS)
+            {
+                return Err(format_err!(
+                    "`state_out` has mappings for more than {} EQ bands on channel \"{}\"",
+                    EQ_BANDS,
+                    name
+                ));
+            }
+        }
+        for (left, right) in config.stereo.iter() {
+            if let Some(name) = [left, right]
+                .iter()
+                .find(|name| !channels.contains_key(**name))
+            {
+                return Err(format_err!(
+                    "`stereo` has a pair with unknown channel \"{}\"",
+                    name
+                ));
+            }
+            if left == right {
+                return Err(format_err!(
+                    "`stereo` pairs channel \"{}\" with itself",
+                    left
+                ));
+            }
+        }
+        for controller in config.controllers.iter_mut() {
+            if let Some(name) = controller
+                .channels
+                .keys()
+                .find(|name| !channels.contains_key(*name))
+            {
+                return Err(format_err!(
+                    "controller \"{}\" has mappings for unknown channel \"{}\"",
+                    controller.name,
+                    name
+                ));
+            }
+            if let Some((name, _)) = controller
+                .channels
+                .iter()
+                .find(|(_, channel)| channel.eq.len() > EQ_BANDS)
+            {
+                return Err(format_err!(
+                    "controller \"{}\" has mappings for more than {} EQ bands on channel \"{}\"",
+                    controller.name,
+                    EQ_BANDS,
+                    name
+                ));
+            }
+            if controller.automation && config.state_out.is_none() {
+                return Err(format_err!(
+                    "controller \"{}\" plays back automation, but `state_out` is not configured",
+                    controller.name
+                ));
+            }
+            if let Some(profile) = controller.profile.as_ref() {
+                let profile = match path.parent() {
+                    Some(dir) => dir.join(profile),
+                    None => profile.clone(),
+                };
+                controller.device = Some(Profile::load(&profile)?);
+            }
+        }
+        if let (Some(session), Some(dir)) = (config.session.as_mut(), path.parent()) {
+            *session = dir.join(&session);
+        }
+        Ok(config)
+    }
+
+    /// If no controllers are listed, make one from the top-level settings.
+    fn with_default_controller(mut self) -> Self {
+        if self.controllers.is_empty() {
+            self.controllers.push(Controller {
+                name: "control".into(),
+                feedback: self.feedback,
+                driver: self.driver,
+                profile: self.profile.clone(),
+                device: None,
+                channels: self.channels.clone(),
+                automation: false,
+            });
+        } else if self.channels.values().any(Channel::has_mappings) {
+            log::warn!("mappings in `channels` are ignored when controllers are listed");
+        }
+        self
+    }
+
+    pub fn midi_lookup(&self, controller: &Controller) -> MidiLookup {
+        MidiLookup::construct(self, controller)
+    }
+
+    /// The indices of the channels in each stereo pair.
+    pub fn stereo_pairs(&self) -> Vec<(usize, usize)> {
+        let idx = |name: &String| self.channels.keys().position(|key| key == name).unwrap();
+        self.stereo
+            .iter()
+            .map(|(left, right)| (idx(left), idx(right)))
+            .collect()
     }
 }
 
+/// A midi controller, with its own pair of ports (`<name>_in` and `<name>_out`).
+///
+/// In the config file each controller is a `[[controller]]` table, with mappings for it in
+/// `[controller.channels.<channel name>]`.
 #[derive(Deserialize, Clone, Debug)]
-pub struct Channel {
-    pub high: Option<MidiKey>,
-    pub mid: Option<MidiKey>,
-    pub low: Option<MidiKey>,
+pub struct Controller {
+    pub name: String,
+    /// The device to send LED feedback to on `<name>_out`, if any.
+    #[serde(default)]
+    pub feedback: Option<FeedbackProfile>,
+    /// The device whose built-in layout we use for any controls not mapped in `channels`. There
+    /// is none unless the config asks for one, so the controls of a `profile` aren't also read as
+    /// those of another device.
+    #[serde(default)]
+    pub driver: DriverProfile,
+    /// The location of a controller profile, relative to the config file.
+    #[serde(default)]
+    pub profile: Option<PathBuf>,
+    /// The controller profile at `profile`, loaded with the config.
+    #[serde(skip)]
+    pub device: Option<Profile>,
+    /// Mappings for this controller, by channel name.
+    #[serde(default)]
+    pub channels: OrdMap<String, Channel>,
+    /// Also respond to the messages we send on `state_out`, so that automation recorded from it
+    /// can be played back into this controller's input.
+    #[serde(default)]
+    pub automation: bool,
+}
+
+/// Where the OSC server listens, e.g. `osc = { port = 9000 }`.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OscConfig {
+    /// The address to listen on. Defaults to localhost, use `"0.0.0.0"` to allow connections from
+    /// other machines.
+    #[serde(default = "localhost")]
+    pub address: IpAddr,
+    pub port: u16,
+}
+
+impl OscConfig {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+/// Where the http server listens, e.g. `http = { port = 8080 }`.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HttpConfig {
+    /// The address to listen on. Defaults to localhost, use `"0.0.0.0"` to allow connections from
+    /// other machines.
+    #[serde(default = "localhost")]
+    pub address: IpAddr,
+    pub port: u16,
+    /// The port for the websocket stream of changes. Defaults to the port after `port`.
+    #[serde(default)]
+    pub websocket_port: Option<u16>,
+}
+
+impl HttpConfig {
+    pub fn socket_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+
+    pub fn websocket_addr(&self) -> SocketAddr {
+        let port = self
+            .websocket_port
+            .unwrap_or_else(|| self.port.wrapping_add(1));
+        SocketAddr::new(self.address, port)
+    }
+}
+
+fn localhost() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+/// How to publish the mixer state on `state_out`, so that a DAW can record it as automation.
+///
+/// Any channels missing from `channels` use the default mapping (see `StateMapping::default_for`).
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct StateOut {
+    #[serde(default)]
+    pub channels: OrdMap<String, StateMapping>,
+}
+
+impl StateOut {
+    /// The mapping for each channel in the config, in order.
+    pub fn mappings(&self, config: &Config) -> Vec<StateMapping> {
+        config
+            .channels
+            .keys()
+            .enumerate()
+            .map(|(idx, name)| match self.channels.get(name) {
+                Some(mapping) => mapping.clone(),
+                None => StateMapping::default_for(idx),
+            })
+            .collect()
+    }
+}
+
+/// The midi we send when a channel parameter changes.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateMapping {
     pub volume: Option<MidiKey>,
+    /// Sent with the maximum value when the channel is muted, and 0 otherwise.
+    pub mute: Option<MidiKey>,
+    /// Sent with the maximum value when the channel is bypassed, and 0 otherwise.
+    pub bypass: Option<MidiKey>,
+    /// The parameters of each band of the EQ, in order.
+    #[serde(default)]
+    pub eq: Vec<StateBandMapping>,
+}
+
+impl StateMapping {
+    /// Channel `n` uses midi channel `n`, with the volume on (14-bit) controller 7, mute on
+    /// controller 20, bypass on controller 21, and the frequency, gain and Q of EQ band `b` on
+    /// NRPNs `4b`, `4b + 1` and `4b + 2`. Channels after the 16th are not sent.
+    pub fn default_for(idx: usize) -> Self {
+        if idx > 0x0f {
+            return StateMapping::default();
+        }
+        let channel = idx as u8;
+        let nrpn = |param| Some(MidiKey::nrpn(channel, param));
+        StateMapping {
+            volume: Some(MidiKey {
+                channel,
+                kind: MidiKeyKind::Controller14(0x07),
+            }),
+            mute: Some(MidiKey::controller(channel, 0x14)),
+            bypass: Some(MidiKey::controller(channel, 0x15)),
+            eq: (0..EQ_BANDS as u16)
+                .map(|band| StateBandMapping {
+                    freq: nrpn(4 * band),
+                    gain: nrpn(4 * band + 1),
+                    q: nrpn(4 * band + 2),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The midi we send when a parameter of an EQ band changes. The values are positions from 0 to
+/// the maximum value of the key, in the same scale as `EqParam::position`. The type of the band
+/// and whether it is on aren't sent.
+#[derive(Deserialize, Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[serde(default)]
+pub struct StateBandMapping {
+    pub freq: Option<MidiKey>,
+    pub gain: Option<MidiKey>,
+    pub q: Option<MidiKey>,
+}
+
+impl StateBandMapping {
+    /// The key for each parameter.
+    pub fn keys(&self) -> [(EqParam, Option<MidiKey>); 3] {
+        [
+            (EqParam::Freq, self.freq),
+            (EqParam::Gain, self.gain),
+            (EqParam::Q, self.q),
+        ]
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Channel {
+    /// The gain of the high shelf (EQ band 3).
+    pub high: Option<Mapping>,
+    /// The gain of the lower peak (EQ band 1).
+    pub mid: Option<Mapping>,
+    /// The gain of the low shelf (EQ band 0).
+    pub low: Option<Mapping>,
+    /// Controls for each band of the EQ, in order. These take priority over `high`, `mid` and
+    /// `low`.
+    #[serde(default)]
+    pub eq: Vec<BandMapping>,
+    pub volume: Option<Mapping>,
+    pub mute: Option<Mapping>,
+    /// Bypassing the channel turns off all processing (including EQ).
+    #[serde(alias = "tog_eq")]
+    pub bypass: Option<Mapping>,
 }
 
 impl Channel {
@@ -127,11 +488,251 @@ impl Channel {
             high: None,
             mid: None,
             low: None,
+            eq: Vec::new(),
             volume: None,
+            mute: None,
+            bypass: None,
+        }
+    }
+
+    /// Whether any controls are mapped for this channel.
+    pub fn has_mappings(&self) -> bool {
+        [
+            &self.high,
+            &self.mid,
+            &self.low,
+            &self.volume,
+            &self.mute,
+            &self.bypass,
+        ]
+        .iter()
+        .any(|mapping| mapping.is_some())
+            || self.eq.iter().any(BandMapping::has_mappings)
+    }
+}
+
+/// The controls for one band of the EQ.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct BandMapping {
+    pub freq: Option<Mapping>,
+    pub gain: Option<Mapping>,
+    pub q: Option<Mapping>,
+}
+
+impl BandMapping {
+    fn has_mappings(&self) -> bool {
+        self.freq.is_some() || self.gain.is_some() || self.q.is_some()
+    }
+}
+
+/// A midi control bound to a mixer parameter, along with how the control should behave.
+///
+/// In the config file this is either just the key (e.g. `[0x8, "ctrl", 0x4d]`), or a table like
+/// `{ key = [0x8, "ctrl", 0x4d], takeover = "pickup" }`. Relative controls are configured like
+/// `{ key = [0x8, "ctrl", 0x0d], encoding = "offset", step = 0.01, acceleration = 0.5 }`, and
+/// buttons like `{ key = [0x8, "note", 41], button = "momentary" }`.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq)]
+#[serde(from = "MappingRaw")]
+pub struct Mapping {
+    pub key: MidiKey,
+    pub takeover: Takeover,
+    pub encoding: Encoding,
+    pub button: Button,
+}
+
+/// What to do when an absolute control (e.g. a fader) doesn't match the parameter it controls,
+/// for example because the parameter was changed in the gui.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Takeover {
+    /// Set the parameter to the position of the control straight away.
+    Jump,
+    /// Ignore the control until it passes the current value of the parameter.
+    Pickup,
+    /// Move the parameter in proportion to the control, so that they meet at the end of the
+    /// control's range.
+    Scale,
+}
+
+impl Default for Takeover {
+    fn default() -> Self {
+        Takeover::Jump
+    }
+}
+
+impl Takeover {
+    /// How close a control must be to the parameter to pick it up without crossing it.
+    const PICKUP_THRESHOLD: f64 = 1.0 / 127.0;
+
+    /// Work out the new value of a parameter at `current` when its control moves from `prev` to
+    /// `next`. `prev` is `None` if we haven't seen the control move before.
+    ///
+    /// All values are between 0 and 1. Returns `None` if the parameter should not change.
+    pub fn apply(self, current: f64, prev: Option<f64>, next: f64) -> Option<f64> {
+        match self {
+            Takeover::Jump => Some(next),
+            Takeover::Pickup => {
+                let crossed = match prev {
+                    Some(prev) => (prev - current) * (next - current) <= 0.0,
+                    None => false,
+                };
+                if crossed || (next - current).abs() <= Self::PICKUP_THRESHOLD {
+                    Some(next)
+                } else {
+                    None
+                }
+            }
+            Takeover::Scale => {
+                let prev = prev?;
+                let value = if next > prev {
+                    current + (next - prev) * (1.0 - current) / (1.0 - prev)
+                } else if next < prev {
+                    current - (prev - next) * current / prev
+                } else {
+                    return None;
+                };
+                Some(value.clamp(0.0, 1.0))
+            }
         }
     }
 }
 
+/// How a control reports its value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    /// The control sends its position (e.g. a fader or a pot).
+    Absolute,
+    /// The control sends how far it has moved (e.g. an endless encoder).
+    Relative(Relative),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Absolute
+    }
+}
+
+/// Settings for a relative control.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Relative {
+    pub format: RelativeFormat,
+    /// How much a parameter changes for one step of the control (parameters are between 0 and
+    /// 1).
+    pub step: f64,
+    /// How much bigger each step gets when the control sends more than one step at a time
+    /// (which encoders do when turned quickly). 0 means no acceleration.
+    pub acceleration: f64,
+}
+
+impl Relative {
+    /// The new value of a parameter at `current` after the control sends `value`.
+    pub fn apply(&self, current: f64, value: u16, max_value: u16) -> f64 {
+        let delta = self.format.delta(value, max_value) as f64;
+        let steps = delta * (1.0 + self.acceleration * (delta.abs() - 1.0).max(0.0));
+        (current + steps * self.step).clamp(0.0, 1.0)
+    }
+}
+
+/// The ways controllers encode movement of a relative control.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelativeFormat {
+    /// Negative numbers are two's complement (`0x01` is +1, `0x7f` is -1).
+    TwosComplement,
+    /// The middle value means no movement (`0x41` is +1, `0x3f` is -1).
+    Offset,
+    /// The top bit is the sign (`0x01` is +1, `0x41` is -1).
+    SignMagnitude,
+}
+
+impl RelativeFormat {
+    /// The number of steps a control moved, given the raw value it sent.
+    pub fn delta(self, value: u16, max_value: u16) -> i32 {
+        let value = value as i32;
+        let half = (max_value as i32 + 1) / 2;
+        match self {
+            RelativeFormat::TwosComplement if value >= half => value - 2 * half,
+            RelativeFormat::TwosComplement => value,
+            RelativeFormat::Offset => value - half,
+            RelativeFormat::SignMagnitude if value >= half => half - value,
+            RelativeFormat::SignMagnitude => value,
+        }
+    }
+}
+
+/// How a button drives an on/off parameter.
+///
+/// A button is pressed when it sends a non-zero value, and released when it sends zero (including
+/// note off and note on with zero velocity).
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Button {
+    /// Each press switches the parameter on or off.
+    Toggle,
+    /// The parameter is on while the button is held.
+    Momentary,
+    /// Each press switches the parameter on.
+    Trigger,
+}
+
+impl Default for Button {
+    fn default() -> Self {
+        Button::Toggle
+    }
+}
+
+impl Button {
+    /// Whether the parameter should be on, given whether it's `on` now and whether the button is
+    /// `pressed`. Returns `None` if the parameter should not change.
+    pub fn apply(self, on: bool, pressed: bool) -> Option<bool> {
+        match self {
+            Button::Toggle if pressed => Some(!on),
+            Button::Momentary => Some(pressed),
+            Button::Trigger if pressed => Some(true),
+            Button::Toggle | Button::Trigger => None,
+        }
+    }
+}
+
+/// Describes how to show mixer state on a controller.
+///
+/// In the config file this looks like `feedback = { device = "launch_control_xl", template = 8 }`.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "device", rename_all = "snake_case")]
+pub enum FeedbackProfile {
+    /// Novation LaunchControl XL. The template is the one whose LEDs we drive (0-7 are user
+    /// templates, 8-15 are factory templates).
+    LaunchControlXl {
+        #[serde(default = "default_lcxl_template")]
+        template: u8,
+    },
+}
+
+/// Devices that we understand without needing any mappings in the config file.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DriverProfile {
+    /// Only use the mappings in the config.
+    None,
+    /// Novation LaunchControl XL, on user template 1 or factory template 1.
+    LaunchControlXl,
+}
+
+impl Default for DriverProfile {
+    fn default() -> Self {
+        DriverProfile::None
+    }
+}
+
+fn default_fft_size() -> usize {
+    512
+}
+
+fn default_lcxl_template() -> u8 {
+    0x08
+}
+
 #[derive(Deserialize, Copy, Clone, Debug, Hash, PartialEq, Eq)]
 #[serde(try_from = "MidiKeyRaw")]
 pub struct MidiKey {
@@ -139,22 +740,6 @@ pub struct MidiKey {
     pub kind: MidiKeyKind,
 }
 
-impl MidiKey {
-    /// If the event is one that we might handle, convert it to our type.
-    pub fn from_opt(evt: MidiEvent) -> Option<Self> {
-        use midi_event::MidiEventType::*;
-        let kind = match evt.event {
-            Controller(ctrl, _) => MidiKeyKind::Controller(ctrl),
-            NoteOn(note, _) => MidiKeyKind::Note(note.into()),
-            _ => return None,
-        };
-        Some(MidiKey {
-            channel: evt.channel,
-            kind,
-        })
-    }
-}
-
 impl MidiKey {
     /// Constructor for controller key.
     pub fn controller(channel: u8, controller: u8) -> Self {
@@ -163,27 +748,59 @@ impl MidiKey {
             kind: MidiKeyKind::Controller(controller),
         }
     }
+
+    /// Constructor for NRPN key.
+    pub fn nrpn(channel: u8, param: u16) -> Self {
+        MidiKey {
+            channel,
+            kind: MidiKeyKind::Nrpn(param),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
 pub enum MidiKeyKind {
     Controller(u8),
     Note(u8),
+    /// A 14-bit controller, with the MSB on controller `n` (0-31) and the LSB on controller
+    /// `n + 32`.
+    Controller14(u8),
+    /// A (14-bit) non-registered parameter number.
+    Nrpn(u16),
+    PitchBend,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+impl MidiKeyKind {
+    /// The largest value a control of this kind can send.
+    pub fn max_value(self) -> u16 {
+        match self {
+            MidiKeyKind::Controller(_) | MidiKeyKind::Note(_) => 0x7f,
+            MidiKeyKind::Controller14(_) | MidiKeyKind::Nrpn(_) | MidiKeyKind::PitchBend => 0x3fff,
+        }
+    }
+
+    /// Map a value from this kind of control into the range 0 to 1.
+    pub fn normalize(self, value: u16) -> f64 {
+        value as f64 / self.max_value() as f64
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct MidiEffect {
     pub channel: usize,
     pub kind: MidiEffectKind,
+    pub takeover: Takeover,
+    pub encoding: Encoding,
+    pub button: Button,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum MidiEffectKind {
-    // ToggleEq
-    // High
-    // Mid
-    // Low
     Gain,
+    /// A parameter of the EQ band with the given index.
+    Eq(usize, EqParam),
+    /// A button that switches the channel into the given mode, and back to normal.
+    Mode(ChannelMode),
 }
 
 // a data structure for quick midi -> action lookups.
@@ -191,20 +808,119 @@ pub enum MidiEffectKind {
 pub struct MidiLookup(HashMap<MidiKey, MidiEffect>);
 
 impl MidiLookup {
-    pub fn construct(config: &Config) -> Self {
-        let mut map = HashMap::default();
-        for (idx, (_, chan)) in config.channels.iter().enumerate() {
-            if let Some(volume) = chan.volume.as_ref() {
-                map.insert(
-                    *volume,
-                    MidiEffect {
-                        channel: idx,
-                        kind: MidiEffectKind::Gain,
-                    },
+    pub fn construct(config: &Config, controller: &Controller) -> Self {
+        let mut lookup = Self(HashMap::default());
+        // automation has the lowest priority, then the profile, then the mappings in the config.
+        if let (true, Some(state_out)) = (controller.automation, config.state_out.as_ref()) {
+            for (idx, mapping) in state_out.mappings(config).into_iter().enumerate() {
+                let key = |key: Option<MidiKey>, button| {
+                    key.map(|key| Mapping {
+                        key,
+                        takeover: Takeover::Jump,
+                        encoding: Encoding::Absolute,
+                        button,
+                    })
+                };
+                lookup.insert(
+                    idx,
+                    key(mapping.volume, Button::default()).as_ref(),
+                    MidiEffectKind::Gain,
+                );
+                // the mode messages say whether the mode is on, like holding a button down.
+                lookup.insert(
+                    idx,
+                    key(mapping.mute, Button::Momentary).as_ref(),
+                    MidiEffectKind::Mode(ChannelMode::Mute),
+                );
+                lookup.insert(
+                    idx,
+                    key(mapping.bypass, Button::Momentary).as_ref(),
+                    MidiEffectKind::Mode(ChannelMode::Bypass),
+                );
+                for (band, keys) in mapping.eq.iter().enumerate() {
+                    for (param, param_key) in keys.keys().iter() {
+                        lookup.insert(
+                            idx,
+                            key(*param_key, Button::default()).as_ref(),
+                            MidiEffectKind::Eq(band, *param),
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(device) = controller.device.as_ref() {
+            for (idx, strip) in device.strips.iter().enumerate() {
+                if idx >= config.channels.len() {
+                    break;
+                }
+                lookup.insert(idx, strip.gain.as_ref(), MidiEffectKind::Gain);
+                lookup.insert_eq(idx, &strip.eq);
+                lookup.insert(
+                    idx,
+                    strip.mute.as_ref(),
+                    MidiEffectKind::Mode(ChannelMode::Mute),
                 );
+                lookup.insert(
+                    idx,
+                    strip.bypass.as_ref(),
+                    MidiEffectKind::Mode(ChannelMode::Bypass),
+                );
+            }
+        }
+        for (idx, name) in config.channels.keys().enumerate() {
+            let chan = match controller.channels.get(name) {
+                Some(chan) => chan,
+                None => continue,
+            };
+            lookup.insert(idx, chan.volume.as_ref(), MidiEffectKind::Gain);
+            for (band, mapping) in &[(3, &chan.high), (1, &chan.mid), (0, &chan.low)] {
+                lookup.insert(
+                    idx,
+                    mapping.as_ref(),
+                    MidiEffectKind::Eq(*band, EqParam::Gain),
+                );
+            }
+            lookup.insert_eq(idx, &chan.eq);
+            lookup.insert(
+                idx,
+                chan.mute.as_ref(),
+                MidiEffectKind::Mode(ChannelMode::Mute),
+            );
+            lookup.insert(
+                idx,
+                chan.bypass.as_ref(),
+                MidiEffectKind::Mode(ChannelMode::Bypass),
+            );
+        }
+        lookup
+    }
+
+    /// Insert the controls for each band of the EQ of `channel`.
+    fn insert_eq(&mut self, channel: usize, bands: &[BandMapping]) {
+        for (band, mapping) in bands.iter().enumerate() {
+            for (param, mapping) in &[
+                (EqParam::Freq, &mapping.freq),
+                (EqParam::Gain, &mapping.gain),
+                (EqParam::Q, &mapping.q),
+            ] {
+                self.insert(channel, mapping.as_ref(), MidiEffectKind::Eq(band, *param));
             }
         }
-        Self(map)
+    }
+
+    fn insert(&mut self, channel: usize, mapping: Option<&Mapping>, kind: MidiEffectKind) {
+        if let Some(mapping) = mapping {
+            self.0.insert(
+                mapping.key,
+                MidiEffect {
+                    channel,
+                    kind,
+                    takeover: mapping.takeover,
+                    encoding: mapping.encoding,
+                    button: mapping.button,
+                },
+            );
+        }
     }
 }
 
@@ -215,17 +931,122 @@ impl Deref for MidiLookup {
     }
 }
 
-// helper for deserialize
+// helpers for deserialize
 
 #[derive(Deserialize, Debug)]
-struct MidiKeyRaw(u8, String, u8);
+#[serde(untagged)]
+enum MappingRaw {
+    Key(MidiKey),
+    Full {
+        key: MidiKey,
+        #[serde(default)]
+        takeover: Takeover,
+        #[serde(default)]
+        encoding: EncodingRaw,
+        #[serde(default = "default_step")]
+        step: f64,
+        #[serde(default)]
+        acceleration: f64,
+        #[serde(default)]
+        button: Button,
+    },
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum EncodingRaw {
+    Absolute,
+    TwosComplement,
+    Offset,
+    SignMagnitude,
+}
+
+impl Default for EncodingRaw {
+    fn default() -> Self {
+        EncodingRaw::Absolute
+    }
+}
+
+fn default_step() -> f64 {
+    0.01
+}
+
+impl From<MappingRaw> for Mapping {
+    fn from(raw: MappingRaw) -> Self {
+        match raw {
+            MappingRaw::Key(key) => Mapping {
+                key,
+                takeover: Takeover::default(),
+                encoding: Encoding::default(),
+                button: Button::default(),
+            },
+            MappingRaw::Full {
+                key,
+                takeover,
+                encoding,
+                step,
+                acceleration,
+                button,
+            } => {
+                let relative = |format| {
+                    Encoding::Relative(Relative {
+                        format,
+                        step,
+                        acceleration,
+                    })
+                };
+                let encoding = match encoding {
+                    EncodingRaw::Absolute => Encoding::Absolute,
+                    EncodingRaw::TwosComplement => relative(RelativeFormat::TwosComplement),
+                    EncodingRaw::Offset => relative(RelativeFormat::Offset),
+                    EncodingRaw::SignMagnitude => relative(RelativeFormat::SignMagnitude),
+                };
+                Mapping {
+                    key,
+                    takeover,
+                    encoding,
+                    button,
+                }
+            }
+        }
+    }
+}
+
+/// `[channel, kind, number]`, where the number is optional for kinds that don't need it (e.g.
+/// `[0x0, "pitch"]`).
+#[derive(Deserialize, Debug)]
+struct MidiKeyRaw(u8, String, #[serde(default)] u16);
 
 impl TryFrom<MidiKeyRaw> for MidiKey {
     type Error = anyhow::Error;
     fn try_from(raw: MidiKeyRaw) -> Result<Self, Self::Error> {
+        if raw.0 > 0x0f {
+            return Err(format_err!(
+                "midi channel must be less than 16, found {}",
+                raw.0
+            ));
+        }
+        let seven_bit = || {
+            u8::try_from(raw.2)
+                .ok()
+                .filter(|v| *v <= 0x7f)
+                .ok_or(format_err!("expected a 7-bit number, found {}", raw.2))
+        };
         let kind = match raw.1.as_str() {
-            "ctrl" => MidiKeyKind::Controller(raw.2),
-            "note" => MidiKeyKind::Note(raw.2),
+            "ctrl" => MidiKeyKind::Controller(seven_bit()?),
+            "note" => MidiKeyKind::Note(seven_bit()?),
+            "ctrl14" => match seven_bit()? {
+                ctrl if ctrl < 32 => MidiKeyKind::Controller14(ctrl),
+                ctrl => {
+                    return Err(format_err!(
+                        "14-bit controllers must be less than 32, found {}",
+                        ctrl
+                    ))
+                }
+            },
+            "nrpn" if raw.2 <= 0x3fff => MidiKeyKind::Nrpn(raw.2),
+            "nrpn" => return Err(format_err!("expected a 14-bit number, found {}", raw.2)),
+            "pitch" => MidiKeyKind::PitchBend,
             o => return Err(format_err!("unrecognised fader midi kind: {}", o)),
         };
         Ok(MidiKey {
@@ -234,3 +1055,159 @@ impl TryFrom<MidiKeyRaw> for MidiKey {
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{
+        Button, Config, DriverProfile, MidiEffectKind, MidiKey, Relative, RelativeFormat, Takeover,
+    };
+    use crate::effects::EqParam;
+    use std::path::Path;
+
+    #[test]
+    fn stereo_pairs() {
+        let path = Path::new("config.toml");
+        let channels = "[channels.a]\n[channels.b]\n";
+        let config = format!("stereo = [[\"b\", \"a\"]]\n{}", channels);
+        let config = Config::from_raw(config.as_bytes(), path).unwrap();
+        assert_eq!(config.stereo_pairs(), vec![(1, 0)]);
+        for stereo in &[r#"[["a", "c"]]"#, r#"[["a", "a"]]"#] {
+            let config = format!("stereo = {}\n{}", stereo, channels);
+            assert!(Config::from_raw(config.as_bytes(), path).is_err());
+        }
+    }
+
+    #[test]
+    fn eq_mappings() {
+        let path = Path::new("config.toml");
+        let config = r#"
+            [channels.a]
+            low = [0, "ctrl", 1]
+            high = [0, "ctrl", 2]
+            eq = [{}, {}, {}, { freq = [0, "ctrl", 2], q = [0, "ctrl", 3] }]
+        "#;
+        let config = Config::from_raw(config.as_bytes(), path).unwrap();
+        let lookup = config.midi_lookup(&config.controllers[0]);
+        let kind = |ctrl| lookup[&MidiKey::controller(0, ctrl)].kind;
+        assert_eq!(kind(1), MidiEffectKind::Eq(0, EqParam::Gain));
+        // `eq` takes priority over `high`.
+        assert_eq!(kind(2), MidiEffectKind::Eq(3, EqParam::Freq));
+        assert_eq!(kind(3), MidiEffectKind::Eq(3, EqParam::Q));
+
+        let config = "[channels.a]\neq = [{}, {}, {}, {}, {}]\n";
+        assert!(Config::from_raw(config.as_bytes(), path).is_err());
+    }
+
+    #[test]
+    fn state_out_eq() {
+        let path = Path::new("config.toml");
+        let config = r#"
+            [channels.a]
+            [channels.b]
+            [state_out.channels.b]
+            eq = [{ gain = [1, "ctrl", 30] }]
+            [[controller]]
+            name = "daw"
+            automation = true
+        "#;
+        let config = Config::from_raw(config.as_bytes(), path).unwrap();
+        let lookup = config.midi_lookup(&config.controllers[0]);
+        // channel a has the default mapping, channel b only what is in the config.
+        assert_eq!(
+            lookup[&MidiKey::nrpn(0, 5)].kind,
+            MidiEffectKind::Eq(1, EqParam::Gain)
+        );
+        assert_eq!(
+            lookup[&MidiKey::nrpn(0, 14)].kind,
+            MidiEffectKind::Eq(3, EqParam::Q)
+        );
+        assert_eq!(
+            lookup[&MidiKey::controller(1, 30)].kind,
+            MidiEffectKind::Eq(0, EqParam::Gain)
+        );
+        assert!(!lookup.contains_key(&MidiKey::nrpn(1, 5)));
+    }
+
+    #[test]
+    fn profile_without_driver() {
+        let path = Path::new("config.toml");
+        let config = r#"
+            [channels.a]
+            [[controller]]
+            name = "nano"
+            profile = "profiles/nanokontrol2.toml"
+        "#;
+        let config = Config::from_raw(config.as_bytes(), path).unwrap();
+        let controller = &config.controllers[0];
+        assert!(controller.device.is_some());
+        // the LaunchControl XL layout would read the nanoKONTROL2 knobs as its faders.
+        assert_eq!(controller.driver, DriverProfile::None);
+    }
+
+    #[test]
+    fn takeover_pickup() {
+        // far away with no history: ignore
+        assert_eq!(Takeover::Pickup.apply(0.5, None, 0.1), None);
+        // moving towards the parameter without reaching it: ignore
+        assert_eq!(Takeover::Pickup.apply(0.5, Some(0.1), 0.2), None);
+        // crossing the parameter: pick up
+        assert_eq!(Takeover::Pickup.apply(0.5, Some(0.4), 0.6), Some(0.6));
+        // already in sync
+        assert_eq!(Takeover::Pickup.apply(0.5, Some(0.5), 0.45), Some(0.45));
+    }
+
+    #[test]
+    fn takeover_scale() {
+        assert_eq!(Takeover::Scale.apply(0.5, None, 0.1), None);
+        assert_eq!(Takeover::Scale.apply(0.5, Some(0.5), 0.5), None);
+        // the parameter reaches the ends of its range with the control.
+        assert_eq!(Takeover::Scale.apply(0.5, Some(0.0), 1.0), Some(1.0));
+        assert_eq!(Takeover::Scale.apply(0.5, Some(1.0), 0.0), Some(0.0));
+        assert_eq!(Takeover::Scale.apply(0.5, Some(0.0), 0.5), Some(0.75));
+    }
+
+    #[test]
+    fn button() {
+        for (button, on, pressed, expected) in vec![
+            (Button::Toggle, false, true, Some(true)),
+            (Button::Toggle, true, true, Some(false)),
+            (Button::Toggle, true, false, None),
+            (Button::Momentary, false, true, Some(true)),
+            (Button::Momentary, true, false, Some(false)),
+            (Button::Trigger, true, true, Some(true)),
+            (Button::Trigger, true, false, None),
+        ] {
+            assert_eq!(button.apply(on, pressed), expected);
+        }
+    }
+
+    #[test]
+    fn relative_delta() {
+        use RelativeFormat::*;
+        for (format, value, expected) in vec![
+            (TwosComplement, 0x01, 1),
+            (TwosComplement, 0x7f, -1),
+            (TwosComplement, 0x40, -64),
+            (Offset, 0x41, 1),
+            (Offset, 0x3f, -1),
+            (SignMagnitude, 0x01, 1),
+            (SignMagnitude, 0x41, -1),
+        ] {
+            assert_eq!(format.delta(value, 0x7f), expected);
+        }
+    }
+
+    #[test]
+    fn relative_acceleration() {
+        let relative = Relative {
+            format: RelativeFormat::TwosComplement,
+            step: 0.125,
+            acceleration: 0.5,
+        };
+        assert_eq!(relative.apply(0.5, 0x01, 0x7f), 0.625);
+        // 2 steps count as 3
+        assert_eq!(relative.apply(0.5, 0x7e, 0x7f), 0.125);
+        // clamped
+        assert_eq!(relative.apply(0.5, 0x04, 0x7f), 1.0);
+    }
+}
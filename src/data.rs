@@ -1,21 +1,37 @@
 use crate::{
     cli::{Config, MidiKey, MidiLookup},
+    metering::{Loudness, TruePeak},
     Result,
 };
 use crossbeam_channel::Sender;
 use druid::{Data, Lens};
 use im::{vector, Vector};
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
-#[derive(Debug, Clone, Copy, PartialEq, Data, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Data, Default, Serialize)]
 pub struct Metering {
     pub max_in: f64,
     pub rms_in: f64,
     pub max_out: f64,
     pub rms_out: f64,
+    pub true_peak_in: TruePeak,
+    pub true_peak_out: TruePeak,
+    /// The loudness of the output.
+    pub loudness: Loudness,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Data)]
+impl Metering {
+    /// Forget the readings that are kept until the meters are reset.
+    pub fn reset(&mut self) {
+        self.true_peak_in = TruePeak::default();
+        self.true_peak_out = TruePeak::default();
+        self.loudness = Loudness::default();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChannelMode {
     Normal,
     Bypass,
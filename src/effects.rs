@@ -5,8 +5,7 @@
 //! the work) we don't need to bounds-check.
 //!
 //! TODO look at choosing the size at compile-time using const generics.
-use crate::{gui::UiMsg, monitor_data::MonitorData};
-use crossbeam_channel as channel;
+use crate::monitor_data::{Monitor, MonitorData};
 use dasp::ring_buffer::{Bounded, Slice, SliceMut};
 use fftw::{
     array::AlignedVec,
@@ -16,7 +15,13 @@ use fftw::{
 use itertools::izip;
 use std::{f32::consts::PI, fmt};
 
-pub type MonitorSpectrum = MonitorData<Box<[f32]>>;
+mod biquad;
+mod eq;
+
+pub use biquad::{Biquad, Coefficients, FilterKind};
+pub use eq::{response as eq_response, Band, EqParam, Equalizer, EQ_BANDS};
+
+pub type MonitorSpectrum = Monitor<Box<[f32]>>;
 
 /// Currently hard-coded for f32. Could be made generic.
 pub trait Effect {
@@ -408,14 +413,12 @@ pub struct SpectralEngine {
     ifft_plan: R2RPlan32,
     // low pass filter
     lpf: Vec<f32>,
-    // message channel
-    tx: channel::Sender<UiMsg>,
     audio_in_spectrum: MonitorData<Box<[f32]>>,
     audio_out_spectrum: MonitorData<Box<[f32]>>,
 }
 
 impl SpectralEngine {
-    pub fn new(sample_rate: f32, fft_length: usize, tx: channel::Sender<UiMsg>) -> Self {
+    pub fn new(sample_rate: f32, fft_length: usize) -> Self {
         let mut windowed_input = AlignedVec::new(fft_length);
         let mut signal_fft = AlignedVec::new(fft_length);
         let mut fft_plan: R2RPlan32 = R2RPlan::new(
@@ -439,7 +442,6 @@ impl SpectralEngine {
         low_pass_filter(800., sample_rate, &mut *windowed_input);
         fft_plan.r2r(&mut windowed_input, &mut signal_fft).unwrap();
         let lpf: Vec<_> = signal_fft.iter().copied().collect();
-        tx.send(UiMsg::LowPassSpectrum(hc_to_mod(&lpf))).unwrap();
         Self {
             oversample: 4,
             in_gain: 1.0,
@@ -452,7 +454,6 @@ impl SpectralEngine {
             fft_plan,
             ifft_plan,
             lpf,
-            tx,
             audio_in_spectrum: MonitorData::new(vec![0.; fft_length].into_boxed_slice()),
             audio_out_spectrum: MonitorData::new(vec![0.; fft_length].into_boxed_slice()),
         }
@@ -462,6 +463,11 @@ impl SpectralEngine {
         self.fft_length / self.oversample
     }
 
+    /// The modulus of the spectrum of the low pass filter, for showing in the ui.
+    pub fn low_pass_spectrum(&self) -> Vec<f32> {
+        hc_to_mod(&self.lpf)
+    }
+
     pub fn latency(&self) -> usize {
         self.fft_length - self.step_size()
     }
@@ -469,17 +475,13 @@ impl SpectralEngine {
     /// Get handles on (`audio_in_spectrum`, `audio_out_spectrum`)
     pub fn monitor_spectra(&self) -> (MonitorSpectrum, MonitorSpectrum) {
         (
-            self.audio_in_spectrum.clone(),
-            self.audio_out_spectrum.clone(),
+            self.audio_in_spectrum.monitor(),
+            self.audio_out_spectrum.monitor(),
         )
     }
 
-    pub fn process<S>(
-        &mut self,
-        input_rb: &mut Bounded<S>,
-        output_rb: &mut Bounded<S>,
-        report_spectra: bool,
-    ) where
+    pub fn process<S>(&mut self, input_rb: &mut Bounded<S>, output_rb: &mut Bounded<S>)
+    where
         S: Slice<Element = f32> + SliceMut,
     {
         let latency = self.latency();
@@ -499,19 +501,18 @@ impl SpectralEngine {
                 .r2r(&mut self.windowed_input, &mut self.signal_fft)
                 .unwrap();
 
-            if report_spectra {
-                self.audio_in_spectrum
-                    .update(|data| data.copy_from_slice(&*self.signal_fft));
-            }
+            // this only copies the spectrum for the readers we have, which are only claimed while
+            // a window shows it.
+            let signal_fft = &self.signal_fft;
+            self.audio_in_spectrum
+                .update(|data| data.copy_from_slice(&*signal_fft));
             // TODO process
             //hc_multiply(&self.lpf, &mut self.signal_fft);
             //println!("{:?}", &*self.lpf);
 
-            if report_spectra {
-                self.tx
-                    .send(UiMsg::AudioOutSpectrum(hc_to_mod(&self.signal_fft)))
-                    .unwrap();
-            }
+            let signal_fft = &self.signal_fft;
+            self.audio_out_spectrum
+                .update(|data| data.copy_from_slice(&*signal_fft));
             self.ifft_plan
                 .r2r(&mut self.signal_fft, &mut self.windowed_input)
                 .unwrap();
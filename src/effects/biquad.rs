@@ -0,0 +1,254 @@
+//! Second order IIR filters ("biquads"), with the coefficients from Robert Bristow-Johnson's Audio
+//! EQ Cookbook.
+use druid::Data;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+
+/// The shapes of filter in the cookbook.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Data, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    /// Boosts or cuts around the frequency, over a width set by Q.
+    Peaking,
+    /// Boosts or cuts everything below the frequency.
+    LowShelf,
+    /// Boosts or cuts everything above the frequency.
+    HighShelf,
+    LowPass,
+    HighPass,
+    /// Cuts a narrow band around the frequency.
+    Notch,
+    /// Leaves the level alone, but shifts the phase around the frequency.
+    AllPass,
+}
+
+impl FilterKind {
+    pub const ALL: [FilterKind; 7] = [
+        FilterKind::Peaking,
+        FilterKind::LowShelf,
+        FilterKind::HighShelf,
+        FilterKind::LowPass,
+        FilterKind::HighPass,
+        FilterKind::Notch,
+        FilterKind::AllPass,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Peaking => "peaking",
+            FilterKind::LowShelf => "low_shelf",
+            FilterKind::HighShelf => "high_shelf",
+            FilterKind::LowPass => "low_pass",
+            FilterKind::HighPass => "high_pass",
+            FilterKind::Notch => "notch",
+            FilterKind::AllPass => "all_pass",
+        }
+    }
+
+    /// Whether the gain does anything for this kind of filter.
+    pub fn has_gain(self) -> bool {
+        matches!(
+            self,
+            FilterKind::Peaking | FilterKind::LowShelf | FilterKind::HighShelf
+        )
+    }
+}
+
+/// The coefficients of a biquad, normalized so `a0` is 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Coefficients {
+    pub b: [f64; 3],
+    /// `a1` and `a2`.
+    pub a: [f64; 2],
+}
+
+impl Coefficients {
+    /// A filter that passes the signal through untouched.
+    pub const PASSTHRU: Coefficients = Coefficients {
+        b: [1.0, 0.0, 0.0],
+        a: [0.0, 0.0],
+    };
+
+    /// Normalize coefficients with any `a0`.
+    pub fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Coefficients {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [a[1] / a[0], a[2] / a[0]],
+        }
+    }
+
+    /// A cookbook filter at `freq` Hz. `gain` (in dB) is only used by the peaking and shelving
+    /// filters. For the shelves, `q` sets the slope (0.707 is as steep as it gets without a bump).
+    pub fn rbj(kind: FilterKind, freq: f64, gain: f64, q: f64, sample_rate: f64) -> Self {
+        let big_a = 10f64.powf(gain / 40.0);
+        // keep below nyquist, where the formulas fall apart.
+        let w0 = 2.0 * PI * freq.min(0.49 * sample_rate) / sample_rate;
+        let (sin, cos) = w0.sin_cos();
+        let alpha = sin / (2.0 * q);
+        match kind {
+            FilterKind::Peaking => Coefficients::new(
+                [1.0 + alpha * big_a, -2.0 * cos, 1.0 - alpha * big_a],
+                [1.0 + alpha / big_a, -2.0 * cos, 1.0 - alpha / big_a],
+            ),
+            FilterKind::LowShelf => {
+                let sqrt = 2.0 * big_a.sqrt() * alpha;
+                Coefficients::new(
+                    [
+                        big_a * ((big_a + 1.0) - (big_a - 1.0) * cos + sqrt),
+                        2.0 * big_a * ((big_a - 1.0) - (big_a + 1.0) * cos),
+                        big_a * ((big_a + 1.0) - (big_a - 1.0) * cos - sqrt),
+                    ],
+                    [
+                        (big_a + 1.0) + (big_a - 1.0) * cos + sqrt,
+                        -2.0 * ((big_a - 1.0) + (big_a + 1.0) * cos),
+                        (big_a + 1.0) + (big_a - 1.0) * cos - sqrt,
+                    ],
+                )
+            }
+            FilterKind::HighShelf => {
+                let sqrt = 2.0 * big_a.sqrt() * alpha;
+                Coefficients::new(
+                    [
+                        big_a * ((big_a + 1.0) + (big_a - 1.0) * cos + sqrt),
+                        -2.0 * big_a * ((big_a - 1.0) + (big_a + 1.0) * cos),
+                        big_a * ((big_a + 1.0) + (big_a - 1.0) * cos - sqrt),
+                    ],
+                    [
+                        (big_a + 1.0) - (big_a - 1.0) * cos + sqrt,
+                        2.0 * ((big_a - 1.0) - (big_a + 1.0) * cos),
+                        (big_a + 1.0) - (big_a - 1.0) * cos - sqrt,
+                    ],
+                )
+            }
+            FilterKind::LowPass => Coefficients::new(
+                [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::HighPass => Coefficients::new(
+                [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::Notch => Coefficients::new(
+                [1.0, -2.0 * cos, 1.0],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+            FilterKind::AllPass => Coefficients::new(
+                [1.0 - alpha, -2.0 * cos, 1.0 + alpha],
+                [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+            ),
+        }
+    }
+
+    /// How much the filter changes the level at `freq` Hz, in dB.
+    pub fn response(&self, freq: f64, sample_rate: f64) -> f64 {
+        // evaluate at z = e^(iw), as (re, im) pairs.
+        let w = 2.0 * PI * freq / sample_rate;
+        let eval = |c: [f64; 3]| {
+            (
+                c[0] + c[1] * w.cos() + c[2] * (2.0 * w).cos(),
+                -c[1] * w.sin() - c[2] * (2.0 * w).sin(),
+            )
+        };
+        let (num_re, num_im) = eval(self.b);
+        let (den_re, den_im) = eval([1.0, self.a[0], self.a[1]]);
+        let power = (num_re * num_re + num_im * num_im) / (den_re * den_re + den_im * den_im);
+        10.0 * power.max(1e-20).log10()
+    }
+}
+
+/// A biquad filter, in transposed direct form II.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Biquad {
+    coefficients: Coefficients,
+    z: [f64; 2],
+}
+
+impl Biquad {
+    pub fn new(coefficients: Coefficients) -> Self {
+        Biquad {
+            coefficients,
+            z: [0.0; 2],
+        }
+    }
+
+    /// Change the filter, keeping its state so there is no click.
+    pub fn set(&mut self, coefficients: Coefficients) {
+        self.coefficients = coefficients;
+    }
+
+    pub fn coefficients(&self) -> &Coefficients {
+        &self.coefficients
+    }
+
+    pub fn process(&mut self, input: f64) -> f64 {
+        let Coefficients { b, a } = self.coefficients;
+        let output = b[0] * input + self.z[0];
+        self.z[0] = b[1] * input - a[0] * output + self.z[1];
+        self.z[1] = b[2] * input - a[1] * output;
+        output
+    }
+
+    pub fn reset(&mut self) {
+        self.z = [0.0; 2];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Biquad, Coefficients, FilterKind};
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    fn response(kind: FilterKind, gain: f64, freq: f64) -> f64 {
+        Coefficients::rbj(kind, 1000.0, gain, 0.707, SAMPLE_RATE).response(freq, SAMPLE_RATE)
+    }
+
+    fn close(value: f64, expected: f64, tolerance: f64) -> bool {
+        (value - expected).abs() < tolerance
+    }
+
+    #[test]
+    fn responses() {
+        use FilterKind::*;
+        // at the frequency.
+        assert!(close(response(Peaking, 6.0, 1000.0), 6.0, 1e-6));
+        assert!(close(response(LowShelf, 6.0, 1000.0), 3.0, 1e-6));
+        assert!(close(response(HighShelf, -6.0, 1000.0), -3.0, 1e-6));
+        assert!(close(response(LowPass, 0.0, 1000.0), -3.0, 0.1));
+        assert!(close(response(HighPass, 0.0, 1000.0), -3.0, 0.1));
+        assert!(response(Notch, 0.0, 1000.0) < -60.0);
+        // far away.
+        assert!(close(response(Peaking, 6.0, 20.0), 0.0, 0.1));
+        assert!(close(response(LowShelf, 6.0, 20.0), 6.0, 0.1));
+        assert!(close(response(LowShelf, 6.0, 20000.0), 0.0, 0.1));
+        assert!(close(response(HighShelf, -6.0, 20000.0), -6.0, 0.1));
+        assert!(close(response(LowPass, 0.0, 100.0), 0.0, 0.1));
+        assert!(close(response(LowPass, 0.0, 10000.0), -42.7, 0.1));
+        assert!(close(response(HighPass, 0.0, 100.0), -40.0, 0.5));
+        assert!(close(response(Notch, 0.0, 100.0), 0.0, 0.1));
+        for freq in &[20.0, 1000.0, 20000.0] {
+            assert!(close(response(AllPass, 0.0, *freq), 0.0, 1e-6));
+        }
+    }
+
+    #[test]
+    fn filtering() {
+        // a 1 kHz sine through a 6 dB peak comes out 6 dB louder.
+        let coefficients = Coefficients::rbj(FilterKind::Peaking, 1000.0, 6.0, 1.0, SAMPLE_RATE);
+        let mut filter = Biquad::new(coefficients);
+        let mut peak = 0f64;
+        for idx in 0..48000 {
+            let output = filter.process((2.0 * PI * 1000.0 * idx as f64 / SAMPLE_RATE).sin());
+            if idx > 4800 {
+                peak = peak.max(output.abs());
+            }
+        }
+        assert!(close(20.0 * peak.log10(), 6.0, 0.01), "{}", peak);
+
+        let mut filter = Biquad::new(Coefficients::PASSTHRU);
+        assert_eq!(filter.process(0.5), 0.5);
+        filter.set(coefficients);
+        assert_eq!(filter.coefficients(), &coefficients);
+    }
+}
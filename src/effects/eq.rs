@@ -0,0 +1,268 @@
+//! A parametric EQ: a few biquad bands in series, each with its own shape, frequency, gain and Q.
+use super::biquad::{Biquad, Coefficients, FilterKind};
+use druid::{Data, Lens};
+use itertools::izip;
+use serde::{Deserialize, Serialize};
+
+/// The number of bands in each channel's EQ.
+pub const EQ_BANDS: usize = 4;
+pub const MIN_FREQ: f64 = 20.0;
+pub const MAX_FREQ: f64 = 20_000.0;
+/// The most a band can boost or cut, in dB.
+pub const MAX_GAIN: f64 = 18.0;
+pub const MIN_Q: f64 = 0.1;
+pub const MAX_Q: f64 = 10.0;
+
+/// The settings of one band of the EQ.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Lens, Serialize, Deserialize)]
+pub struct Band {
+    pub kind: FilterKind,
+    /// In Hz.
+    pub freq: f64,
+    /// In dB.
+    pub gain: f64,
+    pub q: f64,
+    pub on: bool,
+}
+
+impl Band {
+    const fn new(kind: FilterKind, freq: f64, q: f64) -> Self {
+        Band {
+            kind,
+            freq,
+            gain: 0.0,
+            q,
+            on: true,
+        }
+    }
+
+    /// The bands every channel starts with: a low shelf, two peaks and a high shelf, all flat.
+    /// The low, mid and high knobs of the old 3-band EQ are the gains of bands 0, 1 and 3.
+    pub const DEFAULTS: [Band; EQ_BANDS] = [
+        Band::new(FilterKind::LowShelf, 100.0, 0.707),
+        Band::new(FilterKind::Peaking, 500.0, 1.0),
+        Band::new(FilterKind::Peaking, 3000.0, 1.0),
+        Band::new(FilterKind::HighShelf, 8000.0, 0.707),
+    ];
+
+    /// The band with every parameter in range. Parameters that aren't numbers at all are put
+    /// back to those of band `idx` of `DEFAULTS`, as a NaN would silence the channel for good.
+    pub fn clamped(self, idx: usize) -> Self {
+        let default = Band::DEFAULTS[idx];
+        let finite = |value: f64, default: f64| {
+            if value.is_finite() {
+                value
+            } else {
+                default
+            }
+        };
+        Band {
+            freq: finite(self.freq, default.freq).clamp(MIN_FREQ, MAX_FREQ),
+            gain: finite(self.gain, default.gain).clamp(-MAX_GAIN, MAX_GAIN),
+            q: finite(self.q, default.q).clamp(MIN_Q, MAX_Q),
+            ..self
+        }
+    }
+
+    /// The filter for this band, which does nothing if the band is off.
+    pub fn coefficients(&self, sample_rate: f64) -> Coefficients {
+        if self.on {
+            Coefficients::rbj(self.kind, self.freq, self.gain, self.q, sample_rate)
+        } else {
+            Coefficients::PASSTHRU
+        }
+    }
+}
+
+/// The response of the bands together at `freq` Hz, in dB.
+pub fn response(bands: &[Band], freq: f64, sample_rate: f64) -> f64 {
+    bands
+        .iter()
+        .filter(|band| band.on)
+        .map(|band| band.coefficients(sample_rate).response(freq, sample_rate))
+        .sum()
+}
+
+/// The continuous parameters of a band. Controllers and sliders move them by position, from 0 to
+/// 1: the frequency and Q on a log scale, and the gain on a linear one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EqParam {
+    Freq,
+    Gain,
+    Q,
+}
+
+impl EqParam {
+    pub fn position(self, band: &Band) -> f64 {
+        match self {
+            EqParam::Freq => log_position(band.freq, MIN_FREQ, MAX_FREQ),
+            EqParam::Gain => (band.gain + MAX_GAIN) / (2.0 * MAX_GAIN),
+            EqParam::Q => log_position(band.q, MIN_Q, MAX_Q),
+        }
+    }
+
+    pub fn with_position(self, band: Band, position: f64) -> Band {
+        let position = position.clamp(0.0, 1.0);
+        match self {
+            EqParam::Freq => Band {
+                freq: from_log_position(position, MIN_FREQ, MAX_FREQ),
+                ..band
+            },
+            EqParam::Gain => Band {
+                gain: (2.0 * position - 1.0) * MAX_GAIN,
+                ..band
+            },
+            EqParam::Q => Band {
+                q: from_log_position(position, MIN_Q, MAX_Q),
+                ..band
+            },
+        }
+    }
+}
+
+fn log_position(value: f64, min: f64, max: f64) -> f64 {
+    ((value / min).ln() / (max / min).ln()).clamp(0.0, 1.0)
+}
+
+fn from_log_position(position: f64, min: f64, max: f64) -> f64 {
+    min * (max / min).powf(position)
+}
+
+/// The EQ of a channel. It doesn't allocate once made, so it can be used in the RT thread.
+#[derive(Debug, Clone)]
+pub struct Equalizer {
+    sample_rate: f64,
+    bands: [Band; EQ_BANDS],
+    filters: [Biquad; EQ_BANDS],
+}
+
+impl Equalizer {
+    pub fn new(sample_rate: f64, bands: &[Band; EQ_BANDS]) -> Self {
+        let mut filters = [Biquad::new(Coefficients::PASSTHRU); EQ_BANDS];
+        for (filter, band) in filters.iter_mut().zip(bands.iter()) {
+            filter.set(band.coefficients(sample_rate));
+        }
+        Equalizer {
+            sample_rate,
+            bands: *bands,
+            filters,
+        }
+    }
+
+    /// Change the settings, only working out the filters of the bands that changed.
+    pub fn set_bands(&mut self, bands: &[Band; EQ_BANDS]) {
+        for (filter, old, new) in izip!(&mut self.filters, &mut self.bands, bands) {
+            if old != new {
+                if !old.on {
+                    // don't start from whatever was left when the band was turned off.
+                    filter.reset();
+                }
+                filter.set(new.coefficients(self.sample_rate));
+                *old = *new;
+            }
+        }
+    }
+
+    /// Filter `samples` in place.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        for (filter, band) in self.filters.iter_mut().zip(self.bands.iter()) {
+            if !band.on {
+                continue;
+            }
+            for sample in samples.iter_mut() {
+                *sample = filter.process(*sample as f64) as f32;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{response, Band, EqParam, Equalizer, MAX_FREQ, MIN_FREQ};
+    use crate::effects::FilterKind;
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// The peak level of a sine at `freq` Hz through `eq`, in dB.
+    fn sine_through(eq: &mut Equalizer, freq: f64) -> f64 {
+        let mut samples: Vec<f32> = (0..48000)
+            .map(|idx| (0.5 * (2.0 * PI * freq * idx as f64 / SAMPLE_RATE).sin()) as f32)
+            .collect();
+        for chunk in samples.chunks_mut(256) {
+            eq.process(chunk);
+        }
+        let peak = samples[4800..]
+            .iter()
+            .fold(0f32, |peak, sample| peak.max(sample.abs()));
+        20.0 * (2.0 * peak as f64).log10()
+    }
+
+    #[test]
+    fn flat_by_default() {
+        let mut eq = Equalizer::new(SAMPLE_RATE, &Band::DEFAULTS);
+        for freq in &[50.0, 1000.0, 10000.0] {
+            assert!(response(&Band::DEFAULTS, *freq, SAMPLE_RATE).abs() < 1e-6);
+            assert!(sine_through(&mut eq, *freq).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn bands() {
+        let mut bands = Band::DEFAULTS;
+        bands[1].gain = 6.0;
+        bands[3].gain = -6.0;
+        let mut eq = Equalizer::new(SAMPLE_RATE, &Band::DEFAULTS);
+        eq.set_bands(&bands);
+        assert!((response(&bands, 500.0, SAMPLE_RATE) - 6.0).abs() < 0.1);
+        assert!((sine_through(&mut eq, 500.0) - 6.0).abs() < 0.1);
+        assert!((sine_through(&mut eq, 20000.0) + 6.0).abs() < 0.5);
+
+        // a band that is off does nothing.
+        bands[1].on = false;
+        eq.set_bands(&bands);
+        assert!(response(&bands, 500.0, SAMPLE_RATE).abs() < 0.1);
+        assert!(sine_through(&mut eq, 500.0).abs() < 0.1);
+
+        bands[0].kind = FilterKind::HighPass;
+        bands[0].freq = 1000.0;
+        eq.set_bands(&bands);
+        assert!(sine_through(&mut eq, 100.0) < -35.0);
+    }
+
+    #[test]
+    fn positions() {
+        let band = Band::DEFAULTS[2];
+        for param in &[EqParam::Freq, EqParam::Gain, EqParam::Q] {
+            for position in &[0.0, 0.25, 0.5, 1.0] {
+                let moved = param.with_position(band, *position);
+                assert!((param.position(&moved) - position).abs() < 1e-9);
+            }
+        }
+        assert_eq!(EqParam::Freq.with_position(band, 0.0).freq, MIN_FREQ);
+        assert!((EqParam::Freq.with_position(band, 1.0).freq - MAX_FREQ).abs() < 1e-6);
+        assert_eq!(EqParam::Gain.with_position(band, 0.5).gain, 0.0);
+        let wild = Band {
+            freq: 1e6,
+            gain: -100.0,
+            q: 0.0,
+            ..band
+        };
+        assert_eq!(
+            wild.clamped(2),
+            Band {
+                freq: MAX_FREQ,
+                gain: -18.0,
+                q: 0.1,
+                ..band
+            }
+        );
+        let nan = Band {
+            freq: f64::NAN,
+            gain: f64::INFINITY,
+            q: f64::NAN,
+            ..wild
+        };
+        assert_eq!(nan.clamped(2), band);
+    }
+}
@@ -2,31 +2,57 @@ use crate::{
     audio::{AudioMsg, AudioMsgKind},
     cli::Config,
     data::{ChannelMode, Metering},
-    gui::widgets::{Fader, FaderData, Knob, LightRadio, Syncer},
+    effects::{Band, EqParam, FilterKind, EQ_BANDS},
+    gui::{
+        analyzer::{Analysis, Averaging, Settings as AnalyzerSettings, Smoothing},
+        ballistics::{Settings as MeterSettings, Standard},
+        spectrogram::{ColorMap, Settings as SpectrogramSettings},
+        widgets::{
+            Correlation, EqResponse, Fader, FaderData, Goniometer, Knob, LightRadio, Spectra,
+            Spectrogram, Swatch, Syncer,
+        },
+    },
+    metering::StereoScope,
     Result,
 };
 use crossbeam_channel as channel;
 use druid::{
     lens::{Constant, Map as LensMap},
-    widget::{prelude::*, Flex, Label, List, MainAxisAlignment, Radio, Scroll, Switch},
+    widget::{
+        prelude::*, Button, Checkbox, CrossAxisAlignment, EnvScope, Flex, Label, List,
+        MainAxisAlignment, Radio, RadioGroup, Scroll, Slider, Stepper, Switch,
+    },
     AppDelegate, AppLauncher, ArcStr, Color, Command, Data, DelegateCtx, ExtEventSink, Handled,
     Lens, LensExt, LocalizedString, MenuDesc, MenuItem, Selector, Target, Widget, WidgetExt,
-    WindowDesc,
+    WindowDesc, WindowId,
 };
 use druid_graphs::{LineChart, LineChartData, LineChartDataLensBuilder, Range};
 use im::{vector, Vector};
 use itertools::izip;
 use std::{
-    sync::Arc,
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
     thread::{self, JoinHandle},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 const PADDING: f64 = 20.0;
+/// Room for the loudness readouts under the faders, and the reset button.
+const READOUT_HEIGHT: f64 = 115.0;
+const READOUT_TEXT_SIZE: f64 = 10.0;
+/// How often the spectrum windows are updated.
+pub const SPECTRA_FRAME: Duration = Duration::from_millis(1000 / 30);
 pub const UPDATE: Selector<UiMsg> = Selector::new("mixjack.update");
 const SHOW_LOW_PASS: Selector<()> = Selector::new("mixjack.show-low-pass");
 const SHOW_INPUT_SPECTRUM: Selector<()> = Selector::new("mixjack.show-input-spectrum");
 const SHOW_OUTPUT_SPECTRUM: Selector<()> = Selector::new("mixjack.show-output-spectrum");
+const SHOW_SPECTROGRAM: Selector<()> = Selector::new("mixjack.show-spectrogram");
+const SHOW_METERS: Selector<()> = Selector::new("mixjack.show-meters");
+const SHOW_STEREO: Selector<()> = Selector::new("mixjack.show-stereo");
+const SHOW_EQ: Selector<()> = Selector::new("mixjack.show-eq");
 
 const APP_TITLE: LocalizedString<State> = LocalizedString::new("app-title");
 const SPECTRA_MENU: LocalizedString<State> = LocalizedString::new("mixjack.spectra-menu");
@@ -36,7 +62,15 @@ const INPUT_SPECTRUM_MENU_ITEM: LocalizedString<State> =
     LocalizedString::new("mixjack.input-spectrum-menu-item");
 const OUTPUT_SPECTRUM_MENU_ITEM: LocalizedString<State> =
     LocalizedString::new("mixjack.output-spectrum-menu-item");
+const SPECTROGRAM_MENU_ITEM: LocalizedString<State> =
+    LocalizedString::new("mixjack.spectrogram-menu-item");
+const METERS_MENU_ITEM: LocalizedString<State> = LocalizedString::new("mixjack.meters-menu-item");
+const STEREO_MENU_ITEM: LocalizedString<State> = LocalizedString::new("mixjack.stereo-menu-item");
+const EQ_MENU_ITEM: LocalizedString<State> = LocalizedString::new("mixjack.eq-menu-item");
 
+mod analyzer;
+mod ballistics;
+mod spectrogram;
 mod widgets;
 
 #[derive(Debug, Data, Clone, Lens, PartialEq)]
@@ -45,28 +79,58 @@ pub struct State {
     /// `(r0, r1, .. rn/2, i(n+1)/2-1 .., i1)`.
     // TODO use Arc<Vec> because we don't do random changes.
     low_pass_spectrum: Vector<f64>,
-    audio_in_spectrum: Vector<f64>,
-    audio_out_spectrum: Vector<f64>,
     channels: Vector<ChannelState>,
+    spectra: Vector<SpectrumState>,
+    stereo: Vector<StereoState>,
+    analyzer: AnalyzerSettings,
+    spectrogram: SpectrogramSettings,
+    meters: MeterSettings,
+    /// The channel shown in the EQ window.
+    eq_channel: usize,
+    fft_size: usize,
+    /// The width of a frequency bin, in Hz.
+    bin_width: f64,
+    /// Bumped by the reset button, so `sync_audio` knows to reset the meters.
+    meter_resets: u64,
+    /// Bumped when the RT thread tells us about a change made somewhere else (by a controller or
+    /// a remote server), so `sync_audio` doesn't send it straight back.
+    audio_changes: u64,
 }
 
 impl State {
-    pub fn new(config: &Config) -> Self {
+    pub fn new(config: &Config, sample_rate: usize) -> Self {
         let mut channels = Vector::new();
-        for (name, channel) in config.channels.iter() {
+        let mut spectra = Vector::new();
+        for (idx, (name, channel)) in config.channels.iter().enumerate() {
+            let name = Arc::new(name.to_owned());
             channels.push_back(ChannelState {
-                name: Arc::new(name.to_owned()),
+                name: name.clone(),
                 gain: 0.0,
                 metering_on: false,
                 metering: Metering::default(),
                 mode: ChannelMode::default(),
+                eq: Band::DEFAULTS.iter().copied().collect(),
             });
+            spectra.push_back(SpectrumState::new(name, idx));
         }
+        let stereo = config
+            .stereo
+            .iter()
+            .map(|(left, right)| StereoState::new(format!("{} / {}", left, right)))
+            .collect();
         State {
             low_pass_spectrum: vector![],
-            audio_in_spectrum: vector![],
-            audio_out_spectrum: vector![],
             channels,
+            spectra,
+            stereo,
+            analyzer: AnalyzerSettings::default(),
+            spectrogram: SpectrogramSettings::default(),
+            meters: MeterSettings::default(),
+            eq_channel: 0,
+            fft_size: config.fft_size,
+            bin_width: sample_rate as f64 / config.fft_size as f64,
+            meter_resets: 0,
+            audio_changes: 0,
         }
     }
 
@@ -80,6 +144,24 @@ impl State {
                 level: Level::Gain(gain),
             } => {
                 self.channels[*channel].gain = *gain;
+                self.audio_changes += 1;
+            }
+            UiMsg::Mode { channel, mode } => {
+                self.channels[*channel].mode = *mode;
+                self.audio_changes += 1;
+            }
+            UiMsg::MetersReset { channel } => {
+                self.channels[*channel].metering.reset();
+            }
+            UiMsg::Eq {
+                channel,
+                band,
+                settings,
+            } => {
+                if let Some(band) = self.channels[*channel].eq.get_mut(*band) {
+                    *band = *settings;
+                }
+                self.audio_changes += 1;
             }
             UiMsg::ToggleMetering { channel } => {
                 let mut metering_on = &mut self.channels[*channel].metering_on;
@@ -88,17 +170,47 @@ impl State {
             UiMsg::LowPassSpectrum(mod_spectrum) => {
                 self.low_pass_spectrum = mod_spectrum.iter().map(|v| *v as f64).collect();
             }
-            UiMsg::AudioInSpectrum(mod_spectrum) => {
-                self.audio_in_spectrum = mod_spectrum.iter().map(|v| *v as f64).collect();
+            UiMsg::Stereo(idx, scope) => {
+                if let Some(stereo) = self.stereo.get_mut(*idx) {
+                    stereo.correlation = scope.correlation as f64;
+                    stereo.points = scope.points.iter().copied().collect();
+                }
             }
-            UiMsg::AudioOutSpectrum(mod_spectrum) => {
-                self.audio_out_spectrum = mod_spectrum.iter().map(|v| *v as f64).collect();
+            UiMsg::Spectrum(channel, tap, spectrum) => {
+                let settings = self.analyzer;
+                self.spectra[*channel].analysis_mut(*tap).update(
+                    spectrum,
+                    self.fft_size,
+                    &settings,
+                    SPECTRA_FRAME.as_secs_f64(),
+                );
             }
         }
     }
 
+    fn reset_peaks(&mut self) {
+        for spectrum in self.spectra.iter_mut() {
+            spectrum.audio_in.reset_peaks();
+            spectrum.audio_out.reset_peaks();
+        }
+    }
+
     /// Send the required messages to audio to sync its parameters with the ui.
+    ///
+    /// Changes that came from the RT thread aren't sent back to it: by the time they got there,
+    /// they could undo a newer change.
     fn sync_audio(&self, prev: &Self, tx: &channel::Sender<AudioMsg>) -> Result<()> {
+        if self.meter_resets != prev.meter_resets {
+            for idx in 0..self.channels.len() {
+                tx.send(AudioMsg {
+                    channel: idx,
+                    kind: AudioMsgKind::ResetMeters,
+                })?;
+            }
+        }
+        if self.audio_changes != prev.audio_changes {
+            return Ok(());
+        }
         for (idx, (next, prev)) in izip!(self.channels.iter(), prev.channels.iter()).enumerate() {
             if next.gain != prev.gain {
                 tx.send(AudioMsg {
@@ -112,6 +224,14 @@ impl State {
                     kind: AudioMsgKind::Mode(next.mode),
                 })?;
             }
+            for (band, (next, prev)) in izip!(next.eq.iter(), prev.eq.iter()).enumerate() {
+                if next != prev {
+                    tx.send(AudioMsg {
+                        channel: idx,
+                        kind: AudioMsgKind::Eq(band, *next),
+                    })?;
+                }
+            }
         }
         Ok(())
     }
@@ -124,6 +244,7 @@ pub struct ChannelState {
     metering_on: bool,
     metering: Metering,
     mode: ChannelMode,
+    eq: Vector<Band>,
 }
 
 impl Data for ChannelState {
@@ -133,17 +254,124 @@ impl Data for ChannelState {
             && Data::same(&self.metering_on, &other.metering_on)
             && (Data::same(&self.metering, &other.metering) || !self.metering_on)
             && Data::same(&self.mode, &other.mode)
+            && Data::same(&self.eq, &other.eq)
+    }
+}
+
+/// The spectra of a channel, and whether to show them.
+#[derive(Debug, Data, Clone, Lens, PartialEq)]
+pub struct SpectrumState {
+    name: Arc<String>,
+    /// The hue of the line for this channel.
+    hue: f64,
+    show_in: bool,
+    show_out: bool,
+    audio_in: Analysis,
+    audio_out: Analysis,
+}
+
+impl SpectrumState {
+    fn new(name: Arc<String>, idx: usize) -> Self {
+        SpectrumState {
+            name,
+            // the golden angle keeps neighbouring channels' colours apart, however many there are.
+            hue: (idx as f64 * 137.5) % 360.0,
+            // show the first channel until the user picks others.
+            show_in: idx == 0,
+            show_out: idx == 0,
+            audio_in: Analysis::default(),
+            audio_out: Analysis::default(),
+        }
+    }
+
+    fn shown(&self, tap: Tap) -> bool {
+        match tap {
+            Tap::In => self.show_in,
+            Tap::Out => self.show_out,
+        }
+    }
+
+    fn set_shown(&mut self, tap: Tap, shown: bool) {
+        match tap {
+            Tap::In => self.show_in = shown,
+            Tap::Out => self.show_out = shown,
+        }
+    }
+
+    fn analysis(&self, tap: Tap) -> &Analysis {
+        match tap {
+            Tap::In => &self.audio_in,
+            Tap::Out => &self.audio_out,
+        }
+    }
+
+    fn analysis_mut(&mut self, tap: Tap) -> &mut Analysis {
+        match tap {
+            Tap::In => &mut self.audio_in,
+            Tap::Out => &mut self.audio_out,
+        }
     }
 }
 
+/// The latest readings of a stereo pair.
+#[derive(Debug, Data, Clone, Lens, PartialEq)]
+pub struct StereoState {
+    name: Arc<String>,
+    correlation: f64,
+    /// The points for the goniometer (see `StereoScope::points`).
+    points: Arc<[(f32, f32)]>,
+}
+
+impl StereoState {
+    fn new(name: String) -> Self {
+        StereoState {
+            name: Arc::new(name),
+            correlation: 0.0,
+            points: StereoScope::default().points.into(),
+        }
+    }
+}
+
+/// Where in a channel a spectrum is measured. `UiMsg::Spectrum` has the channel index, the tap,
+/// and the modulus of the spectrum.
+#[derive(Debug, Data, Copy, Clone, PartialEq, Eq)]
+pub enum Tap {
+    /// Before the channel's processing.
+    In,
+    /// After the channel's processing (before the EQ and the gain are applied).
+    Out,
+}
+
 #[derive(Debug, Clone)]
 pub enum UiMsg {
-    Levels { channel: usize, level: Level },
-    Metering { channel: usize, metering: Metering },
-    ToggleMetering { channel: usize },
+    Levels {
+        channel: usize,
+        level: Level,
+    },
+    Mode {
+        channel: usize,
+        mode: ChannelMode,
+    },
+    Metering {
+        channel: usize,
+        metering: Metering,
+    },
+    MetersReset {
+        channel: usize,
+    },
+    /// The settings of a band of a channel's EQ.
+    Eq {
+        channel: usize,
+        band: usize,
+        settings: Band,
+    },
+    ToggleMetering {
+        channel: usize,
+    },
     LowPassSpectrum(Vec<f32>),
-    AudioInSpectrum(Vec<f32>),
-    AudioOutSpectrum(Vec<f32>),
+    Spectrum(usize, Tap, Vec<f32>),
+    /// The readings of a stereo pair, by its index in `Config::stereo`.
+    Stereo(usize, StereoScope),
 }
 
 #[derive(Debug, Clone)]
@@ -151,6 +379,29 @@ pub enum Level {
     Gain(f64),
 }
 
+impl From<AudioMsg> for UiMsg {
+    fn from(msg: AudioMsg) -> Self {
+        match msg.kind {
+            AudioMsgKind::Gain(gain) => UiMsg::Levels {
+                channel: msg.channel,
+                level: Level::Gain(gain),
+            },
+            AudioMsgKind::Mode(mode) => UiMsg::Mode {
+                channel: msg.channel,
+                mode,
+            },
+            AudioMsgKind::Eq(band, settings) => UiMsg::Eq {
+                channel: msg.channel,
+                band,
+                settings,
+            },
+            AudioMsgKind::ResetMeters => UiMsg::MetersReset {
+                channel: msg.channel,
+            },
+        }
+    }
+}
+
 fn build_ui(tx: channel::Sender<AudioMsg>) -> impl Widget<State> {
     let red_hue = 10.0;
     let yellow_hue = 90.0;
@@ -191,6 +442,8 @@ fn build_ui(tx: channel::Sender<AudioMsg>) -> impl Widget<State> {
                 },
             )))
             .with_spacer(10.)
+            .with_child(meter_readout())
+            .with_spacer(10.)
             .with_child(Switch::new().lens(ChannelState::metering_on))
             .with_spacer(10.)
             .with_child(
@@ -215,26 +468,113 @@ fn build_ui(tx: channel::Sender<AudioMsg>) -> impl Widget<State> {
     .horizontal()
     .with_spacing(10.);
 
+    let channels = EnvScope::new(
+        |env, state: &State| state.meters.to_env(env),
+        Scroll::new(channels.lens(State::channels)).horizontal(),
+    );
+
     Flex::column()
         .main_axis_alignment(MainAxisAlignment::SpaceEvenly)
-        .with_child(Scroll::new(channels.lens(State::channels)).horizontal())
+        .with_child(channels)
+        .with_child(
+            Button::new("Reset meters").on_click(|_ctx, state: &mut State, _env| {
+                state.meter_resets += 1;
+            }),
+        )
         .padding(10.)
         .controller(Syncer::new(tx))
 }
 
+/// The loudness of a channel's output (momentary, short-term, integrated, and the loudness range),
+/// and its highest true peak in dBTP.
+fn meter_readout() -> impl Widget<ChannelState> {
+    let reading = |name: &'static str, value: fn(&Metering) -> f64| {
+        Label::new(move |state: &ChannelState, _env: &Env| {
+            if !state.metering_on {
+                format!("{} -", name)
+            } else {
+                let value = value(&state.metering);
+                if value.is_finite() {
+                    format!("{} {:.1}", name, value)
+                } else {
+                    format!("{} -inf", name)
+                }
+            }
+        })
+        .with_text_size(READOUT_TEXT_SIZE)
+    };
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(reading("M", |metering| metering.loudness.momentary))
+        .with_child(reading("S", |metering| metering.loudness.short_term))
+        .with_child(reading("I", |metering| metering.loudness.integrated))
+        .with_child(reading("LRA", |metering| metering.loudness.range))
+        .with_child(reading("TP", |metering| {
+            20.0 * metering.true_peak_out.max.log10()
+        }))
+}
+
+/// The windows that show readings from the RT thread.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum View {
+    Spectrum(Tap),
+    Spectrogram,
+    Stereo,
+}
+
+/// How many of each `View` are open, so the readings are only taken while someone is looking.
+#[derive(Debug, Default)]
+pub struct Views {
+    spectrum_in: AtomicUsize,
+    spectrum_out: AtomicUsize,
+    spectrogram: AtomicUsize,
+    stereo: AtomicUsize,
+}
+
+impl Views {
+    fn count(&self, view: View) -> &AtomicUsize {
+        match view {
+            View::Spectrum(Tap::In) => &self.spectrum_in,
+            View::Spectrum(Tap::Out) => &self.spectrum_out,
+            View::Spectrogram => &self.spectrogram,
+            View::Stereo => &self.stereo,
+        }
+    }
+
+    fn is_open(&self, view: View) -> bool {
+        self.count(view).load(Ordering::Relaxed) > 0
+    }
+
+    /// Whether any window shows the spectra at `tap`. The spectrogram can show either.
+    pub fn wants_spectra(&self, tap: Tap) -> bool {
+        self.is_open(View::Spectrum(tap)) || self.is_open(View::Spectrogram)
+    }
+
+    pub fn wants_stereo(&self) -> bool {
+        self.is_open(View::Stereo)
+    }
+}
+
 struct Delegate {
-    // used to debounce feedback from the RT thread.
-    last_update: Instant,
     //info_acc: PcmInfo,
+    views: Arc<Views>,
+    windows: HashMap<WindowId, View>,
 }
 
 impl Delegate {
-    fn new() -> Self {
+    fn new(views: Arc<Views>) -> Self {
         Delegate {
-            last_update: Instant::now(),
             //info_acc: PcmInfo::default(),
+            views,
+            windows: HashMap::new(),
         }
     }
+
+    fn open(&mut self, ctx: &mut DelegateCtx, window: WindowDesc<State>, view: View) {
+        self.views.count(view).fetch_add(1, Ordering::Relaxed);
+        self.windows.insert(window.id, view);
+        ctx.new_window(window);
+    }
 }
 
 impl AppDelegate<State> for Delegate {
@@ -250,18 +590,47 @@ impl AppDelegate<State> for Delegate {
             data.update(msg);
             Handled::Yes
         } else if let Some(()) = cmd.get(SHOW_LOW_PASS) {
-            ctx.new_window(low_pass_window());
+            ctx.new_window(low_pass_window(
+                data.low_pass_spectrum.len(),
+                data.bin_width,
+            ));
             Handled::Yes
         } else if let Some(()) = cmd.get(SHOW_INPUT_SPECTRUM) {
-            ctx.new_window(input_spectrum_window());
+            self.open(ctx, spectrum_window(Tap::In), View::Spectrum(Tap::In));
             Handled::Yes
         } else if let Some(()) = cmd.get(SHOW_OUTPUT_SPECTRUM) {
-            ctx.new_window(output_spectrum_window());
+            self.open(ctx, spectrum_window(Tap::Out), View::Spectrum(Tap::Out));
+            Handled::Yes
+        } else if let Some(()) = cmd.get(SHOW_SPECTROGRAM) {
+            let names = data.spectra.iter().map(|spectrum| spectrum.name.clone());
+            self.open(ctx, spectrogram_window(names.collect()), View::Spectrogram);
+            Handled::Yes
+        } else if let Some(()) = cmd.get(SHOW_STEREO) {
+            self.open(ctx, stereo_window(), View::Stereo);
+            Handled::Yes
+        } else if let Some(()) = cmd.get(SHOW_EQ) {
+            let names = data.channels.iter().map(|channel| channel.name.clone());
+            ctx.new_window(eq_window(names.collect()));
+            Handled::Yes
+        } else if let Some(()) = cmd.get(SHOW_METERS) {
+            ctx.new_window(meters_window());
             Handled::Yes
         } else {
             Handled::No
         }
     }
+
+    fn window_removed(
+        &mut self,
+        id: WindowId,
+        _data: &mut State,
+        _env: &Env,
+        _ctx: &mut DelegateCtx,
+    ) {
+        if let Some(view) = self.windows.remove(&id) {
+            self.views.count(view).fetch_sub(1, Ordering::Relaxed);
+        }
+    }
 }
 
 fn main_menu() -> MenuDesc<State> {
@@ -278,12 +647,25 @@ fn main_menu() -> MenuDesc<State> {
             OUTPUT_SPECTRUM_MENU_ITEM.with_placeholder("Output spectrum"),
             SHOW_OUTPUT_SPECTRUM,
         ))
+        .append(MenuItem::new(
+            SPECTROGRAM_MENU_ITEM.with_placeholder("Spectrogram"),
+            SHOW_SPECTROGRAM,
+        ))
+        .append(MenuItem::new(
+            STEREO_MENU_ITEM.with_placeholder("Stereo"),
+            SHOW_STEREO,
+        ))
+        .append(MenuItem::new(EQ_MENU_ITEM.with_placeholder("EQ"), SHOW_EQ))
+        .append(MenuItem::new(
+            METERS_MENU_ITEM.with_placeholder("Meters"),
+            SHOW_METERS,
+        ))
 }
 
-fn low_pass_window() -> WindowDesc<State> {
-    WindowDesc::new(|| {
+fn low_pass_window(len: usize, bin_width: f64) -> WindowDesc<State> {
+    WindowDesc::new(move || {
         LineChart::new().lens(
-            line_chart_base()
+            line_chart_base(bins(len, bin_width))
                 .title(Constant(ArcStr::from("Low pass filter")))
                 .y_data(State::low_pass_spectrum)
                 .build(),
@@ -292,36 +674,308 @@ fn low_pass_window() -> WindowDesc<State> {
     .title(LOW_PASS_MENU_ITEM.with_placeholder("Low pass filter"))
 }
 
-fn input_spectrum_window() -> WindowDesc<State> {
+/// A spectrum analyzer, overlaying the spectra of the channels the user picks.
+fn spectrum_window(tap: Tap) -> WindowDesc<State> {
+    let title = match tap {
+        Tap::In => INPUT_SPECTRUM_MENU_ITEM.with_placeholder("Input spectrum"),
+        Tap::Out => OUTPUT_SPECTRUM_MENU_ITEM.with_placeholder("Output spectrum"),
+    };
+    WindowDesc::new(move || {
+        let selector = List::new(move || {
+            Flex::row()
+                .with_child(Checkbox::new("").lens(LensMap::new(
+                    move |state: &SpectrumState| state.shown(tap),
+                    move |state: &mut SpectrumState, shown| state.set_shown(tap, shown),
+                )))
+                .with_child(Swatch::new().lens(SpectrumState::hue))
+                .with_spacer(5.)
+                .with_child(Label::raw().lens(SpectrumState::name))
+        });
+        let settings = Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Label::new("Smoothing"))
+            .with_child(
+                RadioGroup::new(vec![
+                    ("Off", Smoothing::Off),
+                    ("1/3 octave", Smoothing::Third),
+                    ("1/6 octave", Smoothing::Sixth),
+                ])
+                .lens(AnalyzerSettings::smoothing),
+            )
+            .with_spacer(10.)
+            .with_child(Label::new("Averaging"))
+            .with_child(
+                RadioGroup::new(vec![
+                    ("Off", Averaging::Off),
+                    ("Fast", Averaging::Fast),
+                    ("Slow", Averaging::Slow),
+                ])
+                .lens(AnalyzerSettings::averaging),
+            )
+            .with_spacer(10.)
+            .with_child(Checkbox::new("Peak hold").lens(AnalyzerSettings::peak_hold));
+        let sidebar = Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_flex_child(Scroll::new(selector.lens(State::spectra)).vertical(), 1.)
+            .with_spacer(10.)
+            .with_child(settings.lens(State::analyzer))
+            .with_child(
+                Button::new("Reset peaks").on_click(|_ctx, state: &mut State, _env| {
+                    state.reset_peaks();
+                }),
+            )
+            .padding(10.);
+        Flex::row()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(sidebar)
+            .with_flex_child(Spectra::new(tap).padding(10.), 1.)
+    })
+    .title(title)
+}
+
+/// A scrolling spectrogram of one channel, for spotting feedback and hum as they come and go.
+fn spectrogram_window(names: Vec<Arc<String>>) -> WindowDesc<State> {
+    WindowDesc::new(move || {
+        let channels = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (name.to_string(), idx))
+            .collect::<Vec<_>>();
+        let settings = Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Label::new("Channel"))
+            .with_child(RadioGroup::new(channels).lens(SpectrogramSettings::channel))
+            .with_spacer(10.)
+            .with_child(
+                RadioGroup::new(vec![("Input", Tap::In), ("Output", Tap::Out)])
+                    .lens(SpectrogramSettings::tap),
+            )
+            .with_spacer(10.)
+            .with_child(Label::new("Colours"))
+            .with_child(
+                RadioGroup::new(vec![
+                    ("Grey", ColorMap::Grey),
+                    ("Heat", ColorMap::Heat),
+                    ("Viridis", ColorMap::Viridis),
+                ])
+                .lens(SpectrogramSettings::color_map),
+            )
+            .with_spacer(10.)
+            .with_child(stepper(
+                "Floor",
+                "dB",
+                (-140., -40.),
+                10.,
+                SpectrogramSettings::min_db,
+            ))
+            .with_child(stepper(
+                "Ceiling",
+                "dB",
+                (-30., 0.),
+                10.,
+                SpectrogramSettings::max_db,
+            ))
+            .with_child(stepper(
+                "Time span",
+                "s",
+                (5., 120.),
+                5.,
+                SpectrogramSettings::span,
+            ))
+            .padding(10.);
+        Flex::row()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Scroll::new(settings.lens(State::spectrogram)).vertical())
+            .with_flex_child(Spectrogram::new().padding(10.), 1.)
+    })
+    .title(SPECTROGRAM_MENU_ITEM.with_placeholder("Spectrogram"))
+}
+
+/// The phase correlation and a goniometer for each stereo pair.
+fn stereo_window() -> WindowDesc<State> {
     WindowDesc::new(|| {
-        WidgetExt::lens(
-            LineChart::new(),
-            line_chart_base()
-                .title(Constant(ArcStr::from("Input spectrum")))
-                .y_data(State::audio_in_spectrum)
-                .build(),
-        )
+        let pairs = List::new(|| {
+            Flex::column()
+                .with_child(Label::raw().lens(StereoState::name))
+                .with_spacer(5.)
+                .with_child(Goniometer::new().lens(StereoState::points))
+                .with_spacer(5.)
+                .with_child(Correlation::new().lens(StereoState::correlation))
+        })
+        .horizontal()
+        .with_spacing(10.);
+        Scroll::new(pairs.lens(State::stereo))
+            .horizontal()
+            .padding(10.)
+    })
+    .title(STEREO_MENU_ITEM.with_placeholder("Stereo"))
+}
+
+/// The EQ of one channel: its response, and the settings of each band.
+fn eq_window(names: Vec<Arc<String>>) -> WindowDesc<State> {
+    WindowDesc::new(move || {
+        let channels = names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| (name.to_string(), idx))
+            .collect::<Vec<_>>();
+        let sidebar = Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Label::new("Channel"))
+            .with_child(RadioGroup::new(channels).lens(State::eq_channel))
+            .padding(10.);
+        let mut bands = Flex::row().cross_axis_alignment(CrossAxisAlignment::Start);
+        for band in 0..EQ_BANDS {
+            bands.add_child(eq_band(band).lens(band_lens(band)));
+            bands.add_spacer(10.);
+        }
+        Flex::row()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Scroll::new(sidebar).vertical())
+            .with_flex_child(
+                Flex::column()
+                    .with_flex_child(EqResponse::new(), 1.)
+                    .with_spacer(10.)
+                    .with_child(bands)
+                    .padding(10.),
+                1.,
+            )
     })
-    .title(INPUT_SPECTRUM_MENU_ITEM.with_placeholder("Input spectrum"))
+    .title(EQ_MENU_ITEM.with_placeholder("EQ"))
+}
+
+/// The settings of a band of the EQ.
+fn eq_band(band: usize) -> impl Widget<Band> {
+    let kinds = FilterKind::ALL
+        .iter()
+        .map(|kind| {
+            let label = match kind {
+                FilterKind::Peaking => "Peaking",
+                FilterKind::LowShelf => "Low shelf",
+                FilterKind::HighShelf => "High shelf",
+                FilterKind::LowPass => "Low pass",
+                FilterKind::HighPass => "High pass",
+                FilterKind::Notch => "Notch",
+                FilterKind::AllPass => "All pass",
+            };
+            (label, *kind)
+        })
+        .collect::<Vec<_>>();
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Checkbox::new(format!("Band {}", band + 1)).lens(Band::on))
+        .with_child(RadioGroup::new(kinds).lens(Band::kind))
+        .with_spacer(10.)
+        .with_child(eq_slider(EqParam::Freq, |band| {
+            if band.freq >= 1000.0 {
+                format!("{:.2} kHz", band.freq / 1000.0)
+            } else {
+                format!("{:.0} Hz", band.freq)
+            }
+        }))
+        .with_child(eq_slider(EqParam::Gain, |band| {
+            format!("{:+.1} dB", band.gain)
+        }))
+        .with_child(eq_slider(EqParam::Q, |band| format!("Q {:.2}", band.q)))
+}
+
+/// A slider for a parameter of a band, with its value shown by `label`.
+fn eq_slider(param: EqParam, label: fn(&Band) -> String) -> impl Widget<Band> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new(move |band: &Band, _env: &Env| label(band)))
+        .with_child(Slider::new().lens(LensMap::new(
+            move |band: &Band| param.position(band),
+            move |band: &mut Band, position| {
+                // leave the band alone unless the slider moved, so we don't send rounding errors
+                // to the mixer.
+                if position != param.position(band) {
+                    *band = param.with_position(*band, position);
+                }
+            },
+        )))
+}
+
+/// A band of the EQ of the channel in the EQ window.
+fn band_lens(band: usize) -> impl Lens<State, Band> {
+    LensMap::new(
+        move |state: &State| {
+            state
+                .channels
+                .get(state.eq_channel)
+                .map(|channel| channel.eq[band])
+                .unwrap_or(Band::DEFAULTS[band])
+        },
+        move |state: &mut State, settings| {
+            let eq_channel = state.eq_channel;
+            if let Some(channel) = state.channels.get_mut(eq_channel) {
+                if channel.eq[band] != settings {
+                    channel.eq[band] = settings;
+                }
+            }
+        },
+    )
 }
 
-fn output_spectrum_window() -> WindowDesc<State> {
+/// How the level meters in the faders behave.
+fn meters_window() -> WindowDesc<State> {
     WindowDesc::new(|| {
-        WidgetExt::lens(
-            LineChart::new(),
-            line_chart_base()
-                .title(Constant(ArcStr::from("Output spectrum")))
-                .y_data(State::audio_out_spectrum)
-                .build(),
-        )
+        Flex::column()
+            .cross_axis_alignment(CrossAxisAlignment::Start)
+            .with_child(Label::new("Meter"))
+            .with_child(
+                RadioGroup::new(vec![
+                    ("Digital peak", Standard::Digital),
+                    ("PPM", Standard::Ppm),
+                    ("VU", Standard::Vu),
+                ])
+                .lens(MeterSettings::standard),
+            )
+            .with_spacer(10.)
+            .with_child(stepper(
+                "Peak decay",
+                "dB/s",
+                (1., 60.),
+                1.,
+                MeterSettings::decay,
+            ))
+            .with_child(stepper(
+                "Peak hold",
+                "s",
+                (0., 10.),
+                0.5,
+                MeterSettings::hold,
+            ))
+            .padding(10.)
+            .lens(State::meters)
     })
-    .title(OUTPUT_SPECTRUM_MENU_ITEM.with_placeholder("Output spectrum"))
+    .title(METERS_MENU_ITEM.with_placeholder("Meters"))
+}
+
+/// A labelled stepper for a setting, showing its value in `unit`.
+fn stepper<T: Data>(
+    label: &'static str,
+    unit: &'static str,
+    (min, max): (f64, f64),
+    step: f64,
+    lens: impl Lens<T, f64> + 'static,
+) -> impl Widget<T> {
+    Flex::row()
+        .with_child(Label::new(label))
+        .with_spacer(5.)
+        .with_child(Label::new(move |value: &f64, _env: &Env| {
+            format!("{} {}", value, unit)
+        }))
+        .with_child(Stepper::new().with_range(min, max).with_step(step))
+        .lens(lens)
 }
 
 pub fn run(
     tx: channel::Sender<AudioMsg>,
     shutdown_tx: channel::Sender<()>,
     config: Arc<Config>,
+    sample_rate: usize,
+    views: Arc<Views>,
 ) -> Result<(ExtEventSink, JoinHandle<Result>)> {
     let (oneshot_tx, oneshot_rx) = channel::bounded(0);
     // todo check if the ui should be on the main thread?
@@ -332,15 +986,15 @@ pub fn run(
             .menu(main_menu())
             .window_size((
                 len_channels * widgets::WIDTH + (len_channels + 1.) * PADDING,
-                3.0 * widgets::KNOB_HEIGHT + widgets::FADER_HEIGHT + 5.0 * PADDING,
+                3.0 * widgets::KNOB_HEIGHT + widgets::FADER_HEIGHT + READOUT_HEIGHT + 5.0 * PADDING,
             ));
         let launcher = AppLauncher::with_window(window)
             .configure_env(|env, _| druid_graphs::add_to_env(env))
-            .delegate(Delegate::new());
+            .delegate(Delegate::new(views));
         oneshot_tx.send(launcher.get_external_handle()).unwrap();
         drop(oneshot_tx);
 
-        launcher.launch(State::new(&*config))?;
+        launcher.launch(State::new(&*config, sample_rate))?;
         shutdown_tx.send(())?;
         Ok(())
     });
@@ -348,7 +1002,9 @@ pub fn run(
     Ok((evt_sink, ui_handle))
 }
 
-fn line_chart_base<L1, L2>() -> LineChartDataLensBuilder<
+fn line_chart_base<L1, L2>(
+    bins: Option<Vector<f64>>,
+) -> LineChartDataLensBuilder<
     L1,
     Constant<ArcStr>,
     Constant<Option<Range>>,
@@ -365,18 +1021,17 @@ fn line_chart_base<L1, L2>() -> LineChartDataLensBuilder<
         .x_range(Constant(None))
         .draw_x_tick_labels(Constant(true))
         .draw_x_axis(Constant(false))
-        // hard code bin frequencies for now (assume sampling frequency is 44_100 Hz)
-        .x_data(Constant(bins()))
+        .x_data(Constant(bins))
         .y_range(Constant(Some(Range::new(0., 1.))))
         .draw_y_tick_labels(Constant(true))
         .draw_y_axis(Constant(true))
 }
 
-fn bins() -> Option<Vector<f64>> {
+/// The frequencies of the first `len` bins.
+fn bins(len: usize, bin_width: f64) -> Option<Vector<f64>> {
     Some(
-        (0..256)
-            .into_iter()
-            .map(|idx| idx as f64 * 44_100. / 256.)
+        (0..len)
+            .map(|idx| idx as f64 * bin_width)
             .collect::<Vector<_>>(),
     )
 }
@@ -0,0 +1,222 @@
+//! Turning the spectra from the RT thread into something worth looking at: levels in dB, smoothed
+//! over fractions of an octave, averaged over time, with the peaks held.
+use druid::{Data, Lens};
+use im::Vector;
+
+/// The quietest level we report, in dB.
+pub const DB_FLOOR: f64 = -120.0;
+/// The average of the (blackman) analysis window, including the fiddle factor in
+/// `effects::blackman`. A full scale sine in the middle of a bin has a modulus of
+/// `fft_size * WINDOW_GAIN / 2`.
+const WINDOW_GAIN: f64 = 0.42 * 0.9;
+
+/// Analyzer settings, shared by every spectrum window.
+#[derive(Debug, Data, Copy, Clone, Lens, PartialEq)]
+pub struct Settings {
+    pub smoothing: Smoothing,
+    pub averaging: Averaging,
+    pub peak_hold: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            smoothing: Smoothing::Third,
+            averaging: Averaging::Fast,
+            peak_hold: false,
+        }
+    }
+}
+
+/// Averaging each bin with its neighbours within a fraction of an octave.
+#[derive(Debug, Data, Copy, Clone, PartialEq, Eq)]
+pub enum Smoothing {
+    Off,
+    Third,
+    Sixth,
+}
+
+impl Smoothing {
+    /// The number of bands per octave, if we smooth.
+    fn bands(self) -> Option<f64> {
+        match self {
+            Smoothing::Off => None,
+            Smoothing::Third => Some(3.0),
+            Smoothing::Sixth => Some(6.0),
+        }
+    }
+}
+
+/// Exponential averaging over time, with the time constants of a sound level meter.
+#[derive(Debug, Data, Copy, Clone, PartialEq, Eq)]
+pub enum Averaging {
+    Off,
+    Fast,
+    Slow,
+}
+
+impl Averaging {
+    /// The time constant, in seconds.
+    fn time_constant(self) -> Option<f64> {
+        match self {
+            Averaging::Off => None,
+            Averaging::Fast => Some(0.125),
+            Averaging::Slow => Some(1.0),
+        }
+    }
+}
+
+/// The analysis of one spectrum (e.g. the input of a channel).
+#[derive(Debug, Data, Clone, Default, PartialEq)]
+pub struct Analysis {
+    /// The level of each bin, in dB relative to full scale.
+    pub level: Vector<f64>,
+    /// The highest level of each bin since the peaks were reset, or empty if we aren't holding
+    /// peaks.
+    pub peak: Vector<f64>,
+    /// The level of each bin in the latest spectrum, before smoothing or averaging.
+    pub raw: Vector<f64>,
+    /// The averaged power of each bin.
+    power: Vector<f64>,
+}
+
+impl Analysis {
+    /// Add a new spectrum (the modulus of each bin), `dt` seconds after the last one.
+    pub fn update(&mut self, modulus: &[f32], fft_size: usize, settings: &Settings, dt: f64) {
+        let mut power = power(modulus, fft_size);
+        self.raw = power.iter().map(|power| to_db(*power)).collect();
+        if let Some(bands) = settings.smoothing.bands() {
+            power = smooth(&power, bands);
+        }
+        match settings.averaging.time_constant() {
+            Some(time_constant) if self.power.len() == power.len() => {
+                let alpha = 1.0 - (-dt / time_constant).exp();
+                for (avg, power) in self.power.iter_mut().zip(power) {
+                    *avg += alpha * (power - *avg);
+                }
+            }
+            _ => self.power = power.into_iter().collect(),
+        }
+        self.level = self.power.iter().map(|power| to_db(*power)).collect();
+        if !settings.peak_hold {
+            self.peak.clear();
+        } else if self.peak.len() != self.level.len() {
+            self.peak = self.level.clone();
+        } else {
+            for (peak, level) in self.peak.iter_mut().zip(self.level.iter()) {
+                *peak = peak.max(*level);
+            }
+        }
+    }
+
+    pub fn reset_peaks(&mut self) {
+        self.peak = self.level.clone();
+    }
+}
+
+/// The power of each bin, relative to a full scale sine.
+fn power(modulus: &[f32], fft_size: usize) -> Vec<f64> {
+    let full_scale = fft_size as f64 * WINDOW_GAIN * 0.5;
+    modulus
+        .iter()
+        .map(|modulus| (*modulus as f64 / full_scale).powi(2))
+        .collect()
+}
+
+/// Average the power in each bin over the bins within `1 / bands` of an octave around it.
+///
+/// Bin frequencies are proportional to their index, so we can work with indices.
+fn smooth(power: &[f64], bands: f64) -> Vec<f64> {
+    let half_band = 2f64.powf(0.5 / bands);
+    // prefix[i] is the sum of the first i bins.
+    let mut prefix = Vec::with_capacity(power.len() + 1);
+    prefix.push(0.0);
+    for power in power {
+        prefix.push(prefix.last().unwrap() + power);
+    }
+    (0..power.len())
+        .map(|idx| {
+            if idx == 0 {
+                // DC has no octave.
+                return power[0];
+            }
+            let lo = ((idx as f64 / half_band).round() as usize).max(1).min(idx);
+            let hi = ((idx as f64 * half_band).round() as usize)
+                .min(power.len() - 1)
+                .max(idx);
+            (prefix[hi + 1] - prefix[lo]) / (hi + 1 - lo) as f64
+        })
+        .collect()
+}
+
+fn to_db(power: f64) -> f64 {
+    if power > 0.0 {
+        (10.0 * power.log10()).max(DB_FLOOR)
+    } else {
+        DB_FLOOR
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{smooth, Analysis, Averaging, Settings, Smoothing, DB_FLOOR, WINDOW_GAIN};
+
+    #[test]
+    fn full_scale() {
+        let fft_size = 512;
+        let modulus = [0.0, (fft_size as f64 * WINDOW_GAIN * 0.5) as f32, 0.0];
+        let mut analysis = Analysis::default();
+        let settings = Settings {
+            smoothing: Smoothing::Off,
+            averaging: Averaging::Off,
+            peak_hold: false,
+        };
+        analysis.update(&modulus, fft_size, &settings, 0.1);
+        assert_eq!(analysis.level[0], DB_FLOOR);
+        assert!(analysis.level[1].abs() < 1e-6);
+    }
+
+    #[test]
+    fn smoothing() {
+        // a flat spectrum stays flat.
+        let flat = vec![1.0; 100];
+        assert!(smooth(&flat, 3.0)
+            .iter()
+            .all(|power| (power - 1.0).abs() < 1e-9));
+        // a spike is spread over more bins higher up, and spread further by wider bands.
+        let mut spike = vec![0.0; 100];
+        spike[80] = 1.0;
+        let third = smooth(&spike, 3.0);
+        let sixth = smooth(&spike, 6.0);
+        assert!(third[80] < 1.0 && third[80] > 0.0);
+        assert!(sixth[80] > third[80]);
+        assert!(third[75] > 0.0 && sixth[75] == 0.0);
+        assert_eq!(third[10], 0.0);
+    }
+
+    #[test]
+    fn averaging_and_peaks() {
+        let fft_size = 2;
+        let mut settings = Settings {
+            smoothing: Smoothing::Off,
+            averaging: Averaging::Fast,
+            peak_hold: true,
+        };
+        let loud = [(fft_size as f64 * WINDOW_GAIN * 0.5) as f32];
+        let mut analysis = Analysis::default();
+        analysis.update(&loud, fft_size, &settings, 0.1);
+        analysis.update(&[0.0], fft_size, &settings, 0.1);
+        // falls, but not all the way (unless you look at the raw level).
+        assert!(analysis.level[0] < -1.0 && analysis.level[0] > -10.0);
+        assert_eq!(analysis.raw[0], DB_FLOOR);
+        assert!(analysis.peak[0].abs() < 1e-6);
+        // after a long time, it has gone.
+        analysis.update(&[0.0], fft_size, &settings, 100.0);
+        assert_eq!(analysis.level[0], DB_FLOOR);
+        analysis.reset_peaks();
+        assert_eq!(analysis.peak[0], DB_FLOOR);
+        settings.peak_hold = false;
+        analysis.update(&[0.0], fft_size, &settings, 0.1);
+        assert!(analysis.peak.is_empty());
+    }
+}
@@ -0,0 +1,215 @@
+//! How the level meters in the faders move. The mixer sends the peak and RMS of each meter frame,
+//! and we turn them into the reading of a digital peak meter, a PPM or a VU meter, holding the
+//! highest reading for a while. Readings only arrive once per meter frame, so the attack of the PPM
+//! (and the rise of anything shorter than a frame) is only approximate.
+use druid::{Data, Env, Key, Lens};
+
+/// The quietest level we report, in dB.
+pub const DB_FLOOR: f64 = -120.0;
+/// PPM integration time (IEC 60268-10 type I), in seconds.
+const PPM_ATTACK: f64 = 0.005;
+/// A PPM falls 20 dB in 1.5 seconds.
+const PPM_FALL: f64 = 20.0 / 1.5;
+/// The time constant of a VU meter, which gets to 99% of a steady level in 300 ms.
+const VU_TIME: f64 = 0.065;
+
+const STANDARD: Key<u64> = Key::new("mixjack.meter-standard");
+const DECAY: Key<f64> = Key::new("mixjack.meter-decay");
+const HOLD: Key<f64> = Key::new("mixjack.meter-hold");
+
+/// What the level meters show.
+#[derive(Debug, Data, Copy, Clone, PartialEq, Eq)]
+pub enum Standard {
+    /// The sample peak, rising at once and falling at `Settings::decay`.
+    Digital,
+    /// A quasi-peak programme meter: it rises almost as fast as the peaks, and falls slowly.
+    Ppm,
+    /// The RMS level, rising and falling slowly, so it reads closer to how loud things sound.
+    Vu,
+}
+
+/// Level meter settings, shared by every fader.
+#[derive(Debug, Data, Copy, Clone, Lens, PartialEq)]
+pub struct Settings {
+    pub standard: Standard,
+    /// How fast the digital peak meter falls, in dB per second.
+    pub decay: f64,
+    /// How long the held peak stays before it drops back to the meter, in seconds. With 0 there is
+    /// no held peak.
+    pub hold: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            standard: Standard::Digital,
+            decay: 12.0,
+            hold: 2.0,
+        }
+    }
+}
+
+impl Settings {
+    /// Put the settings in the environment, where the faders can find them.
+    pub fn to_env(&self, env: &mut Env) {
+        let standard = match self.standard {
+            Standard::Digital => 0,
+            Standard::Ppm => 1,
+            Standard::Vu => 2,
+        };
+        env.set(STANDARD, standard);
+        env.set(DECAY, self.decay);
+        env.set(HOLD, self.hold);
+    }
+
+    /// The settings put in the environment by `to_env`, or the defaults if there are none.
+    pub fn from_env(env: &Env) -> Self {
+        let default = Settings::default();
+        let standard = match env.try_get(STANDARD) {
+            Ok(1) => Standard::Ppm,
+            Ok(2) => Standard::Vu,
+            Ok(_) => Standard::Digital,
+            Err(_) => default.standard,
+        };
+        Settings {
+            standard,
+            decay: env.try_get(DECAY).unwrap_or(default.decay),
+            hold: env.try_get(HOLD).unwrap_or(default.hold),
+        }
+    }
+}
+
+/// The state of one level meter. Levels are in dB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ballistics {
+    level: f64,
+    held: f64,
+    /// How long the held peak has been above the level, in seconds.
+    held_for: f64,
+}
+
+impl Default for Ballistics {
+    fn default() -> Self {
+        Ballistics {
+            level: DB_FLOOR,
+            held: DB_FLOOR,
+            held_for: 0.0,
+        }
+    }
+}
+
+impl Ballistics {
+    /// Move the meter on by `dt` seconds, given the (linear) `peak` and `rms` of the signal over
+    /// that time.
+    pub fn update(&mut self, peak: f64, rms: f64, dt: f64, settings: &Settings) {
+        self.level = match settings.standard {
+            Standard::Digital => fall(self.level, to_db(peak), settings.decay * dt),
+            Standard::Ppm if to_db(peak) > self.level => follow(self.level, peak, dt, PPM_ATTACK),
+            Standard::Ppm => fall(self.level, to_db(peak), PPM_FALL * dt),
+            Standard::Vu => follow(self.level, rms, dt, VU_TIME),
+        };
+        if self.level >= self.held || self.held_for >= settings.hold {
+            self.held = self.level;
+            self.held_for = 0.0;
+        } else {
+            self.held_for += dt;
+        }
+    }
+
+    /// The reading of the meter.
+    pub fn level(&self) -> f64 {
+        self.level
+    }
+
+    /// The highest reading in the last `Settings::hold` seconds (or so).
+    pub fn held(&self) -> f64 {
+        self.held
+    }
+}
+
+/// Jump up to `target`, or fall towards it by at most `fall` dB.
+fn fall(level: f64, target: f64, fall: f64) -> f64 {
+    target.max(level - fall)
+}
+
+/// Move the (linear) amplitude towards `target` with time constant `time`, and return it in dB.
+fn follow(level: f64, target: f64, dt: f64, time: f64) -> f64 {
+    let amplitude = 10f64.powf(level / 20.0);
+    to_db(amplitude + (target - amplitude) * (1.0 - (-dt / time).exp()))
+}
+
+pub fn to_db(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        (20.0 * amplitude.log10()).max(DB_FLOOR)
+    } else {
+        DB_FLOOR
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Ballistics, Settings, Standard, DB_FLOOR};
+
+    /// Run the meter for `secs` seconds of a steady signal, in 1/60 s frames.
+    fn run(meter: &mut Ballistics, peak: f64, rms: f64, secs: f64, settings: &Settings) {
+        for _ in 0..(secs * 60.0).round() as usize {
+            meter.update(peak, rms, 1.0 / 60.0, settings);
+        }
+    }
+
+    #[test]
+    fn digital() {
+        let settings = Settings::default();
+        let mut meter = Ballistics::default();
+        meter.update(0.5, 0.1, 1.0 / 60.0, &settings);
+        // straight up to the peak.
+        assert!((meter.level() + 6.02).abs() < 0.01);
+        run(&mut meter, 0.0, 0.0, 1.0, &settings);
+        assert!((meter.level() - (-6.02 - settings.decay)).abs() < 0.01);
+        run(&mut meter, 0.0, 0.0, 20.0, &settings);
+        assert_eq!(meter.level(), DB_FLOOR);
+    }
+
+    #[test]
+    fn ppm_and_vu() {
+        let ppm = Settings {
+            standard: Standard::Ppm,
+            ..Settings::default()
+        };
+        let mut meter = Ballistics::default();
+        run(&mut meter, 1.0, 0.5, 0.1, &ppm);
+        assert!(meter.level().abs() < 0.01);
+        run(&mut meter, 0.0, 0.0, 1.5, &ppm);
+        assert!((meter.level() + 20.0).abs() < 0.01);
+
+        let vu = Settings {
+            standard: Standard::Vu,
+            ..Settings::default()
+        };
+        let mut meter = Ballistics::default();
+        // a VU meter reads the RMS, and takes its time getting there.
+        meter.update(1.0, 0.5, 1.0 / 60.0, &vu);
+        assert!(meter.level() < -12.0);
+        run(&mut meter, 1.0, 0.5, 0.3, &vu);
+        assert!((meter.level() + 6.02).abs() < 0.1);
+    }
+
+    #[test]
+    fn hold() {
+        let settings = Settings::default();
+        let mut meter = Ballistics::default();
+        meter.update(1.0, 1.0, 1.0 / 60.0, &settings);
+        run(&mut meter, 0.1, 0.1, 1.9, &settings);
+        assert_eq!(meter.held(), 0.0);
+        run(&mut meter, 0.1, 0.1, 0.2, &settings);
+        assert_eq!(meter.held(), meter.level());
+
+        let settings = Settings {
+            hold: 0.0,
+            ..settings
+        };
+        meter.update(1.0, 1.0, 1.0 / 60.0, &settings);
+        meter.update(0.1, 0.1, 1.0 / 60.0, &settings);
+        assert_eq!(meter.held(), meter.level());
+    }
+}
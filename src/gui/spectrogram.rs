@@ -0,0 +1,223 @@
+//! Keeping the recent spectra of a channel, and turning them into an image for the spectrogram
+//! window.
+use crate::gui::{analyzer::DB_FLOOR, Tap};
+use druid::{Data, Lens};
+use im::Vector;
+use std::{collections::VecDeque, ops::Range};
+
+/// Spectrogram settings, shared by every spectrogram window.
+#[derive(Debug, Data, Copy, Clone, Lens, PartialEq)]
+pub struct Settings {
+    /// The index of the channel we show.
+    pub channel: usize,
+    pub tap: Tap,
+    pub color_map: ColorMap,
+    /// The level drawn with the bottom of the colour map (and anything quieter), in dB.
+    pub min_db: f64,
+    /// The level drawn with the top of the colour map (and anything louder), in dB.
+    pub max_db: f64,
+    /// How much history we show, in seconds.
+    pub span: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            channel: 0,
+            tap: Tap::In,
+            color_map: ColorMap::Heat,
+            min_db: -100.0,
+            max_db: 0.0,
+            span: 10.0,
+        }
+    }
+}
+
+/// How levels are turned into colours, from quiet to loud.
+#[derive(Debug, Data, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMap {
+    Grey,
+    /// Black through red and yellow to white.
+    Heat,
+    /// Matplotlib's viridis, which stays readable for colour blind people and in greyscale.
+    Viridis,
+}
+
+impl ColorMap {
+    /// The colours we interpolate between, evenly spaced.
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            ColorMap::Grey => &[[0, 0, 0], [255, 255, 255]],
+            ColorMap::Heat => &[
+                [0, 0, 0],
+                [128, 0, 0],
+                [255, 64, 0],
+                [255, 224, 0],
+                [255, 255, 255],
+            ],
+            ColorMap::Viridis => &[
+                [68, 1, 84],
+                [59, 82, 139],
+                [33, 145, 140],
+                [94, 201, 98],
+                [253, 231, 37],
+            ],
+        }
+    }
+
+    /// The colour at `t` (between 0 and 1) along the map.
+    pub fn rgb(self, t: f64) -> [u8; 3] {
+        let stops = self.stops();
+        let pos = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+        let idx = (pos.floor() as usize).min(stops.len() - 2);
+        let frac = pos - idx as f64;
+        let mut rgb = [0; 3];
+        for (out, (lo, hi)) in rgb
+            .iter_mut()
+            .zip(stops[idx].iter().zip(stops[idx + 1].iter()))
+        {
+            *out = (*lo as f64 + (*hi as f64 - *lo as f64) * frac).round() as u8;
+        }
+        rgb
+    }
+}
+
+/// The latest spectra (levels in dB for each bin) of a channel, newest first.
+#[derive(Debug, Default)]
+pub struct History {
+    rows: VecDeque<Vec<f32>>,
+    /// The most rows we keep.
+    len: usize,
+}
+
+impl History {
+    pub fn new(len: usize) -> Self {
+        History {
+            rows: VecDeque::with_capacity(len),
+            len,
+        }
+    }
+
+    pub fn push(&mut self, level: &Vector<f64>) {
+        let row = if self.rows.len() == self.len {
+            // reuse the oldest row.
+            self.rows.pop_back().map(|mut row| {
+                row.clear();
+                row
+            })
+        } else {
+            None
+        };
+        let mut row = row.unwrap_or_default();
+        row.extend(level.iter().map(|level| *level as f32));
+        self.rows.push_front(row);
+        self.rows.truncate(self.len);
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.rows.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    /// Draw the history into `image`, as RGBA with separate alpha. The image is `columns.len()`
+    /// wide, where each column shows the loudest of the bins in its range, and has a row for each
+    /// spectrum we could keep, newest at the top. Rows we don't have yet are transparent.
+    pub fn render(&self, columns: &[Range<usize>], settings: &Settings, image: &mut Vec<u8>) {
+        image.clear();
+        let db_range = (settings.max_db - settings.min_db).max(1.0);
+        for row in self.rows.iter() {
+            for bins in columns {
+                let bins = bins.start.min(row.len())..bins.end.min(row.len());
+                let level = row[bins]
+                    .iter()
+                    .fold(DB_FLOOR, |max, level| max.max(*level as f64));
+                let [r, g, b] = settings.color_map.rgb((level - settings.min_db) / db_range);
+                image.extend_from_slice(&[r, g, b, 255]);
+            }
+        }
+        image.resize(columns.len() * self.len * 4, 0);
+    }
+}
+
+/// The bins shown in each of `count` columns, spread evenly over a log frequency axis from
+/// `min_freq` to the highest of `bins` bins (bin `i` is at `i * bin_width` Hz). Every column gets
+/// at least one bin, so at low frequencies neighbouring columns may show the same one.
+pub fn log_columns(count: usize, bins: usize, bin_width: f64, min_freq: f64) -> Vec<Range<usize>> {
+    let max_freq = bins.saturating_sub(1) as f64 * bin_width;
+    let freq = |col: usize| min_freq * (max_freq / min_freq).powf(col as f64 / count as f64);
+    // the bin closest to `freq`.
+    let bin = |freq: f64| ((freq / bin_width + 0.5).floor() as usize).min(bins.saturating_sub(1));
+    (0..count)
+        .map(|col| {
+            let start = bin(freq(col));
+            let end = bin(freq(col + 1)).max(start + 1);
+            start..end
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{log_columns, ColorMap, History, Settings};
+    use im::vector;
+
+    #[test]
+    fn color_maps() {
+        for map in &[ColorMap::Grey, ColorMap::Heat, ColorMap::Viridis] {
+            assert_eq!(map.rgb(0.0), map.stops()[0]);
+            assert_eq!(map.rgb(1.0), *map.stops().last().unwrap());
+            // out of range levels are clamped.
+            assert_eq!(map.rgb(-1.0), map.rgb(0.0));
+            assert_eq!(map.rgb(2.0), map.rgb(1.0));
+        }
+        assert_eq!(ColorMap::Grey.rgb(0.5), [128, 128, 128]);
+    }
+
+    #[test]
+    fn columns() {
+        // 48kHz, 512 point fft.
+        let columns = log_columns(100, 257, 93.75, 20.0);
+        assert_eq!(columns.len(), 100);
+        assert_eq!(columns[0].start, 0);
+        assert_eq!(columns[99].end, 256);
+        assert!(columns.iter().all(|bins| !bins.is_empty()));
+        // the columns cover every bin from the lowest, in order.
+        assert!(columns
+            .windows(2)
+            .all(|pair| pair[1].start == pair[0].end || pair[1].start == pair[0].start));
+    }
+
+    #[test]
+    fn history() {
+        let settings = Settings {
+            color_map: ColorMap::Grey,
+            min_db: -100.0,
+            max_db: 0.0,
+            ..Settings::default()
+        };
+        let mut history = History::new(3);
+        for level in &[-100.0, -50.0, 0.0, 0.0] {
+            history.push(&vector![*level, -100.0]);
+        }
+        let mut image = Vec::new();
+        history.render(&[0..1, 0..2], &settings, &mut image);
+        assert_eq!(image.len(), 2 * 3 * 4);
+        // newest first, and the loudest bin in a column wins.
+        assert_eq!(&image[..8], &[255, 255, 255, 255, 255, 255, 255, 255]);
+        assert_eq!(&image[16..20], &[128, 128, 128, 255]);
+
+        history.set_len(4);
+        history.clear();
+        history.push(&vector![0.0, 0.0]);
+        history.render(&[0..2], &settings, &mut image);
+        // empty rows are transparent.
+        assert_eq!(
+            image,
+            vec![255, 255, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
@@ -1,21 +1,64 @@
-use crate::{audio::AudioMsg, data::Metering, gui::State};
+use crate::{
+    audio::AudioMsg,
+    data::Metering,
+    effects::{eq_response, Band},
+    gui::{
+        ballistics::{Ballistics, Settings as MeterSettings, DB_FLOOR},
+        spectrogram::{log_columns, History, Settings as SpectrogramSettings},
+        State, Tap, SPECTRA_FRAME,
+    },
+};
 use crossbeam_channel as channel;
 use druid::{
     piet::{
-        kurbo::{Arc, BezPath, Line, PathEl},
-        Brush,
+        kurbo::{Arc, BezPath, Circle, Line, PathEl},
+        Brush, FontFamily, ImageFormat, InterpolationMode, Text, TextLayoutBuilder,
     },
     theme,
     widget::{prelude::*, Controller},
     Color, Data, Insets, MouseButton, MouseEvent, Point, Rect, Vec2, Widget, WidgetPod,
 };
-use std::f64::consts::FRAC_PI_4;
+use std::{
+    f64::consts::{FRAC_PI_4, SQRT_2},
+    ops::Range,
+    sync::Arc as Shared,
+};
 
 pub const WIDTH: f64 = 50.0;
 pub const KNOB_HEIGHT: f64 = 50.0;
 pub const FADER_HEIGHT: f64 = 200.0;
 
 const SLIDER_HEIGHT: f64 = 20.0;
+/// The height of the clip lights above the meters.
+const CLIP_HEIGHT: f64 = 12.0;
+/// Room under the fader for the held peak.
+const PEAK_LABEL_HEIGHT: f64 = 12.0;
+/// The quietest level on the meters, in dB.
+const METER_FLOOR: f64 = -60.0;
+/// How far apart the marks on the meter scale are, in dB.
+const METER_GRID: f64 = 10.0;
+/// The longest we let the meters move in one go, in seconds, so they don't jump after a stall.
+const METER_MAX_STEP: f64 = 0.1;
+const SWATCH_SIZE: f64 = 12.0;
+/// The size of the spectrum plot, if the window doesn't decide for us.
+const SPECTRA_SIZE: Size = Size::new(600.0, 300.0);
+const GONIOMETER_SIZE: f64 = 250.0;
+const CORRELATION_HEIGHT: f64 = 20.0;
+const EQ_RESPONSE_SIZE: Size = Size::new(600.0, 250.0);
+/// The EQ response plot goes from minus this to plus this, in dB.
+const EQ_RESPONSE_DB: f64 = 24.0;
+const EQ_DB_GRID: f64 = 6.0;
+/// The range of the spectrum plot.
+const SPECTRA_MIN_FREQ: f64 = 20.0;
+const SPECTRA_MIN_DB: f64 = -100.0;
+const SPECTRA_MAX_DB: f64 = 0.0;
+/// Where we draw grid lines on the spectrum plot.
+const FREQ_GRID: [f64; 10] = [
+    20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0, 20000.0,
+];
+const DB_GRID: f64 = 20.0;
+/// How many parts the time axis of the spectrogram is divided into by grid lines.
+const TIME_GRID: usize = 5;
 
 pub struct Syncer {
     tx: channel::Sender<AudioMsg>,
@@ -179,23 +222,24 @@ pub struct FaderData {
     pub show_levels: bool,
 }
 
-#[derive(Debug, Data, Clone)]
 pub struct Fader {
     drag_start: Option<DragStart>,
-    all_time_max_in: f64,
+    meter_in: Ballistics,
+    meter_out: Ballistics,
 }
 
 impl Fader {
     pub fn new() -> Self {
         Fader {
             drag_start: None,
-            all_time_max_in: 0.0,
+            meter_in: Ballistics::default(),
+            meter_out: Ballistics::default(),
         }
     }
 }
 
 impl Widget<FaderData> for Fader {
-    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut FaderData, _env: &Env) {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut FaderData, env: &Env) {
         match event {
             Event::MouseDown(MouseEvent {
                 button: MouseButton::Left,
@@ -217,7 +261,11 @@ impl Widget<FaderData> for Fader {
                     if let Some(drag_start) = self.drag_start {
                         data.position = (drag_start.widget_val
                             + (drag_start.mouse_y - window_pos.y)
-                                / (ctx.size().height - SLIDER_HEIGHT).max(0.0))
+                                / (ctx.size().height
+                                    - SLIDER_HEIGHT
+                                    - CLIP_HEIGHT
+                                    - PEAK_LABEL_HEIGHT)
+                                    .max(0.0))
                         .max(0.0)
                         .min(1.0);
                     }
@@ -227,13 +275,30 @@ impl Widget<FaderData> for Fader {
                 self.drag_start = None;
                 ctx.set_active(false);
             }
+            // the meters keep moving between readings, so they are driven by the animation.
+            Event::AnimFrame(interval) if data.show_levels => {
+                let settings = MeterSettings::from_env(env);
+                let dt = (*interval as f64 * 1e-9).min(METER_MAX_STEP);
+                let metering = &data.metering;
+                self.meter_in
+                    .update(metering.max_in, metering.rms_in, dt, &settings);
+                self.meter_out
+                    .update(metering.max_out, metering.rms_out, dt, &settings);
+                ctx.request_anim_frame();
+                ctx.request_paint();
+            }
             _ => (),
         }
     }
 
     fn update(&mut self, ctx: &mut UpdateCtx, old: &FaderData, new: &FaderData, _env: &Env) {
+        if new.show_levels && !old.show_levels {
+            ctx.request_anim_frame();
+        } else if !new.show_levels {
+            self.meter_in = Ballistics::default();
+            self.meter_out = Ballistics::default();
+        }
         if old != new {
-            self.all_time_max_in = new.metering.max_in;
             ctx.request_paint();
         }
     }
@@ -258,24 +323,24 @@ impl Widget<FaderData> for Fader {
         bc.constrain(Size::new(WIDTH, FADER_HEIGHT))
     }
 
-    fn paint(&mut self, ctx: &mut PaintCtx, data: &FaderData, _env: &Env) {
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &FaderData, env: &Env) {
         // Clamp the relative position.
         let position = data.position.min(1.0).max(0.0);
-        let max_in = data.metering.max_in.min(1.0).max(0.0);
-        let rms_in = data.metering.rms_in.min(1.0).max(0.0);
-        let max_out = data.metering.max_out.min(1.0).max(0.0);
-        let rms_out = data.metering.rms_out.min(1.0).max(0.0);
+        let settings = MeterSettings::from_env(env);
 
         let light_brush = ctx.solid_brush(Color::WHITE);
         let dark_brush = ctx.solid_brush(Color::grey(0.5));
         let black_brush = ctx.solid_brush(Color::BLACK);
-        let max_brush = ctx.solid_brush(Color::rgba(0.0, 1.0, 0.0, 0.2));
-        let rms_brush = ctx.solid_brush(Color::rgb(0.0, 0.6, 0.0));
+        let level_brush = ctx.solid_brush(Color::rgb(0.0, 0.6, 0.0));
+        let held_brush = ctx.solid_brush(Color::rgb(0.6, 1.0, 0.6));
+        let clip_brush = ctx.solid_brush(Color::rgb(0.9, 0.0, 0.0));
 
-        let bounds = ctx
-            .size()
+        let size = ctx.size();
+        let bounds = size
             // a widget origin is always (0,0)
             .to_rect()
+            // leave room for the clip lights and the held peak
+            .inset(Insets::new(0.0, -CLIP_HEIGHT, 0.0, -PEAK_LABEL_HEIGHT))
             // leave room for the fader
             .inset(Insets::uniform_xy(0.0, -0.5 * SLIDER_HEIGHT));
         let center = bounds.center();
@@ -283,24 +348,44 @@ impl Widget<FaderData> for Fader {
         let top = Point::new(center.x, bounds.min_y());
         let bottom = Point::new(center.x, bounds.max_y());
         let fader_center = bottom.lerp(top, position);
-
-        let max_in_top = bottom.lerp(top, max_in).y;
-        let rms_in_top = bottom.lerp(top, rms_in).y;
-        let max_out_top = bottom.lerp(top, max_out).y;
-        let rms_out_top = bottom.lerp(top, rms_out).y;
+        // the height of a level on the meter scale.
+        let level_y = |db: f64| {
+            let fraction = ((db - METER_FLOOR) / -METER_FLOOR).clamp(0.0, 1.0);
+            bottom.lerp(top, fraction).y
+        };
 
         let level_start_x = lerp(bounds.x0, bounds.x1, 0.2);
         let level_mid_x = lerp(bounds.x0, bounds.x1, 0.5);
         let level_end_x = lerp(bounds.x0, bounds.x1, 0.8);
         if data.show_levels {
-            ctx.fill(
-                Rect::from_points((level_start_x, rms_in_top), (level_mid_x, bounds.y1)),
-                &rms_brush,
-            );
-            ctx.fill(
-                Rect::from_points((level_mid_x, rms_out_top), (level_end_x, bounds.y1)),
-                &rms_brush,
-            );
+            let mut db = 0.0;
+            while db >= METER_FLOOR {
+                let y = level_y(db);
+                ctx.stroke(
+                    Line::new((bounds.x0, y), (bounds.x0 + 4.0, y)),
+                    &dark_brush,
+                    1.0,
+                );
+                ctx.stroke(
+                    Line::new((bounds.x1 - 4.0, y), (bounds.x1, y)),
+                    &dark_brush,
+                    1.0,
+                );
+                db -= METER_GRID;
+            }
+            for (x0, x1, meter) in &[
+                (level_start_x, level_mid_x, &self.meter_in),
+                (level_mid_x, level_end_x, &self.meter_out),
+            ] {
+                ctx.fill(
+                    Rect::new(*x0, level_y(meter.level()), *x1, bounds.y1),
+                    &level_brush,
+                );
+                if settings.hold > 0.0 && meter.held() > METER_FLOOR {
+                    let y = level_y(meter.held());
+                    ctx.stroke(Line::new((*x0, y), (*x1, y)), &held_brush, 2.0);
+                }
+            }
         }
 
         // draw fader
@@ -317,15 +402,38 @@ impl Widget<FaderData> for Fader {
             ctx,
         );
 
-        // draw sound level
         if data.show_levels {
-            ctx.fill(
-                Rect::new(level_start_x, max_in_top, level_mid_x, bounds.y1),
-                &max_brush,
-            );
-            ctx.fill(
-                Rect::new(level_mid_x, max_out_top, level_end_x, bounds.y1),
-                &max_brush,
+            // the clip lights stay on (showing the number of overs) until the meters are reset.
+            for (x0, x1, overs) in &[
+                (level_start_x, level_mid_x, data.metering.true_peak_in.overs),
+                (level_mid_x, level_end_x, data.metering.true_peak_out.overs),
+            ] {
+                let light = Rect::new(*x0, 0.0, *x1, CLIP_HEIGHT - 2.0).inset(-0.5);
+                if *overs > 0 {
+                    ctx.fill(light, &clip_brush);
+                    draw_label(
+                        ctx,
+                        (*overs).min(99).to_string(),
+                        (x0 + 1.0, -1.0),
+                        &Color::WHITE,
+                    );
+                } else {
+                    ctx.stroke(light, &dark_brush, 1.0);
+                }
+            }
+
+            // the held peak of the output, in dB.
+            let held = self.meter_out.held();
+            let label = if held > DB_FLOOR {
+                format!("{:.1}", held)
+            } else {
+                "-inf".to_string()
+            };
+            draw_label(
+                ctx,
+                label,
+                (center.x - 12.0, size.height - PEAK_LABEL_HEIGHT),
+                &Color::WHITE,
             );
         }
     }
@@ -394,6 +502,555 @@ impl<E: Copy + PartialEq> Widget<E> for LightRadio<E> {
     }
 }
 
+/// A square of the colour used for a channel's spectrum. The data is the hue.
+pub struct Swatch;
+
+impl Swatch {
+    pub fn new() -> Self {
+        Swatch
+    }
+}
+
+impl Widget<f64> for Swatch {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut f64, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old: &f64, new: &f64, _env: &Env) {
+        if old != new {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &f64, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &f64,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(SWATCH_SIZE, SWATCH_SIZE))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &f64, _env: &Env) {
+        let rect = ctx.size().to_rect();
+        ctx.fill(rect, &spectrum_color(*data));
+    }
+}
+
+/// A spectrum analyzer, with the spectra of the channels the user has picked drawn over each
+/// other on a log frequency axis.
+pub struct Spectra {
+    tap: Tap,
+}
+
+impl Spectra {
+    pub fn new(tap: Tap) -> Self {
+        Spectra { tap }
+    }
+}
+
+impl Widget<State> for Spectra {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut State, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old: &State, new: &State, _env: &Env) {
+        if !old.spectra.same(&new.spectra) {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &State,
+        _env: &Env,
+    ) {
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &State,
+        _env: &Env,
+    ) -> Size {
+        fill(bc, SPECTRA_SIZE)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &State, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_DARK));
+        ctx.stroke(bounds.inset(-0.5), &env.get(theme::BORDER_DARK), 1.0);
+
+        let nyquist = data.bin_width * (data.fft_size / 2) as f64;
+        let x = |freq: f64| freq_x(bounds, nyquist, freq);
+        let y = |db: f64| {
+            let db = db.clamp(SPECTRA_MIN_DB, SPECTRA_MAX_DB);
+            lerp(
+                bounds.y1,
+                bounds.y0,
+                (db - SPECTRA_MIN_DB) / (SPECTRA_MAX_DB - SPECTRA_MIN_DB),
+            )
+        };
+
+        // grid
+        draw_freq_grid(ctx, bounds, nyquist, env);
+        let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+        let label_color = env.get(theme::LABEL_COLOR);
+        let mut db = SPECTRA_MAX_DB;
+        while db >= SPECTRA_MIN_DB {
+            let y = y(db);
+            ctx.stroke(Line::new((bounds.x0, y), (bounds.x1, y)), &grid_color, 1.0);
+            draw_label(
+                ctx,
+                format!("{} dB", db),
+                (bounds.x0 + 2.0, y),
+                &label_color,
+            );
+            db -= DB_GRID;
+        }
+
+        // spectra
+        let first_bin = (SPECTRA_MIN_FREQ / data.bin_width).ceil().max(1.0) as usize;
+        for spectrum in data
+            .spectra
+            .iter()
+            .filter(|spectrum| spectrum.shown(self.tap))
+        {
+            let color = spectrum_color(spectrum.hue);
+            let analysis = spectrum.analysis(self.tap);
+            for (levels, color) in &[
+                (&analysis.level, color.clone()),
+                (&analysis.peak, color.with_alpha(0.4)),
+            ] {
+                let mut path = BezPath::new();
+                for (idx, level) in levels.iter().enumerate().skip(first_bin) {
+                    let point = Point::new(x(idx as f64 * data.bin_width), y(*level));
+                    if idx == first_bin {
+                        path.move_to(point);
+                    } else {
+                        path.line_to(point);
+                    }
+                }
+                ctx.stroke(path, color, 1.5);
+            }
+        }
+    }
+}
+
+/// A spectrogram of one channel: time goes down the plot (newest at the top), frequency across it
+/// on a log axis, and level is shown by colour.
+pub struct Spectrogram {
+    history: History,
+    /// The bins shown in each column of the image.
+    columns: Vec<Range<usize>>,
+    /// The number of bins and bin width `columns` were worked out for.
+    columns_for: (usize, f64),
+    image: Vec<u8>,
+}
+
+impl Spectrogram {
+    pub fn new() -> Self {
+        Spectrogram {
+            history: History::default(),
+            columns: Vec::new(),
+            columns_for: (0, 0.0),
+            image: Vec::new(),
+        }
+    }
+
+    /// The number of spectra in `settings.span`. We get at most one spectrum per frame, and the
+    /// engine makes at least one per jack cycle, so this holds unless jack cycles are longer than a
+    /// frame.
+    fn history_len(settings: &SpectrogramSettings) -> usize {
+        (settings.span / SPECTRA_FRAME.as_secs_f64())
+            .round()
+            .max(1.0) as usize
+    }
+}
+
+impl Widget<State> for Spectrogram {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut State, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old: &State, new: &State, _env: &Env) {
+        let settings = &new.spectrogram;
+        if settings.channel != old.spectrogram.channel || settings.tap != old.spectrogram.tap {
+            self.history.clear();
+        }
+        if settings.span != old.spectrogram.span {
+            self.history.set_len(Self::history_len(settings));
+        }
+        let level = &new.spectra[settings.channel].analysis(settings.tap).raw;
+        if !level.same(&old.spectra[settings.channel].analysis(settings.tap).raw) {
+            self.history.push(level);
+            ctx.request_paint();
+        } else if !settings.same(&old.spectrogram) {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, event: &LifeCycle, data: &State, _env: &Env) {
+        if let LifeCycle::WidgetAdded = event {
+            self.history = History::new(Self::history_len(&data.spectrogram));
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &State,
+        _env: &Env,
+    ) -> Size {
+        fill(bc, SPECTRA_SIZE)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &State, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        let settings = &data.spectrogram;
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_DARK));
+
+        let bins = data.fft_size / 2 + 1;
+        let width = bounds.width().round().max(1.0) as usize;
+        if self.columns.len() != width || self.columns_for != (bins, data.bin_width) {
+            self.columns = log_columns(width, bins, data.bin_width, SPECTRA_MIN_FREQ);
+            self.columns_for = (bins, data.bin_width);
+        }
+        self.history
+            .render(&self.columns, settings, &mut self.image);
+        let height = self.image.len() / (4 * width);
+        if height > 0 {
+            let image = ctx.make_image(width, height, &self.image, ImageFormat::RgbaSeparate);
+            if let Ok(image) = image {
+                ctx.draw_image(&image, bounds, InterpolationMode::NearestNeighbor);
+            }
+        }
+
+        // grid
+        let nyquist = data.bin_width * (data.fft_size / 2) as f64;
+        draw_freq_grid(ctx, bounds, nyquist, env);
+        let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+        let label_color = env.get(theme::LABEL_COLOR);
+        for step in 0..TIME_GRID {
+            let y = lerp(bounds.y0, bounds.y1, step as f64 / TIME_GRID as f64);
+            ctx.stroke(Line::new((bounds.x0, y), (bounds.x1, y)), &grid_color, 1.0);
+            let time = settings.span * step as f64 / TIME_GRID as f64;
+            draw_label(
+                ctx,
+                format!("-{} s", time),
+                (bounds.x0 + 2.0, y),
+                &label_color,
+            );
+        }
+        ctx.stroke(bounds.inset(-0.5), &env.get(theme::BORDER_DARK), 1.0);
+    }
+}
+
+/// A goniometer (vector scope) of a stereo pair: the latest samples plotted as points, with mono
+/// straight up and down and the left and right channels on the diagonals.
+pub struct Goniometer;
+
+impl Goniometer {
+    pub fn new() -> Self {
+        Goniometer
+    }
+}
+
+impl Widget<Shared<[(f32, f32)]>> for Goniometer {
+    fn event(
+        &mut self,
+        _ctx: &mut EventCtx,
+        _event: &Event,
+        _data: &mut Shared<[(f32, f32)]>,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(
+        &mut self,
+        ctx: &mut UpdateCtx,
+        old: &Shared<[(f32, f32)]>,
+        new: &Shared<[(f32, f32)]>,
+        _env: &Env,
+    ) {
+        if !old.same(new) {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Shared<[(f32, f32)]>,
+        _env: &Env,
+    ) {
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &Shared<[(f32, f32)]>,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(GONIOMETER_SIZE, GONIOMETER_SIZE))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Shared<[(f32, f32)]>, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_DARK));
+        ctx.stroke(bounds.inset(-0.5), &env.get(theme::BORDER_DARK), 1.0);
+
+        // full scale mono reaches the top.
+        let center = bounds.center();
+        let scale = 0.5 * bounds.height().min(bounds.width()) / SQRT_2;
+        let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+        let label_color = env.get(theme::LABEL_COLOR);
+        let radius = 0.5 * bounds.height().min(bounds.width());
+        for (label, angle) in &[("M", 0.0), ("L", -FRAC_PI_4), ("R", FRAC_PI_4)] {
+            let end = Vec2::new(angle.sin(), -angle.cos()) * radius;
+            ctx.stroke(Line::new(center - end, center + end), &grid_color, 1.0);
+            draw_label(
+                ctx,
+                label.to_string(),
+                center + end * 0.9 - Vec2::new(4.0, 0.0),
+                &label_color,
+            );
+        }
+
+        let point_color = spectrum_color(120.0).with_alpha(0.6);
+        for (side, mid) in data.iter() {
+            let point = center + Vec2::new(*side as f64, -*mid as f64) * scale;
+            ctx.fill(Rect::from_center_size(point, (1.5, 1.5)), &point_color);
+        }
+    }
+}
+
+/// A phase correlation meter, from -1 (out of phase) on the left to 1 (mono) on the right.
+pub struct Correlation;
+
+impl Correlation {
+    pub fn new() -> Self {
+        Correlation
+    }
+}
+
+impl Widget<f64> for Correlation {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut f64, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old: &f64, new: &f64, _env: &Env) {
+        if old != new {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(&mut self, _ctx: &mut LifeCycleCtx, _event: &LifeCycle, _data: &f64, _env: &Env) {}
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &f64,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(GONIOMETER_SIZE, CORRELATION_HEIGHT))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &f64, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_DARK));
+        ctx.stroke(bounds.inset(-0.5), &env.get(theme::BORDER_DARK), 1.0);
+
+        let x = |correlation: f64| lerp(bounds.x0, bounds.x1, 0.5 * (correlation + 1.0));
+        let correlation = data.clamp(-1.0, 1.0);
+        // anything below 0 is worth a look, so it's red.
+        let color = if correlation < 0.0 {
+            Color::rgb(0.9, 0.0, 0.0)
+        } else {
+            Color::rgb(0.0, 0.6, 0.0)
+        };
+        ctx.fill(
+            Rect::new(x(0.0), bounds.y0, x(correlation), bounds.y1).abs(),
+            &color,
+        );
+
+        let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+        let label_color = env.get(theme::LABEL_COLOR);
+        for (label, correlation) in &[("-1", -1.0), ("0", 0.0), ("+1", 1.0)] {
+            let x = x(*correlation);
+            ctx.stroke(Line::new((x, bounds.y0), (x, bounds.y1)), &grid_color, 1.0);
+            let x = x.max(bounds.x0 + 2.0).min(bounds.x1 - 12.0);
+            draw_label(ctx, label.to_string(), (x, bounds.y0 + 2.0), &label_color);
+        }
+    }
+}
+
+/// The response of the EQ of the channel picked in the EQ window, on a log frequency axis, with a
+/// numbered dot at the frequency of each band that is on.
+pub struct EqResponse;
+
+impl EqResponse {
+    pub fn new() -> Self {
+        EqResponse
+    }
+}
+
+impl Widget<State> for EqResponse {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut State, _env: &Env) {}
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old: &State, new: &State, _env: &Env) {
+        if old.eq_channel != new.eq_channel || !old.channels.same(&new.channels) {
+            ctx.request_paint();
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &State,
+        _env: &Env,
+    ) {
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &State,
+        _env: &Env,
+    ) -> Size {
+        fill(bc, EQ_RESPONSE_SIZE)
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &State, env: &Env) {
+        let bounds = ctx.size().to_rect();
+        ctx.fill(bounds, &env.get(theme::BACKGROUND_DARK));
+        ctx.stroke(bounds.inset(-0.5), &env.get(theme::BORDER_DARK), 1.0);
+
+        let bands: Vec<Band> = match data.channels.get(data.eq_channel) {
+            Some(channel) => channel.eq.iter().copied().collect(),
+            None => return,
+        };
+        let sample_rate = data.bin_width * data.fft_size as f64;
+        let nyquist = 0.5 * sample_rate;
+        let x = |freq: f64| freq_x(bounds, nyquist, freq);
+        let y = |db: f64| {
+            let db = db.clamp(-EQ_RESPONSE_DB, EQ_RESPONSE_DB);
+            lerp(bounds.y1, bounds.y0, 0.5 * (db / EQ_RESPONSE_DB + 1.0))
+        };
+
+        // grid
+        draw_freq_grid(ctx, bounds, nyquist, env);
+        let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+        let label_color = env.get(theme::LABEL_COLOR);
+        let mut db = -EQ_RESPONSE_DB;
+        while db <= EQ_RESPONSE_DB {
+            let y = y(db);
+            let width = if db == 0.0 { 2.0 } else { 1.0 };
+            ctx.stroke(
+                Line::new((bounds.x0, y), (bounds.x1, y)),
+                &grid_color,
+                width,
+            );
+            draw_label(
+                ctx,
+                format!("{:+} dB", db),
+                (bounds.x0 + 2.0, y),
+                &label_color,
+            );
+            db += EQ_DB_GRID;
+        }
+
+        // the response, worked out every couple of pixels.
+        let color = spectrum_color(120.0);
+        let mut path = BezPath::new();
+        let steps = (bounds.width() / 2.0).ceil().max(1.0) as usize;
+        for step in 0..=steps {
+            let t = step as f64 / steps as f64;
+            let freq = SPECTRA_MIN_FREQ * (nyquist / SPECTRA_MIN_FREQ).powf(t);
+            let point = Point::new(x(freq), y(eq_response(&bands, freq, sample_rate)));
+            if step == 0 {
+                path.move_to(point);
+            } else {
+                path.line_to(point);
+            }
+        }
+        ctx.stroke(path, &color, 2.0);
+
+        for (idx, band) in bands.iter().enumerate().filter(|(_, band)| band.on) {
+            let center = Point::new(x(band.freq), y(eq_response(&bands, band.freq, sample_rate)));
+            ctx.fill(Circle::new(center, 4.0), &color);
+            draw_label(
+                ctx,
+                format!("{}", idx + 1),
+                center + Vec2::new(5.0, -16.0),
+                &label_color,
+            );
+        }
+    }
+}
+
+/// Take all the space we're given, or `default` if it's unbounded.
+fn fill(bc: &BoxConstraints, default: Size) -> Size {
+    let max = bc.max();
+    bc.constrain(Size::new(
+        if bc.is_width_bounded() {
+            max.width
+        } else {
+            default.width
+        },
+        if bc.is_height_bounded() {
+            max.height
+        } else {
+            default.height
+        },
+    ))
+}
+
+/// Where `freq` goes on a log frequency axis, from `SPECTRA_MIN_FREQ` to `nyquist` across `bounds`.
+fn freq_x(bounds: Rect, nyquist: f64, freq: f64) -> f64 {
+    lerp(
+        bounds.x0,
+        bounds.x1,
+        (freq / SPECTRA_MIN_FREQ).ln() / (nyquist / SPECTRA_MIN_FREQ).ln(),
+    )
+}
+
+/// Draw the lines and labels of a log frequency axis (see `freq_x`).
+fn draw_freq_grid(ctx: &mut PaintCtx, bounds: Rect, nyquist: f64, env: &Env) {
+    let grid_color = env.get(theme::BORDER_LIGHT).with_alpha(0.3);
+    let label_color = env.get(theme::LABEL_COLOR);
+    for freq in FREQ_GRID.iter().filter(|freq| **freq < nyquist) {
+        let x = freq_x(bounds, nyquist, *freq);
+        ctx.stroke(Line::new((x, bounds.y0), (x, bounds.y1)), &grid_color, 1.0);
+        let label = if *freq >= 1000.0 {
+            format!("{}k", freq / 1000.0)
+        } else {
+            format!("{}", freq)
+        };
+        draw_label(ctx, label, (x + 2.0, bounds.y1 - 14.0), &label_color);
+    }
+}
+
+fn draw_label(ctx: &mut PaintCtx, text: String, origin: impl Into<Point>, color: &Color) {
+    let layout = ctx
+        .text()
+        .new_text_layout(text)
+        .font(FontFamily::SYSTEM_UI, 10.0)
+        .text_color(color.clone())
+        .build();
+    if let Ok(layout) = layout {
+        ctx.draw_text(&layout, origin);
+    }
+}
+
+fn spectrum_color(hue: f64) -> Color {
+    Color::hlc(hue, 70.0, 60.0)
+}
+
 /// Paint the actual physical fader that you move up and down.
 fn fader(bounds: Rect, fg_brush: &Brush, bg_brush: &Brush, ctx: &mut PaintCtx) {
     const MIDDLE_LINE_BORDER: f64 = SLIDER_HEIGHT * 0.1;
@@ -3,25 +3,44 @@ pub mod cli;
 mod data;
 pub mod effects;
 mod gui;
+pub mod metering;
 mod monitor_data;
+pub mod profile;
+mod remote;
+mod session;
+mod tui;
 
 use crossbeam_channel as channel;
-use druid::Target;
+use druid::{ExtEventSink, Target};
 use jack::Client;
-use std::{sync::Arc, thread};
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
 use structopt::StructOpt;
 
 use crate::{
     audio::{Audio, Info as AudioInfo},
     cli::{Config, Opt},
     effects::hc_to_mod,
-    gui::UiMsg,
+    gui::{Tap, UiMsg, Views, SPECTRA_FRAME},
+    metering::StereoScope,
+    monitor_data::{Monitor, MonitorReader},
+    remote::{RemoteState, UpdateSender},
+    session::Session,
 };
 
 pub type Result<T = (), E = anyhow::Error> = std::result::Result<T, E>;
 
-const LOW_CUTOFF: f32 = 200.0;
-const HIGH_CUTOFF: f32 = 2000.0;
+/// How long we wait for worker threads to stop when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The ui we are showing, and how to send it updates.
+enum Frontend {
+    Gui(ExtEventSink),
+    Tui(channel::Sender<UiMsg>),
+}
 
 /// Main programm runner.
 pub fn run_mixer(config: Arc<Config>, opts: Opt) -> Result {
@@ -37,41 +56,112 @@ pub fn run_mixer(config: Arc<Config>, opts: Opt) -> Result {
     let (tx_ui, rx_rt) = channel::bounded(1024);
     // a channel for sending updates from the RT thread to the gui.
     let (tx_rt, rx_ui) = channel::bounded(1024);
-    let tx_spectra_in = tx_rt.clone();
-    let tx_spectra_out = tx_rt.clone();
-    // a channel for finding out when the ui has shut down.
-    let (shutdown_tx, shutdown_rx) = channel::bounded(0);
+    let tx_monitors = tx_rt.clone();
+    // the same for the remote servers. We keep `tx_remote_in` even when there are no servers, so
+    // the RT thread doesn't think we've gone away.
+    let (tx_remote_in, rx_rt_remote) = channel::bounded(1024);
+    let (tx_rt_remote, rx_remote_out) = channel::bounded(1024);
+    // a channel for finding out when the ui has shut down (or we've been asked to stop when
+    // headless).
+    let (shutdown_tx, shutdown_rx) = channel::bounded(1);
 
-    let audio = Audio::setup(&*config, &client, tx_rt, rx_rt, LOW_CUTOFF, HIGH_CUTOFF)?;
-    let (audio_in_spectrum, audio_out_spectrum) =
-        audio.monitor_spectra().into_iter().next().unwrap();
-    // todo look at shutting down gracefully, whether that is necessary
-    let _async_client = client.activate_async((), audio)?;
+    let mut audio = Audio::setup(&*config, &client, tx_rt, rx_rt, tx_rt_remote, rx_rt_remote)?;
+    if let Some(path) = config.session.as_ref() {
+        Session::load(path)?.restore(&config, audio.state_mut());
+    }
+    // kept so we can stop the threads reading them.
+    let monitors: Vec<_> = audio
+        .monitor_spectra()
+        .into_iter()
+        .enumerate()
+        .flat_map(|(idx, (audio_in, audio_out))| {
+            vec![(idx, Tap::In, audio_in), (idx, Tap::Out, audio_out)]
+        })
+        .collect();
+    let stereo = audio.monitor_stereo();
+    let async_client = client.activate_async((), audio)?;
 
-    let (evt_sink, ui_handle) = gui::run(tx_ui, shutdown_tx, config)?;
+    // remote servers, and the channels we use to tell them about changes.
+    let mut servers = Vec::new();
+    let mut remotes = Vec::new();
+    if let Some(osc) = config.osc.as_ref() {
+        let state = RemoteState::new(&config);
+        let (tx_remote, rx_remote) = remote::updates(state.clone());
+        servers.push(remote::osc::spawn(
+            osc.socket_addr(),
+            state,
+            tx_remote_in.clone(),
+            rx_remote,
+        )?);
+        remotes.push(tx_remote);
+    }
+    if let Some(http) = config.http.as_ref() {
+        let state = RemoteState::new(&config);
+        let (tx_remote, rx_remote) = remote::updates(state.clone());
+        servers.push(remote::http::spawn(
+            http.socket_addr(),
+            http.websocket_addr(),
+            state,
+            tx_remote_in.clone(),
+            rx_remote,
+        )?);
+        remotes.push(tx_remote);
+    }
 
-    thread::spawn(move || {
-        audio_in_spectrum.on_changed(|spec| {
-            tx_spectra_in
-                .send(UiMsg::AudioInSpectrum(hc_to_mod(spec)))
-                .unwrap()
-        });
-    });
+    let mut workers = Vec::new();
+    let ui = if opts.headless {
+        log::info!("running headless, press ctrl-c to stop");
+        ctrlc::set_handler(move || {
+            // if we've already been asked to stop, there's nothing more to do.
+            let _ = shutdown_tx.try_send(());
+        })?;
+        None
+    } else if opts.tui {
+        let (tx_tui, ui_handle) = tui::run(tx_ui.clone(), shutdown_tx, config.clone())?;
+        Some((Frontend::Tui(tx_tui), ui_handle))
+    } else {
+        let views = Arc::new(Views::default());
+        let (evt_sink, ui_handle) = gui::run(
+            tx_ui.clone(),
+            shutdown_tx,
+            config.clone(),
+            info.sample_rate(),
+            views.clone(),
+        )?;
 
-    thread::spawn(move || {
-        audio_out_spectrum.on_changed(|spec| {
-            tx_spectra_out
-                .send(UiMsg::AudioOutSpectrum(hc_to_mod(spec)))
-                .unwrap()
-        });
-    });
+        let (monitors, stereo) = (monitors.clone(), stereo.clone());
+        workers.push(thread::spawn(move || {
+            forward_monitors(monitors, stereo, views, tx_monitors)
+        }));
+        Some((Frontend::Gui(evt_sink), ui_handle))
+    };
 
     loop {
         channel::select! {
+            recv(rx_remote_out) -> msg => {
+                let msg = msg?; // There should never be an error here.
+                forward_remote(&remotes, msg);
+            }
             recv(rx_ui) -> msg => {
-                // translate from non-blocking crossbeam::Channel to blocking to ExtEventSink
                 let msg = msg?; // There should never be an error here.
-                evt_sink.submit_command(gui::UPDATE, msg, Target::Global)?;
+                if remote::is_remote(&msg) {
+                    forward_remote(&remotes, msg.clone());
+                }
+                let sent = match ui.as_ref() {
+                    // translate from non-blocking crossbeam::Channel to blocking to ExtEventSink
+                    Some((Frontend::Gui(evt_sink), _)) => evt_sink
+                        .submit_command(gui::UPDATE, msg, Target::Global)
+                        .map_err(anyhow::Error::from),
+                    Some((Frontend::Tui(tx_tui), _)) => {
+                        tx_tui.send(msg).map_err(anyhow::Error::from)
+                    }
+                    None => Ok(()),
+                };
+                if let Err(e) = sent {
+                    // the ui has gone, so stop everything else too.
+                    log::error!("error sending update to ui: {}", e);
+                    break;
+                }
             }
             recv(shutdown_rx) -> res => {
                 // There should never be an error here.
@@ -80,6 +170,112 @@ pub fn run_mixer(config: Arc<Config>, opts: Opt) -> Result {
             }
         }
     }
-    ui_handle.join().unwrap()?;
+    log::info!("shutting down");
+
+    // stop the RT thread first, so nothing else changes while we stop everything else.
+    match async_client.deactivate() {
+        Ok((_, (), audio)) => {
+            if let Some(path) = config.session.as_ref() {
+                if let Err(e) = Session::from_state(&config, audio.state()).save(path) {
+                    log::error!("error saving session: {}", e);
+                }
+            }
+        }
+        Err(e) => log::error!("error deactivating jack client: {}", e),
+    }
+    for (_, _, monitor) in monitors.iter() {
+        monitor.shutdown();
+    }
+    for monitor in stereo.iter() {
+        monitor.shutdown();
+    }
+    for server in servers {
+        workers.extend(server.stop());
+    }
+    // the remote servers stop waiting for changes when these are gone.
+    drop(remotes);
+    join_all(workers, SHUTDOWN_TIMEOUT);
+
+    // the ui has already finished (that's why we're shutting down).
+    if let Some((_, ui_handle)) = ui {
+        ui_handle.join().unwrap()?;
+    }
     Ok(())
 }
+
+/// Pass a message on to every remote server.
+fn forward_remote(remotes: &[UpdateSender], msg: UiMsg) {
+    for tx_remote in remotes.iter() {
+        tx_remote.send(msg.clone());
+    }
+}
+
+/// Send the gui the latest spectra of every channel and readings of every stereo pair,
+/// `SPECTRA_FRAME` times a second, until the monitors are shut down.
+///
+/// Readers are only claimed while a window in `views` shows them, so the RT thread doesn't copy
+/// anything out when nobody is looking.
+fn forward_monitors(
+    monitors: Vec<(usize, Tap, Monitor<Box<[f32]>>)>,
+    stereo: Vec<Monitor<StereoScope>>,
+    views: Arc<Views>,
+    tx: channel::Sender<UiMsg>,
+) {
+    let mut monitors: Vec<_> = monitors
+        .into_iter()
+        .map(|(idx, tap, monitor)| (idx, tap, monitor, None))
+        .collect();
+    let mut stereo: Vec<_> = stereo.into_iter().map(|monitor| (monitor, None)).collect();
+    while !monitors
+        .iter()
+        .any(|(_, _, monitor, _)| monitor.is_shut_down())
+    {
+        for (idx, tap, monitor, reader) in monitors.iter_mut() {
+            let reader = claim_while(views.wants_spectra(*tap), monitor, reader);
+            if let Some(spectrum) = reader.and_then(|reader| reader.latest()) {
+                // drop spectra if the ui is falling behind, so we can always see when to stop.
+                let _ = tx.try_send(UiMsg::Spectrum(*idx, *tap, hc_to_mod(spectrum)));
+            }
+        }
+        for (idx, (monitor, reader)) in stereo.iter_mut().enumerate() {
+            let reader = claim_while(views.wants_stereo(), monitor, reader);
+            if let Some(scope) = reader.and_then(|reader| reader.latest()) {
+                let _ = tx.try_send(UiMsg::Stereo(idx, scope.clone()));
+            }
+        }
+        thread::sleep(SPECTRA_FRAME);
+    }
+}
+
+/// Hold a reader of `monitor` while `wanted`, and let it go otherwise.
+fn claim_while<'a, T>(
+    wanted: bool,
+    monitor: &Monitor<T>,
+    reader: &'a mut Option<MonitorReader<T>>,
+) -> Option<&'a mut MonitorReader<T>> {
+    if !wanted {
+        *reader = None;
+    } else if reader.is_none() {
+        *reader = monitor.reader();
+    }
+    reader.as_mut()
+}
+
+/// Wait for `threads` to finish, giving up after `timeout`.
+fn join_all(threads: Vec<JoinHandle<()>>, timeout: Duration) {
+    let (tx_done, rx_done) = channel::bounded(1);
+    thread::spawn(move || {
+        for thread in threads {
+            if thread.join().is_err() {
+                log::error!("a worker thread panicked");
+            }
+        }
+        let _ = tx_done.send(());
+    });
+    if rx_done.recv_timeout(timeout).is_err() {
+        log::warn!(
+            "worker threads didn't stop within {} seconds, giving up on them",
+            timeout.as_secs()
+        );
+    }
+}
@@ -0,0 +1,9 @@
+//! Meters that are fed audio a buffer at a time. None of them allocate once they are made, so they
+//! can be used in the RT thread.
+mod loudness;
+mod stereo;
+mod true_peak;
+
+pub use loudness::{Loudness, LoudnessMeter};
+pub use stereo::{StereoMeter, StereoScope, SCOPE_LEN};
+pub use true_peak::{TruePeak, TruePeakMeter};
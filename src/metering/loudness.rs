@@ -0,0 +1,375 @@
+//! Loudness as defined by ITU-R BS.1770 and used by EBU R128: momentary (400 ms) and short-term
+//! (3 s) loudness, gated integrated loudness, and loudness range (EBU Tech 3342).
+//!
+//! Gating needs the loudness of every block since the meter was reset. Rather than keeping them
+//! all, we count them in a histogram with 0.1 LU bins, which is much finer than anyone reads a
+//! meter.
+use crate::effects::{Biquad, Coefficients};
+use druid::Data;
+use serde::Serialize;
+use std::f64::consts::PI;
+
+/// Measurements are made every 100 ms.
+const BLOCK_SECS: f64 = 0.1;
+/// Momentary loudness is measured over 400 ms.
+const MOMENTARY_BLOCKS: usize = 4;
+/// Short-term loudness is measured over 3 s.
+const SHORT_TERM_BLOCKS: usize = 30;
+/// Blocks quieter than this are ignored by the integrated loudness and loudness range, in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks more than this far below the average are ignored by the integrated loudness, in LU.
+const INTEGRATED_GATE: f64 = -10.0;
+/// Blocks more than this far below the average are ignored by the loudness range, in LU.
+const RANGE_GATE: f64 = -20.0;
+/// The histogram covers loudnesses from `ABSOLUTE_GATE` to this, in LUFS (anything louder goes in
+/// the top bin).
+const HISTOGRAM_MAX: f64 = 10.0;
+const HISTOGRAM_STEP: f64 = 0.1;
+const HISTOGRAM_LEN: usize = ((HISTOGRAM_MAX - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+
+/// Loudness readings of a channel. Loudnesses are in LUFS, and are `-inf` until there is
+/// something to measure. The range is in LU.
+#[derive(Debug, Clone, Copy, PartialEq, Data, Serialize)]
+pub struct Loudness {
+    pub momentary: f64,
+    pub short_term: f64,
+    pub integrated: f64,
+    pub range: f64,
+}
+
+impl Default for Loudness {
+    fn default() -> Self {
+        Loudness {
+            momentary: f64::NEG_INFINITY,
+            short_term: f64::NEG_INFINITY,
+            integrated: f64::NEG_INFINITY,
+            range: 0.0,
+        }
+    }
+}
+
+/// Measures the loudness of a single channel.
+#[derive(Debug, Clone)]
+pub struct LoudnessMeter {
+    /// The K-weighting filter: a high shelf for the effect of the head, then a high pass.
+    filters: [Biquad; 2],
+    /// The number of samples in a block.
+    block_len: usize,
+    /// The number of samples in the current block so far, and their sum of squares.
+    block_pos: usize,
+    block_sum: f64,
+    /// The mean square of the last `SHORT_TERM_BLOCKS` blocks. `next_block` is the oldest.
+    blocks: [f64; SHORT_TERM_BLOCKS],
+    next_block: usize,
+    /// The number of blocks since the meter was reset, up to `SHORT_TERM_BLOCKS`.
+    blocks_seen: usize,
+    /// Momentary loudnesses, for the integrated loudness.
+    momentary: Gated,
+    /// Short-term loudnesses, for the loudness range.
+    short_term: Gated,
+    readings: Loudness,
+}
+
+impl LoudnessMeter {
+    pub fn new(sample_rate: f64) -> Self {
+        LoudnessMeter {
+            filters: [
+                Biquad::new(high_shelf(sample_rate)),
+                Biquad::new(high_pass(sample_rate)),
+            ],
+            block_len: ((sample_rate * BLOCK_SECS).round() as usize).max(1),
+            block_pos: 0,
+            block_sum: 0.0,
+            blocks: [0.0; SHORT_TERM_BLOCKS],
+            next_block: 0,
+            blocks_seen: 0,
+            momentary: Gated::new(),
+            short_term: Gated::new(),
+            readings: Loudness::default(),
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for sample in samples {
+            let mut value = *sample as f64;
+            for filter in self.filters.iter_mut() {
+                value = filter.process(value);
+            }
+            self.block_sum += value * value;
+            self.block_pos += 1;
+            if self.block_pos == self.block_len {
+                self.end_block();
+            }
+        }
+    }
+
+    /// The latest readings.
+    pub fn loudness(&self) -> Loudness {
+        self.readings
+    }
+
+    /// Start measuring again from scratch.
+    pub fn reset(&mut self) {
+        for filter in self.filters.iter_mut() {
+            filter.reset();
+        }
+        self.block_pos = 0;
+        self.block_sum = 0.0;
+        self.blocks = [0.0; SHORT_TERM_BLOCKS];
+        self.next_block = 0;
+        self.blocks_seen = 0;
+        self.momentary.reset();
+        self.short_term.reset();
+        self.readings = Loudness::default();
+    }
+
+    fn end_block(&mut self) {
+        self.blocks[self.next_block] = self.block_sum / self.block_len as f64;
+        self.next_block = (self.next_block + 1) % SHORT_TERM_BLOCKS;
+        self.blocks_seen = (self.blocks_seen + 1).min(SHORT_TERM_BLOCKS);
+        self.block_pos = 0;
+        self.block_sum = 0.0;
+
+        if self.blocks_seen >= MOMENTARY_BLOCKS {
+            let momentary = self.loudness_of_last(MOMENTARY_BLOCKS);
+            self.momentary.add(momentary);
+            self.readings.momentary = momentary;
+            self.readings.integrated = self.momentary.integrated();
+        }
+        if self.blocks_seen >= SHORT_TERM_BLOCKS {
+            let short_term = self.loudness_of_last(SHORT_TERM_BLOCKS);
+            self.short_term.add(short_term);
+            self.readings.short_term = short_term;
+            self.readings.range = self.short_term.range();
+        }
+    }
+
+    /// The loudness of the last `count` blocks.
+    fn loudness_of_last(&self, count: usize) -> f64 {
+        let sum: f64 = (1..=count)
+            .map(|age| (self.next_block + SHORT_TERM_BLOCKS - age) % SHORT_TERM_BLOCKS)
+            .map(|idx| self.blocks[idx])
+            .sum();
+        loudness(sum / count as f64)
+    }
+}
+
+/// The loudnesses of the blocks above the absolute gate.
+#[derive(Debug, Clone)]
+struct Gated {
+    /// The blocks in each `HISTOGRAM_STEP` from `ABSOLUTE_GATE`.
+    histogram: Box<[Bin]>,
+    /// The total power of the blocks, and how many there are.
+    power: f64,
+    count: u64,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct Bin {
+    count: u32,
+    /// The total power of the blocks in the bin, so the integrated loudness doesn't depend on
+    /// where in their bins the blocks were.
+    power: f64,
+}
+
+impl Gated {
+    fn new() -> Self {
+        Gated {
+            histogram: vec![Bin::default(); HISTOGRAM_LEN].into_boxed_slice(),
+            power: 0.0,
+            count: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        for bin in self.histogram.iter_mut() {
+            *bin = Bin::default();
+        }
+        self.power = 0.0;
+        self.count = 0;
+    }
+
+    fn add(&mut self, loudness: f64) {
+        if loudness <= ABSOLUTE_GATE {
+            return;
+        }
+        let idx = ((loudness - ABSOLUTE_GATE) / HISTOGRAM_STEP) as usize;
+        let bin = &mut self.histogram[idx.min(HISTOGRAM_LEN - 1)];
+        bin.count += 1;
+        bin.power += power(loudness);
+        self.power += power(loudness);
+        self.count += 1;
+    }
+
+    /// The loudness at the middle of each bin, with the bin, for the bins above a relative gate
+    /// `gate` LU from the average loudness.
+    fn above(&self, gate: f64) -> impl Iterator<Item = (f64, Bin)> + '_ {
+        let gate = loudness(self.power / self.count as f64) + gate;
+        self.histogram
+            .iter()
+            .enumerate()
+            .map(|(idx, bin)| (ABSOLUTE_GATE + (idx as f64 + 0.5) * HISTOGRAM_STEP, *bin))
+            .filter(move |(loudness, bin)| *loudness > gate && bin.count > 0)
+    }
+
+    /// The integrated loudness, from momentary loudnesses.
+    fn integrated(&self) -> f64 {
+        if self.count == 0 {
+            return f64::NEG_INFINITY;
+        }
+        let (power, count) = self
+            .above(INTEGRATED_GATE)
+            .fold((0.0, 0), |(power, count), (_, bin)| {
+                (power + bin.power, count + bin.count)
+            });
+        loudness(power / count as f64)
+    }
+
+    /// The loudness range (the spread between the 10th and 95th percentiles), from short-term
+    /// loudnesses.
+    fn range(&self) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let total: u32 = self.above(RANGE_GATE).map(|(_, bin)| bin.count).sum();
+        if total == 0 {
+            return 0.0;
+        }
+        let percentile = |fraction: f64| {
+            let idx = ((total - 1) as f64 * fraction).round() as u32;
+            let mut seen = 0;
+            self.above(RANGE_GATE)
+                .find(|(_, bin)| {
+                    seen += bin.count;
+                    seen > idx
+                })
+                .map_or(0.0, |(loudness, _)| loudness)
+        };
+        percentile(0.95) - percentile(0.1)
+    }
+}
+
+/// The loudness of a K-weighted mean square, in LUFS.
+fn loudness(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+/// The K-weighted mean square for a loudness.
+fn power(loudness: f64) -> f64 {
+    10f64.powf((loudness + 0.691) / 10.0)
+}
+
+/// The high shelf of the K-weighting filter. The coefficients for the K-weighting filters are
+/// worked out for the sample rate from the analogue prototypes of the filters in BS.1770 (which
+/// only lists them for 48 kHz).
+fn high_shelf(sample_rate: f64) -> Coefficients {
+    const FREQ: f64 = 1_681.974_450_955_533;
+    const GAIN_DB: f64 = 3.999_843_853_973_347;
+    const Q: f64 = 0.707_175_236_955_419_6;
+    let k = (PI * FREQ / sample_rate).tan();
+    let vh = 10f64.powf(GAIN_DB / 20.0);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    Coefficients::new(
+        [
+            vh + vb * k / Q + k * k,
+            2.0 * (k * k - vh),
+            vh - vb * k / Q + k * k,
+        ],
+        [
+            1.0 + k / Q + k * k,
+            2.0 * (k * k - 1.0),
+            1.0 - k / Q + k * k,
+        ],
+    )
+}
+
+/// The high pass of the K-weighting filter. Unlike the rest of the coefficients, `b` is not
+/// normalized in BS.1770, so we leave it alone too.
+fn high_pass(sample_rate: f64) -> Coefficients {
+    const FREQ: f64 = 38.135_470_876_024_44;
+    const Q: f64 = 0.500_327_037_323_877_3;
+    let k = (PI * FREQ / sample_rate).tan();
+    let a0 = 1.0 + k / Q + k * k;
+    Coefficients {
+        b: [1.0, -2.0, 1.0],
+        a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / Q + k * k) / a0],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{high_pass, high_shelf, LoudnessMeter};
+    use std::f64::consts::PI;
+
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    /// `secs` of a 1 kHz sine at `db` dBFS.
+    fn sine(db: f64, secs: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(db / 20.0);
+        (0..(secs * SAMPLE_RATE) as usize)
+            .map(|idx| (amplitude * (2.0 * PI * 1000.0 * idx as f64 / SAMPLE_RATE).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn k_weighting() {
+        // the coefficients in BS.1770.
+        let shelf = high_shelf(SAMPLE_RATE);
+        let expected_b = [
+            1.535_124_859_586_97,
+            -2.691_696_189_406_38,
+            1.198_392_810_852_85,
+        ];
+        let expected_a = [-1.690_659_293_182_41, 0.732_480_774_215_85];
+        for (coeff, expected) in shelf
+            .b
+            .iter()
+            .chain(&shelf.a)
+            .zip(expected_b.iter().chain(&expected_a))
+        {
+            assert!((coeff - expected).abs() < 1e-8, "{} != {}", coeff, expected);
+        }
+        let expected_a = [-1.990_047_454_833_98, 0.990_072_250_366_21];
+        for (coeff, expected) in high_pass(SAMPLE_RATE).a.iter().zip(expected_a.iter()) {
+            assert!((coeff - expected).abs() < 1e-8, "{} != {}", coeff, expected);
+        }
+    }
+
+    #[test]
+    fn steady_sine() {
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        // a 1 kHz sine at -20 dBFS is -23 LUFS (in one channel).
+        for chunk in sine(-20.0, 5.0).chunks(256) {
+            meter.process(chunk);
+        }
+        let loudness = meter.loudness();
+        for reading in &[loudness.momentary, loudness.short_term, loudness.integrated] {
+            assert!((reading + 23.0).abs() < 0.1, "{}", reading);
+        }
+        assert!(loudness.range < 0.2);
+
+        meter.reset();
+        assert_eq!(meter.loudness().integrated, f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn gating_and_range() {
+        // EBU Tech 3342 test 1 (in one channel): 20 s at -20 dBFS then 20 s at -30 dBFS has a
+        // range of 10 LU.
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        meter.process(&sine(-20.0, 20.0));
+        meter.process(&sine(-30.0, 20.0));
+        let range = meter.loudness().range;
+        assert!((range - 10.0).abs() < 1.0, "{}", range);
+
+        // silence doesn't change the integrated loudness (much: the blocks as the sound stops
+        // still count).
+        let mut meter = LoudnessMeter::new(SAMPLE_RATE);
+        meter.process(&sine(-20.0, 10.0));
+        let before = meter.loudness().integrated;
+        meter.process(&vec![0.0; 10 * SAMPLE_RATE as usize]);
+        let loudness = meter.loudness();
+        assert!((loudness.integrated - before).abs() < 0.1);
+        assert_eq!(loudness.momentary, f64::NEG_INFINITY);
+    }
+}
@@ -0,0 +1,131 @@
+//! Metering a stereo pair: the phase correlation between left and right, and the latest samples
+//! for a goniometer (vector scope).
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// How many samples the goniometer shows.
+pub const SCOPE_LEN: usize = 1024;
+/// How long the correlation is averaged over, in seconds.
+const CORRELATION_TIME: f64 = 0.3;
+/// Below this power (about -100 dBFS) we call a channel silent, and the correlation 0.
+const SILENCE: f64 = 1e-10;
+
+/// The readings for a stereo pair, as published to the ui.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StereoScope {
+    /// Between 1 (the same on both sides) and -1 (one side is the other upside down). Unrelated
+    /// signals read 0, as does silence.
+    pub correlation: f32,
+    /// The latest `SCOPE_LEN` samples as `(side, mid)` points, oldest first. Mono is straight up
+    /// and down, the left channel on its own leans left, and the right leans right.
+    pub points: Box<[(f32, f32)]>,
+}
+
+impl Default for StereoScope {
+    fn default() -> Self {
+        StereoScope {
+            correlation: 0.0,
+            points: vec![(0.0, 0.0); SCOPE_LEN].into_boxed_slice(),
+        }
+    }
+}
+
+/// Measures a stereo pair.
+#[derive(Debug, Clone)]
+pub struct StereoMeter {
+    /// How much of each sample goes into the running averages.
+    coeff: f64,
+    /// Running averages of left * right, left² and right².
+    lr: f64,
+    ll: f64,
+    rr: f64,
+    /// The latest points, with the oldest at `next`.
+    points: Box<[(f32, f32)]>,
+    next: usize,
+}
+
+impl StereoMeter {
+    pub fn new(sample_rate: f64) -> Self {
+        StereoMeter {
+            coeff: 1.0 - (-1.0 / (CORRELATION_TIME * sample_rate)).exp(),
+            lr: 0.0,
+            ll: 0.0,
+            rr: 0.0,
+            points: vec![(0.0, 0.0); SCOPE_LEN].into_boxed_slice(),
+            next: 0,
+        }
+    }
+
+    pub fn process(&mut self, left: &[f32], right: &[f32]) {
+        for (l, r) in left.iter().zip(right.iter()) {
+            let (l64, r64) = (*l as f64, *r as f64);
+            self.lr += (l64 * r64 - self.lr) * self.coeff;
+            self.ll += (l64 * l64 - self.ll) * self.coeff;
+            self.rr += (r64 * r64 - self.rr) * self.coeff;
+            self.points[self.next] = ((r - l) * FRAC_1_SQRT_2, (l + r) * FRAC_1_SQRT_2);
+            self.next = (self.next + 1) % SCOPE_LEN;
+        }
+    }
+
+    pub fn correlation(&self) -> f64 {
+        if self.ll < SILENCE || self.rr < SILENCE {
+            0.0
+        } else {
+            (self.lr / (self.ll * self.rr).sqrt()).clamp(-1.0, 1.0)
+        }
+    }
+
+    /// Overwrite `scope` with the latest readings, without allocating.
+    pub fn write_scope(&self, scope: &mut StereoScope) {
+        scope.correlation = self.correlation() as f32;
+        let (newest, oldest) = self.points.split_at(self.next);
+        scope.points[..oldest.len()].copy_from_slice(oldest);
+        scope.points[oldest.len()..].copy_from_slice(newest);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{StereoMeter, StereoScope, SCOPE_LEN};
+    use std::f64::consts::PI;
+
+    fn sine(phase: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|idx| (0.5 * (2.0 * PI * 1000.0 * idx as f64 / 48000.0 + phase).sin()) as f32)
+            .collect()
+    }
+
+    fn correlation(left: &[f32], right: &[f32]) -> f64 {
+        let mut meter = StereoMeter::new(48000.0);
+        meter.process(left, right);
+        meter.correlation()
+    }
+
+    #[test]
+    fn correlation_readings() {
+        let left = sine(0.0, 48000);
+        let inverted: Vec<f32> = left.iter().map(|sample| -sample).collect();
+        assert!((correlation(&left, &left) - 1.0).abs() < 1e-6);
+        assert!((correlation(&left, &inverted) + 1.0).abs() < 1e-6);
+        assert!(correlation(&left, &sine(PI / 2.0, 48000)).abs() < 0.01);
+        assert_eq!(correlation(&left, &[0.0; 48000]), 0.0);
+        assert_eq!(correlation(&[0.0; 100], &[0.0; 100]), 0.0);
+    }
+
+    #[test]
+    fn scope() {
+        let mut meter = StereoMeter::new(48000.0);
+        let mut scope = StereoScope::default();
+        // the left on its own leans left.
+        meter.process(&[0.5; 10], &[0.0; 10]);
+        meter.write_scope(&mut scope);
+        let (side, mid) = scope.points[SCOPE_LEN - 1];
+        assert!(side < 0.0 && (side + mid).abs() < 1e-6);
+        assert_eq!(scope.points[SCOPE_LEN - 11], (0.0, 0.0));
+        // oldest first, wherever the ring has got to.
+        let ramp: Vec<f32> = (0..SCOPE_LEN + 10).map(|idx| idx as f32).collect();
+        meter.process(&ramp, &ramp);
+        meter.write_scope(&mut scope);
+        assert!(scope.points.windows(2).all(|pair| pair[0].1 < pair[1].1));
+        assert_eq!(scope.points[0].0, 0.0);
+    }
+}
@@ -0,0 +1,197 @@
+//! True-peak metering as in ITU-R BS.1770 annex 2: the signal is oversampled 4 times, so we catch
+//! peaks between samples that a sample peak meter misses (and that will clip after conversion to
+//! analogue or a lossy codec).
+use druid::Data;
+use serde::Serialize;
+
+const TAPS: usize = 12;
+/// The interpolation filter from BS.1770, split into the 4 phases (the 4 samples made from each
+/// input sample). `PHASES[k][i]` multiplies the input sample `i` samples ago.
+const PHASES: [[f32; TAPS]; 4] = [
+    [
+        0.001_708_984_4,
+        0.010_986_328,
+        -0.019_653_32,
+        0.033_203_125,
+        -0.059_448_242,
+        0.137_329_1,
+        0.972_167_97,
+        -0.102_294_92,
+        0.047_607_42,
+        -0.026_611_328,
+        0.014_892_578,
+        -0.008_300_781,
+    ],
+    [
+        -0.029_174_805,
+        0.029_296_875,
+        -0.051_757_812,
+        0.089_111_33,
+        -0.166_503_9,
+        0.465_087_9,
+        0.779_785_16,
+        -0.200_317_38,
+        0.101_562_5,
+        -0.058_227_54,
+        0.033_081_055,
+        -0.018_920_898,
+    ],
+    [
+        -0.018_920_898,
+        0.033_081_055,
+        -0.058_227_54,
+        0.101_562_5,
+        -0.200_317_38,
+        0.779_785_16,
+        0.465_087_9,
+        -0.166_503_9,
+        0.089_111_33,
+        -0.051_757_812,
+        0.029_296_875,
+        -0.029_174_805,
+    ],
+    [
+        -0.008_300_781,
+        0.014_892_578,
+        -0.026_611_328,
+        0.047_607_42,
+        -0.102_294_92,
+        0.972_167_97,
+        0.137_329_1,
+        -0.059_448_242,
+        0.033_203_125,
+        -0.019_653_32,
+        0.010_986_328,
+        0.001_708_984_4,
+    ],
+];
+
+/// True-peak readings of a signal. Peaks are linear (1 is full scale).
+#[derive(Debug, Clone, Copy, PartialEq, Data, Default, Serialize)]
+pub struct TruePeak {
+    /// The highest peak since the last reading.
+    pub peak: f64,
+    /// The highest peak since the meter was reset.
+    pub max: f64,
+    /// The number of overs (times the signal went above full scale) since the meter was reset.
+    pub overs: u64,
+}
+
+/// Measures the true peak of a single channel.
+#[derive(Debug, Clone, Default)]
+pub struct TruePeakMeter {
+    /// The last `TAPS` samples, twice over so they are always in one slice. The newest is at
+    /// `next - 1` (wrapping).
+    history: [f32; 2 * TAPS],
+    next: usize,
+    /// Whether the signal is above full scale.
+    over: bool,
+    readings: TruePeak,
+}
+
+impl TruePeakMeter {
+    pub fn new() -> Self {
+        TruePeakMeter::default()
+    }
+
+    pub fn process(&mut self, samples: &[f32]) {
+        for sample in samples {
+            self.sample(*sample);
+        }
+    }
+
+    pub fn sample(&mut self, sample: f32) {
+        self.history[self.next] = sample;
+        self.history[self.next + TAPS] = sample;
+        self.next = (self.next + 1) % TAPS;
+        // oldest first, so reversed against the filter.
+        let history = &self.history[self.next..self.next + TAPS];
+        let mut peak = 0f32;
+        for phase in PHASES.iter() {
+            let value: f32 = phase
+                .iter()
+                .zip(history.iter().rev())
+                .map(|(coeff, sample)| coeff * sample)
+                .sum();
+            peak = peak.max(value.abs());
+        }
+        let peak = peak as f64;
+        let over = peak > 1.0;
+        if over && !self.over {
+            self.readings.overs += 1;
+        }
+        self.over = over;
+        self.readings.peak = self.readings.peak.max(peak);
+        self.readings.max = self.readings.max.max(peak);
+    }
+
+    /// The latest readings.
+    pub fn true_peak(&self) -> TruePeak {
+        self.readings
+    }
+
+    /// Start a new reading of `TruePeak::peak`.
+    pub fn clear_peak(&mut self) {
+        self.readings.peak = 0.0;
+    }
+
+    /// Forget the maximum and the overs.
+    pub fn reset(&mut self) {
+        self.readings = TruePeak::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TruePeakMeter;
+    use std::f64::consts::PI;
+
+    /// A sine at a quarter of the sample rate, sampled 45 degrees away from its peaks, so every
+    /// sample is at `amplitude / sqrt(2)`.
+    fn quarter_rate_sine(amplitude: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|idx| (amplitude * (PI / 2.0 * idx as f64 + PI / 4.0).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn dc() {
+        let mut meter = TruePeakMeter::new();
+        meter.process(&[0.5; 100]);
+        // the step at the start really does overshoot, so only look at the steady part.
+        meter.clear_peak();
+        meter.process(&[0.5; 100]);
+        let peak = meter.true_peak().peak;
+        assert!((peak - 0.5).abs() < 0.01, "{}", peak);
+    }
+
+    #[test]
+    fn inter_sample_peaks() {
+        let mut meter = TruePeakMeter::new();
+        let signal = quarter_rate_sine(0.9, 1000);
+        meter.process(&signal);
+        let sample_peak = signal.iter().fold(0f32, |max, s| max.max(s.abs()));
+        let true_peak = meter.true_peak().max;
+        assert!(sample_peak < 0.65);
+        // within 0.5 dB.
+        assert!(true_peak > 0.85 && true_peak < 0.95, "{}", true_peak);
+        assert_eq!(meter.true_peak().overs, 0);
+        meter.process(&[0.0; 100]);
+        meter.clear_peak();
+        meter.process(&[0.0; 100]);
+        assert_eq!(meter.true_peak().peak, 0.0);
+        assert_eq!(meter.true_peak().max, true_peak);
+    }
+
+    #[test]
+    fn overs() {
+        let mut meter = TruePeakMeter::new();
+        // no sample is over, but the signal between them is (twice per cycle).
+        meter.process(&quarter_rate_sine(1.2, 100));
+        let overs = meter.true_peak().overs;
+        assert!((40..=50).contains(&overs), "{}", overs);
+        meter.reset();
+        assert_eq!(meter.true_peak().overs, 0);
+        assert_eq!(meter.true_peak().max, 0.0);
+    }
+}
@@ -1,66 +1,374 @@
-use parking_lot::{Condvar, Mutex};
-use std::sync::{atomic::AtomicU64, Arc};
+//! Sharing the latest value of something measured in the RT thread (e.g. a spectrum) with other
+//! threads, without the RT thread ever waiting for them.
+//!
+//! The RT thread owns a `MonitorData`, and publishes values with `update`. Other threads get a
+//! `Monitor` (which can be cloned), and from that a `MonitorReader` each. Every reader has its own
+//! triple buffer, so readers never see a half-written value, never hold each other up, and always
+//! see the latest value (values published while a reader is busy are skipped).
+use crossbeam_channel as channel;
+use sync::{Arc, AtomicBool, AtomicU8, Ordering, UnsafeCell};
 
-/// A way of sharing data between the RT thread and the UI thread such that the RT thread never
-/// blocks.
-///
-/// Only works with 1 waiter & 1 updater.
-#[derive(Clone)]
+/// The most readers a value can have at once. Buffers for each one are allocated up front, so the
+/// RT thread never has to.
+pub const MAX_READERS: usize = 4;
+
+/// Set in `TripleBuffer::middle` when the middle buffer holds a value the reader hasn't seen.
+const FRESH: u8 = 0b100;
+const INDEX: u8 = 0b011;
+
+/// The writing side, owned by the RT thread.
 pub struct MonitorData<T> {
-    inner: Arc<Shared<T>>,
+    shared: Arc<Shared<T>>,
+}
+
+/// A handle for making readers, and for stopping them.
+pub struct Monitor<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// One reader of the value.
+pub struct MonitorReader<T> {
+    shared: Arc<Shared<T>>,
+    slot: usize,
 }
 
 struct Shared<T> {
-    /// data (usize is generation number)
-    data: Mutex<Inner<T>>,
-    /// waker
-    waker: Condvar,
+    slots: Vec<Slot<T>>,
+    shutdown: AtomicBool,
 }
 
-struct Inner<T> {
-    value: T,
-    new_data: bool,
-    shutdown: bool,
+/// The buffers for a reader.
+struct Slot<T> {
+    /// Whether a reader is using this slot.
+    claimed: AtomicBool,
+    buffer: TripleBuffer<T>,
+    /// Wakes the reader. This is never waited on by the writer: if it's full, the reader has
+    /// already been told there is something new.
+    notify_tx: channel::Sender<()>,
+    notify_rx: channel::Receiver<()>,
 }
 
-impl<T> MonitorData<T> {
-    pub fn new(inner: T) -> Self {
+impl<T: Clone> MonitorData<T> {
+    pub fn new(value: T) -> Self {
+        let slots = (0..MAX_READERS)
+            .map(|_| {
+                let (notify_tx, notify_rx) = channel::bounded(1);
+                Slot {
+                    claimed: AtomicBool::new(false),
+                    buffer: TripleBuffer::new(value.clone()),
+                    notify_tx,
+                    notify_rx,
+                }
+            })
+            .collect();
         MonitorData {
-            inner: Arc::new(Shared {
-                data: Mutex::new(Inner {
-                    value: inner,
-                    new_data: false,
-                    shutdown: false,
-                }),
-                waker: Condvar::new(),
+            shared: Arc::new(Shared {
+                slots,
+                shutdown: AtomicBool::new(false),
             }),
         }
     }
+}
+
+impl<T> MonitorData<T> {
+    /// Publish a new value to every reader, and wake them.
+    ///
+    /// `cb` is called once for each reader, with a buffer holding an older value that it must
+    /// overwrite completely. This never blocks or allocates.
+    pub fn update(&mut self, mut cb: impl FnMut(&mut T)) {
+        for slot in self.shared.slots.iter() {
+            if !slot.claimed.load(Ordering::Acquire) {
+                continue;
+            }
+            // Safe: we are the only writer (we have `&mut self`, and `MonitorData` isn't `Clone`).
+            unsafe { slot.buffer.write(&mut cb) };
+            let _ = slot.notify_tx.try_send(());
+        }
+    }
+
+    pub fn monitor(&self) -> Monitor<T> {
+        Monitor {
+            shared: self.shared.clone(),
+        }
+    }
+}
 
-    /// Add an update and inc. the gen number. If the mutex is locked then skip over.
-    pub fn update(&self, cb: impl FnOnce(&mut T)) {
-        let mut data = match self.inner.data.try_lock() {
-            Some(lock) => lock,
-            // we couldn't get a lock, try again on next frame
-            None => return,
+impl<T> Monitor<T> {
+    /// Start reading the value, or `None` if there are already `MAX_READERS` readers.
+    pub fn reader(&self) -> Option<MonitorReader<T>> {
+        let slot = self.shared.slots.iter().position(|slot| {
+            slot.claimed
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        })?;
+        let mut reader = MonitorReader {
+            shared: self.shared.clone(),
+            slot,
         };
-        data.new_data = true;
-        cb(&mut data.value);
-        self.inner.waker.notify_one();
+        // don't report anything left over from the last reader of this slot.
+        reader.latest();
+        while reader.slot().notify_rx.try_recv().is_ok() {}
+        Some(reader)
+    }
+
+    /// Make every reader's `on_changed` return. Safe to call from any thread except the RT
+    /// thread.
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        for slot in self.shared.slots.iter() {
+            let _ = slot.notify_tx.try_send(());
+        }
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Clone for Monitor<T> {
+    fn clone(&self) -> Self {
+        Monitor {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> MonitorReader<T> {
+    fn slot(&self) -> &Slot<T> {
+        &self.shared.slots[self.slot]
     }
 
-    /// Wait until prev_gen < current generation, then update prev_gen to gen and call cb.
-    pub fn on_changed(&self, mut cb: impl FnMut(&T)) {
-        let mut data = self.inner.data.lock();
-        loop {
-            if data.shutdown {
-                break;
+    /// The latest value, if it has changed since we last looked.
+    pub fn latest(&mut self) -> Option<&T> {
+        let buffer = &self.shared.slots[self.slot].buffer;
+        // Safe: we have claimed the slot, so we are its only reader.
+        unsafe {
+            if buffer.read() {
+                Some(buffer.front())
+            } else {
+                None
             }
-            if !data.new_data {
-                self.inner.waker.wait(&mut data);
+        }
+    }
+
+    /// Whether `Monitor::shutdown` has been called, for readers that poll rather than wait.
+    pub fn is_shut_down(&self) -> bool {
+        self.shared.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Wait for the value to change, and call `cb` with it, until `Monitor::shutdown` is called.
+    pub fn on_changed(&mut self, mut cb: impl FnMut(&T)) {
+        while !self.shared.shutdown.load(Ordering::SeqCst) {
+            if let Some(value) = self.latest() {
+                cb(value);
+                continue;
             }
-            cb(&data.value);
-            data.new_data = false;
+            // we hold a receiver, so this can't fail.
+            let _ = self.slot().notify_rx.recv();
         }
     }
 }
+
+impl<T> Drop for MonitorReader<T> {
+    fn drop(&mut self) {
+        self.slot().claimed.store(false, Ordering::Release);
+    }
+}
+
+/// A single writer, single reader triple buffer.
+///
+/// The writer and reader each own one buffer, and swap it with the one in the middle. `middle`
+/// holds the index of the middle buffer, and `FRESH` if the writer put it there after the reader
+/// last took it.
+struct TripleBuffer<T> {
+    buffers: [UnsafeCell<T>; 3],
+    middle: AtomicU8,
+    /// The writer's buffer, only touched by the writer.
+    back: UnsafeCell<u8>,
+    /// The reader's buffer, only touched by the reader.
+    front: UnsafeCell<u8>,
+}
+
+// The buffers are only ever accessed by one thread at a time, which `middle` arbitrates.
+unsafe impl<T: Send> Sync for TripleBuffer<T> {}
+
+impl<T: Clone> TripleBuffer<T> {
+    fn new(value: T) -> Self {
+        TripleBuffer {
+            buffers: [
+                UnsafeCell::new(value.clone()),
+                UnsafeCell::new(value.clone()),
+                UnsafeCell::new(value),
+            ],
+            middle: AtomicU8::new(1),
+            back: UnsafeCell::new(0),
+            front: UnsafeCell::new(2),
+        }
+    }
+}
+
+impl<T> TripleBuffer<T> {
+    /// Fill the back buffer with `cb`, and swap it into the middle.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may write.
+    unsafe fn write(&self, cb: impl FnOnce(&mut T)) {
+        let back = self.back.with(|back| *back);
+        self.buffers[back as usize].with_mut(|value| cb(&mut *value));
+        let prev = self.middle.swap(back | FRESH, Ordering::AcqRel);
+        self.back.with_mut(|back| *back = prev & INDEX);
+    }
+
+    /// If there's a fresh value in the middle, swap it to the front. Returns whether we did.
+    ///
+    /// # Safety
+    ///
+    /// Only one thread may read (calling `read` or `front`).
+    unsafe fn read(&self) -> bool {
+        if self.middle.load(Ordering::Relaxed) & FRESH == 0 {
+            return false;
+        }
+        let front = self.front.with(|front| *front);
+        let prev = self.middle.swap(front, Ordering::AcqRel);
+        self.front.with_mut(|front| *front = prev & INDEX);
+        true
+    }
+
+    /// # Safety
+    ///
+    /// As `read`.
+    unsafe fn front(&self) -> &T {
+        let front = self.front.with(|front| *front);
+        self.buffers[front as usize].with(|value| &*value)
+    }
+}
+
+/// Swap in loom's versions of the primitives when checking the triple buffer with loom (run
+/// `RUSTFLAGS="--cfg loom" cargo test --release monitor_data`).
+mod sync {
+    #[cfg(loom)]
+    pub use loom::{
+        cell::UnsafeCell,
+        sync::{
+            atomic::{AtomicBool, AtomicU8, Ordering},
+            Arc,
+        },
+    };
+    #[cfg(not(loom))]
+    pub use std::sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        Arc,
+    };
+
+    /// `std::cell::UnsafeCell` with loom's api.
+    #[cfg(not(loom))]
+    pub struct UnsafeCell<T>(std::cell::UnsafeCell<T>);
+
+    #[cfg(not(loom))]
+    impl<T> UnsafeCell<T> {
+        pub fn new(value: T) -> Self {
+            UnsafeCell(std::cell::UnsafeCell::new(value))
+        }
+
+        pub fn with<R>(&self, f: impl FnOnce(*const T) -> R) -> R {
+            f(self.0.get())
+        }
+
+        pub fn with_mut<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+            f(self.0.get())
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod test {
+    use super::{MonitorData, MAX_READERS};
+    use std::thread;
+
+    #[test]
+    fn readers() {
+        let mut data = MonitorData::new(0);
+        let monitor = data.monitor();
+        let mut first = monitor.reader().unwrap();
+        let mut second = monitor.reader().unwrap();
+        assert_eq!(first.latest(), None);
+
+        data.update(|value| *value = 1);
+        data.update(|value| *value = 2);
+        // readers only see the latest value, once.
+        assert_eq!(first.latest(), Some(&2));
+        assert_eq!(first.latest(), None);
+        data.update(|value| *value = 3);
+        assert_eq!(first.latest(), Some(&3));
+        assert_eq!(second.latest(), Some(&3));
+
+        // slots are reused when readers are dropped.
+        let others: Vec<_> = (2..MAX_READERS)
+            .map(|_| monitor.reader().unwrap())
+            .collect();
+        assert!(monitor.reader().is_none());
+        drop(first);
+        let mut third = monitor.reader().unwrap();
+        assert_eq!(third.latest(), None);
+        drop(others);
+    }
+
+    #[test]
+    fn shutdown() {
+        let mut data = MonitorData::new(0);
+        let monitor = data.monitor();
+        let mut reader = monitor.reader().unwrap();
+        let handle = thread::spawn(move || {
+            let mut seen = Vec::new();
+            reader.on_changed(|value| seen.push(*value));
+            seen
+        });
+        for value in 1..=100 {
+            data.update(|v| *v = value);
+        }
+        monitor.shutdown();
+        let seen = handle.join().unwrap();
+        // we may skip values, but never go backwards.
+        assert!(seen.windows(2).all(|w| w[0] < w[1]));
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_test {
+    use super::TripleBuffer;
+    use loom::{sync::Arc, thread};
+
+    #[test]
+    fn triple_buffer() {
+        loom::model(|| {
+            let buffer = Arc::new(TripleBuffer::new([0usize; 2]));
+            let writer = buffer.clone();
+            let handle = thread::spawn(move || {
+                for value in 1..=3 {
+                    // both halves are always written together.
+                    unsafe { writer.write(|v| *v = [value, value]) };
+                }
+            });
+            let mut last = 0;
+            for _ in 0..3 {
+                unsafe {
+                    if buffer.read() {
+                        let [a, b] = *buffer.front();
+                        assert_eq!(a, b, "torn read");
+                        assert!(a > last, "went backwards");
+                        last = a;
+                    }
+                }
+            }
+            handle.join().unwrap();
+            // once the writer has finished, the reader sees the final value.
+            unsafe {
+                if buffer.read() {
+                    last = buffer.front()[0];
+                }
+            }
+            assert_eq!(last, 3);
+        });
+    }
+}
@@ -0,0 +1,166 @@
+//! Controller profiles: declarative descriptions of midi controllers, so we can support new
+//! hardware without writing a driver.
+//!
+//! A profile lists the strips of the device in order. Strip `n` controls mixer channel `n`, and
+//! each strip says which control sets the gain, which controls set the parameters of the EQ
+//! bands and which buttons toggle mute and bypass, along with the raw midi to send to light the
+//! button LEDs. Mappings in the config file take priority over the profile. See `profiles/` for
+//! examples.
+use crate::{
+    cli::{BandMapping, Mapping},
+    effects::EQ_BANDS,
+    Result,
+};
+use anyhow::{format_err, Context};
+use serde::Deserialize;
+use std::{ffi::OsStr, fs, path::Path};
+
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Profile {
+    /// A name for the device, used for logging.
+    #[serde(default)]
+    pub name: String,
+    /// Raw midi messages to send to the device when we start (e.g. to put it in a mode where the
+    /// LEDs are controlled externally).
+    #[serde(default)]
+    pub init: Vec<Vec<u8>>,
+    pub strips: Vec<Strip>,
+}
+
+impl Profile {
+    /// Load a profile from a RON file (if the extension is `.ron`) or a TOML file (otherwise).
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = fs::read(path)
+            .with_context(|| format!("could not read profile at \"{}\"", path.display()))?;
+        let profile: Profile = if path.extension() == Some(OsStr::new("ron")) {
+            ron::de::from_bytes(&raw)?
+        } else {
+            toml::from_slice(&raw)?
+        };
+        profile
+            .check()
+            .with_context(|| format!("invalid profile at \"{}\"", path.display()))?;
+        log::info!(
+            "using controller profile \"{}\" from \"{}\"",
+            profile.name,
+            path.display()
+        );
+        Ok(profile)
+    }
+
+    /// Check the things serde can't. The LED messages are sent from the RT thread, where one that
+    /// isn't midi would stop the mixer.
+    fn check(&self) -> Result {
+        for (idx, message) in self.init.iter().enumerate() {
+            if !is_midi(message) {
+                return Err(format_err!(
+                    "init message {} isn't midi: {:?}",
+                    idx,
+                    message
+                ));
+            }
+        }
+        for (idx, strip) in self.strips.iter().enumerate() {
+            if strip.eq.len() > EQ_BANDS {
+                return Err(format_err!(
+                    "strip {} has mappings for more than {} EQ bands",
+                    idx,
+                    EQ_BANDS
+                ));
+            }
+            for (name, led) in &[
+                ("mute_led", &strip.mute_led),
+                ("bypass_led", &strip.bypass_led),
+            ] {
+                let led = match led {
+                    Some(led) => led,
+                    None => continue,
+                };
+                for (state, message) in &[("on", &led.on), ("off", &led.off)] {
+                    if !is_midi(message) {
+                        return Err(format_err!(
+                            "the `{}` message of `{}` on strip {} isn't midi: {:?}",
+                            state,
+                            name,
+                            idx,
+                            message
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the profile has any LEDs we can drive.
+    pub fn has_leds(&self) -> bool {
+        !self.init.is_empty()
+            || self
+                .strips
+                .iter()
+                .any(|strip| strip.mute_led.is_some() || strip.bypass_led.is_some())
+    }
+}
+
+/// The controls for one mixer channel.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Strip {
+    pub gain: Option<Mapping>,
+    /// Controls for each band of the EQ, in order.
+    #[serde(default)]
+    pub eq: Vec<BandMapping>,
+    pub mute: Option<Mapping>,
+    pub mute_led: Option<Led>,
+    pub bypass: Option<Mapping>,
+    pub bypass_led: Option<Led>,
+}
+
+/// The raw midi messages that turn an LED on and off.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct Led {
+    pub on: Vec<u8>,
+    pub off: Vec<u8>,
+}
+
+impl Led {
+    pub fn message(&self, on: bool) -> &[u8] {
+        if on {
+            &self.on
+        } else {
+            &self.off
+        }
+    }
+}
+
+/// Whether `message` starts with a status byte.
+fn is_midi(message: &[u8]) -> bool {
+    matches!(message.first(), Some(status) if *status >= 0x80)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Profile;
+    use crate::cli::MidiKey;
+
+    #[test]
+    fn nanokontrol2() {
+        let profile: Profile =
+            toml::from_str(include_str!("../profiles/nanokontrol2.toml")).unwrap();
+        profile.check().unwrap();
+        assert_eq!(profile.strips.len(), 8);
+        assert!(profile.has_leds());
+        // the knobs set the gain of the lower peak.
+        let knob = profile.strips[2].eq[1].gain.unwrap();
+        assert_eq!(knob.key, MidiKey::controller(0, 0x12));
+    }
+
+    #[test]
+    fn bad_messages() {
+        let check = |raw: &str| toml::from_str::<Profile>(raw).unwrap().check();
+        assert!(check("init = [[0xf0, 0x7e, 0xf7]]\nstrips = []").is_ok());
+        assert!(check("init = [[]]\nstrips = []").is_err());
+        let strip = "[[strips]]\nmute_led = { on = [0xb0, 0x30, 0x7f], off = [0x30, 0x00] }";
+        assert!(check(strip).is_err());
+        assert!(check("[[strips]]\neq = [{}, {}, {}, {}, {}]").is_err());
+    }
+}
@@ -0,0 +1,302 @@
+//! Controlling the mixer from other programs.
+//!
+//! Remote servers change the mixer by sending `AudioMsg`s to the RT thread, just like the gui. The
+//! RT thread reports every change (whatever made it) to the remote servers as a `UiMsg`, so they
+//! can keep track of the state of the mixer. The meter readings are passed on from the gui's
+//! updates.
+//!
+//! Meter readings are dropped when a server falls behind. Changes to the state are on a separate
+//! queue, so the readings can't crowd them out. If a server falls so far behind that the changes
+//! fill up their queue too, we drop them and send it the whole state once it catches up, so its
+//! `RemoteState` can't go wrong.
+use crate::{
+    cli::Config,
+    data::{ChannelMode, Metering},
+    effects::Band,
+    gui::{Level, UiMsg},
+};
+use crossbeam_channel as channel;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+pub mod http;
+pub mod osc;
+
+/// How often server threads that wait for clients check whether they should stop.
+const POLL: Duration = Duration::from_millis(100);
+
+/// How many meter readings can wait for a server before we start dropping them.
+const METERING_QUEUE: usize = 1024;
+
+/// How many changes to the state can wait for a server before we give up on sending them one by
+/// one, and send it the whole state instead.
+const STATE_QUEUE: usize = 1024;
+
+/// Make the queues that tell a remote server about changes to the mixer. `state` must be the
+/// state the server starts with.
+pub fn updates(state: RemoteState) -> (UpdateSender, Updates) {
+    let (tx_state, rx_state) = channel::bounded(STATE_QUEUE);
+    let (tx_metering, rx_metering) = channel::bounded(METERING_QUEUE);
+    let latest = Arc::new(Mutex::new(state));
+    let overflowed = Arc::new(AtomicBool::new(false));
+    (
+        UpdateSender {
+            state: tx_state,
+            metering: tx_metering,
+            latest: latest.clone(),
+            overflowed: overflowed.clone(),
+        },
+        Updates {
+            state: rx_state,
+            metering: rx_metering,
+            latest,
+            overflowed,
+        },
+    )
+}
+
+/// Something a server needs to know about the mixer.
+#[derive(Debug, Clone)]
+pub enum Update {
+    /// A change reported by the RT thread, or new meter readings.
+    Change(UiMsg),
+    /// The whole state, because changes were dropped while the server was behind. It replaces the
+    /// server's `RemoteState`, and clients should be sent all of it.
+    Resync(RemoteState),
+}
+
+/// The sending end of a server's updates. The server stops waiting for updates when it is dropped.
+pub struct UpdateSender {
+    state: channel::Sender<UiMsg>,
+    metering: channel::Sender<UiMsg>,
+    /// The state with every change we've been sent, for catching the server up if it falls behind.
+    latest: Arc<Mutex<RemoteState>>,
+    /// Set when a change is dropped because the queue is full.
+    overflowed: Arc<AtomicBool>,
+}
+
+impl UpdateSender {
+    /// Pass on a message. This never blocks, so a slow server can't hold up the gui.
+    pub fn send(&self, msg: UiMsg) {
+        // this comes first, so a change is always in `latest` by the time the server can see it.
+        self.latest.lock().update(&msg);
+        if let UiMsg::Metering { .. } = msg {
+            if self.metering.try_send(msg).is_err() {
+                log::debug!("dropped meter readings for slow remote server");
+            }
+            return;
+        }
+        match self.state.try_send(msg) {
+            Ok(()) => (),
+            Err(channel::TrySendError::Full(_)) => {
+                if !self.overflowed.swap(true, Ordering::SeqCst) {
+                    log::warn!("remote server has fallen behind, it will be sent the whole state");
+                }
+            }
+            Err(channel::TrySendError::Disconnected(_)) => log::debug!("remote server has stopped"),
+        }
+    }
+}
+
+/// The receiving end of a server's updates.
+pub struct Updates {
+    state: channel::Receiver<UiMsg>,
+    metering: channel::Receiver<UiMsg>,
+    latest: Arc<Mutex<RemoteState>>,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl Updates {
+    /// Wait for the next update. Returns `None` once the sender has been dropped.
+    pub fn recv(&self) -> Option<Update> {
+        if let Some(update) = self.resync() {
+            return Some(update);
+        }
+        channel::select! {
+            recv(self.state) -> msg => msg.ok().map(Update::Change),
+            recv(self.metering) -> msg => msg.ok().map(Update::Change),
+        }
+    }
+
+    /// Wait for the next update, giving up after `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Update, channel::RecvTimeoutError> {
+        if let Some(update) = self.resync() {
+            return Ok(update);
+        }
+        let disconnected = |_| channel::RecvTimeoutError::Disconnected;
+        channel::select! {
+            recv(self.state) -> msg => msg.map(Update::Change).map_err(disconnected),
+            recv(self.metering) -> msg => msg.map(Update::Change).map_err(disconnected),
+            default(timeout) => Err(channel::RecvTimeoutError::Timeout),
+        }
+    }
+
+    /// The whole state, if changes have been dropped since we last sent it.
+    fn resync(&self) -> Option<Update> {
+        if !self.overflowed.swap(false, Ordering::SeqCst) {
+            return None;
+        }
+        // the changes still waiting are already in `latest`. Any sent after this are in it as
+        // well, but applying them again doesn't do any harm.
+        while self.state.try_recv().is_ok() {}
+        Some(Update::Resync(self.latest.lock().clone()))
+    }
+}
+
+/// The threads of a running server.
+///
+/// Threads that wait for clients check `stopping` every `POLL`. Threads that wait for changes to
+/// the mixer stop when the `UpdateSender` for their `Updates` is dropped.
+pub struct ServerHandle {
+    stopping: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ServerHandle {
+    fn new(stopping: Arc<AtomicBool>, threads: Vec<JoinHandle<()>>) -> Self {
+        ServerHandle { stopping, threads }
+    }
+
+    /// Ask the server to stop, and return its threads so they can be joined.
+    pub fn stop(self) -> Vec<JoinHandle<()>> {
+        self.stopping.store(true, Ordering::SeqCst);
+        self.threads
+    }
+}
+
+/// Whether an error just means a non-blocking call (or one with a timeout) had nothing to do.
+fn timed_out(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// What the remote servers know about the mixer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoteState {
+    pub channels: Vec<RemoteChannel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteChannel {
+    pub name: String,
+    pub gain: f64,
+    pub mode: ChannelMode,
+    pub metering: Metering,
+    pub eq: Vec<Band>,
+}
+
+impl RemoteState {
+    pub fn new(config: &Config) -> Self {
+        let channels = config
+            .channels
+            .keys()
+            .map(|name| RemoteChannel {
+                name: name.clone(),
+                gain: 0.0,
+                mode: ChannelMode::default(),
+                metering: Metering::default(),
+                eq: Band::DEFAULTS.to_vec(),
+            })
+            .collect();
+        RemoteState { channels }
+    }
+
+    /// Keep track of a change reported by the RT thread. Returns `true` if the message was about
+    /// the state we keep.
+    pub fn update(&mut self, msg: &UiMsg) -> bool {
+        let idx = match msg {
+            UiMsg::Levels { channel, .. }
+            | UiMsg::Mode { channel, .. }
+            | UiMsg::Metering { channel, .. }
+            | UiMsg::MetersReset { channel }
+            | UiMsg::Eq { channel, .. } => *channel,
+            _ => return false,
+        };
+        let channel = match self.channels.get_mut(idx) {
+            Some(channel) => channel,
+            None => return false,
+        };
+        match msg {
+            UiMsg::Levels {
+                level: Level::Gain(gain),
+                ..
+            } => channel.gain = *gain,
+            UiMsg::Mode { mode, .. } => channel.mode = *mode,
+            UiMsg::Metering { metering, .. } => channel.metering = *metering,
+            UiMsg::MetersReset { .. } => channel.metering.reset(),
+            UiMsg::Eq { band, settings, .. } => match channel.eq.get_mut(*band) {
+                Some(band) => *band = *settings,
+                None => return false,
+            },
+            _ => unreachable!(),
+        }
+        true
+    }
+}
+
+/// Whether a message for the gui should be passed on to the remote servers as well. They are
+/// sent changes to the state separately, and don't need the spectra.
+pub fn is_remote(msg: &UiMsg) -> bool {
+    matches!(msg, UiMsg::Metering { .. })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{updates, RemoteChannel, RemoteState, Update, STATE_QUEUE};
+    use crate::{
+        data::{ChannelMode, Metering},
+        effects::Band,
+        gui::UiMsg,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn resync() {
+        let state = RemoteState {
+            channels: vec![RemoteChannel {
+                name: "left".into(),
+                gain: 0.0,
+                mode: ChannelMode::Mute,
+                metering: Metering::default(),
+                eq: Band::DEFAULTS.to_vec(),
+            }],
+        };
+        let (tx, rx) = updates(state);
+        let mode = |idx| {
+            if idx % 2 == 0 {
+                ChannelMode::Normal
+            } else {
+                ChannelMode::Bypass
+            }
+        };
+        // one more change than fits in the queue.
+        for idx in 0..=STATE_QUEUE {
+            tx.send(UiMsg::Mode {
+                channel: 0,
+                mode: mode(idx),
+            });
+        }
+        match rx.recv() {
+            Some(Update::Resync(state)) => assert_eq!(state.channels[0].mode, mode(STATE_QUEUE)),
+            other => panic!("expected the whole state, got {:?}", other),
+        }
+        // the changes that were waiting have been dropped, and new ones are sent as usual.
+        assert!(rx.recv_timeout(Duration::from_millis(1)).is_err());
+        tx.send(UiMsg::MetersReset { channel: 0 });
+        assert!(matches!(
+            rx.recv(),
+            Some(Update::Change(UiMsg::MetersReset { channel: 0 }))
+        ));
+    }
+}
@@ -0,0 +1,397 @@
+//! An http server with a JSON api, so the mixer can be controlled from a browser.
+//!
+//! Channels are numbered from 0, in the order they appear in the config.
+//!
+//!  - `GET /api/channels` lists the channels, and `GET /api/channels/<n>` gets one of them.
+//!  - `PUT /api/channels/<n>` changes a channel, with a body like `{ "gain": 0.5 }` or
+//!    `{ "mode": "mute" }` (the mode is one of `normal`, `mute` and `bypass`).
+//!  - `GET /` is a simple page for testing the api.
+//!
+//! Changes are streamed as JSON over a websocket on a separate port. When a client connects it is
+//! sent the state of every channel (`{ "type": "state", "channels": [...] }`), followed by a
+//! message for every change, e.g. `{ "type": "gain", "channel": 0, "gain": 0.5 }`, `{ "type":
+//! "mode", "channel": 0, "mode": "mute" }`, `{ "type": "eq", "channel": 0, "band": 1, "settings":
+//! {...} }`, or `{ "type": "metering", "channel": 0, "metering": {...} }`. If we fall behind and
+//! have to drop changes, clients are sent the state of every channel again instead. The EQ can only
+//! be changed over OSC for now.
+use super::{timed_out, RemoteChannel, RemoteState, ServerHandle, Update, Updates, POLL};
+use crate::{
+    audio::{AudioMsg, AudioMsgKind},
+    data::{ChannelMode, Metering},
+    effects::Band,
+    gui::{Level, UiMsg},
+    Result,
+};
+use anyhow::format_err;
+use crossbeam_channel as channel;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, Read},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, Server};
+use tungstenite::{Message, WebSocket};
+
+/// The test page. `WEBSOCKET_PORT` is replaced with the port of the websocket server.
+const INDEX: &str = include_str!("index.html");
+
+type HttpResponse = Response<io::Cursor<Vec<u8>>>;
+
+/// How long we wait for a websocket client before giving up on it.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Start the server, with the api on `addr` and the websocket on `websocket_addr`.
+///
+/// Changes are sent to the RT thread on `tx`, and `updates` tells us about changes to the mixer.
+pub fn spawn(
+    addr: SocketAddr,
+    websocket_addr: SocketAddr,
+    state: RemoteState,
+    tx: channel::Sender<AudioMsg>,
+    updates: Updates,
+) -> Result<ServerHandle> {
+    let server =
+        Server::http(addr).map_err(|e| format_err!("could not start http server: {}", e))?;
+    let listener = TcpListener::bind(websocket_addr)?;
+    // so we can check whether to stop while waiting for clients.
+    listener.set_nonblocking(true)?;
+    log::info!(
+        "http server listening on {}, websocket on {}",
+        addr,
+        websocket_addr
+    );
+    let state = Arc::new(Mutex::new(state));
+    // new websocket clients, which are handed over to the thread that writes to them.
+    let (tx_clients, rx_clients) = channel::unbounded();
+
+    let stopping = Arc::new(AtomicBool::new(false));
+
+    let api = Api {
+        state: state.clone(),
+        tx,
+        index: INDEX.replace("WEBSOCKET_PORT", &websocket_addr.port().to_string()),
+    };
+    let api_stopping = stopping.clone();
+    let accept_stopping = stopping.clone();
+    let threads = vec![
+        thread::spawn(move || {
+            while !api_stopping.load(Ordering::SeqCst) {
+                match server.recv_timeout(POLL) {
+                    Ok(Some(request)) => api.request(request),
+                    Ok(None) => (),
+                    Err(e) => log::error!("error receiving http request: {}", e),
+                }
+            }
+        }),
+        thread::spawn(move || accept(listener, tx_clients, accept_stopping)),
+        thread::spawn(move || notify(state, updates, rx_clients)),
+    ];
+    Ok(ServerHandle::new(stopping, threads))
+}
+
+/// A websocket client, and its address for logging.
+type Client = (SocketAddr, WebSocket<TcpStream>);
+
+/// A change to a channel. Missing fields are left alone.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ChannelUpdate {
+    gain: Option<f64>,
+    mode: Option<ChannelMode>,
+}
+
+/// The messages we send over the websocket.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Event<'a> {
+    State {
+        channels: &'a [RemoteChannel],
+    },
+    Gain {
+        channel: usize,
+        gain: f64,
+    },
+    Mode {
+        channel: usize,
+        mode: ChannelMode,
+    },
+    Eq {
+        channel: usize,
+        band: usize,
+        settings: Band,
+    },
+    Metering {
+        channel: usize,
+        metering: Metering,
+    },
+}
+
+impl<'a> Event<'a> {
+    fn from_msg(msg: &UiMsg) -> Option<Self> {
+        Some(match *msg {
+            UiMsg::Levels {
+                channel,
+                level: Level::Gain(gain),
+            } => Event::Gain { channel, gain },
+            UiMsg::Mode { channel, mode } => Event::Mode { channel, mode },
+            UiMsg::Eq {
+                channel,
+                band,
+                settings,
+            } => Event::Eq {
+                channel,
+                band,
+                settings,
+            },
+            UiMsg::Metering { channel, metering } => Event::Metering { channel, metering },
+            _ => return None,
+        })
+    }
+}
+
+struct Api {
+    state: Arc<Mutex<RemoteState>>,
+    tx: channel::Sender<AudioMsg>,
+    index: String,
+}
+
+impl Api {
+    fn request(&self, mut request: Request) {
+        let response = match self.route(&mut request) {
+            Ok(response) => response,
+            Err(e) => {
+                log::warn!(
+                    "error handling {} {}: {}",
+                    request.method(),
+                    request.url(),
+                    e
+                );
+                Response::from_string(format!("{}\n", e)).with_status_code(400)
+            }
+        };
+        if let Err(e) = request.respond(response) {
+            log::warn!("error sending http response: {}", e);
+        }
+    }
+
+    fn route(&self, request: &mut Request) -> Result<HttpResponse> {
+        let method = request.method().clone();
+        let url = request.url().split('?').next().unwrap_or("").to_owned();
+        let path: Vec<&str> = url.trim_end_matches('/').split('/').collect();
+        match (method, path.as_slice()) {
+            (Method::Get, [""]) => Ok(Response::from_string(self.index.as_str())
+                .with_header(content_type("text/html; charset=utf-8"))),
+            (Method::Get, ["", "api", "channels"]) => json(&self.state.lock().channels),
+            (Method::Get, ["", "api", "channels", idx]) => {
+                let idx = idx.parse()?;
+                json(channel(&self.state.lock(), idx)?)
+            }
+            (Method::Put, ["", "api", "channels", idx]) => {
+                let idx = idx.parse()?;
+                let mut body = String::new();
+                request.as_reader().read_to_string(&mut body)?;
+                let update: ChannelUpdate = serde_json::from_str(&body)?;
+                self.update(idx, update)?;
+                Ok(Response::from_string("").with_status_code(204))
+            }
+            _ => Ok(Response::from_string("not found\n").with_status_code(404)),
+        }
+    }
+
+    /// Send the changes in `update` to the RT thread, the same way the gui does (only the values
+    /// that differ from the current state are sent).
+    fn update(&self, idx: usize, update: ChannelUpdate) -> Result {
+        let state = self.state.lock();
+        let current = channel(&state, idx)?;
+        if let Some(gain) = update.gain {
+            let gain = gain.clamp(0.0, 1.0);
+            if gain != current.gain {
+                self.tx.send(AudioMsg {
+                    channel: idx,
+                    kind: AudioMsgKind::Gain(gain),
+                })?;
+            }
+        }
+        if let Some(mode) = update.mode {
+            if mode != current.mode {
+                self.tx.send(AudioMsg {
+                    channel: idx,
+                    kind: AudioMsgKind::Mode(mode),
+                })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn channel(state: &RemoteState, idx: usize) -> Result<&RemoteChannel> {
+    state
+        .channels
+        .get(idx)
+        .ok_or(format_err!("no channel {}", idx))
+}
+
+fn json(value: &impl Serialize) -> Result<HttpResponse> {
+    Ok(Response::from_string(serde_json::to_string(value)?)
+        .with_header(content_type("application/json")))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).unwrap()
+}
+
+/// Accept websocket clients, and hand them over to `notify`.
+fn accept(listener: TcpListener, tx: channel::Sender<Client>, stopping: Arc<AtomicBool>) {
+    while !stopping.load(Ordering::SeqCst) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(ref e) if timed_out(e) => {
+                thread::sleep(POLL);
+                continue;
+            }
+            Err(e) => {
+                log::warn!("error accepting websocket client: {}", e);
+                continue;
+            }
+        };
+        match handshake(stream) {
+            Ok(client) => {
+                if tx.send(client).is_err() {
+                    // the mixer has stopped.
+                    break;
+                }
+            }
+            Err(e) => log::warn!("error accepting websocket client: {}", e),
+        }
+    }
+}
+
+fn handshake(stream: TcpStream) -> Result<(SocketAddr, WebSocket<TcpStream>)> {
+    // the listener is non-blocking, but we want to block (with a timeout) on clients.
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+    let client = tungstenite::accept(stream)
+        .map_err(|e| format_err!("websocket handshake failed: {}", e))?;
+    Ok((peer, client))
+}
+
+/// Send new websocket clients the current state, and tell all of them about changes to the
+/// mixer. Clients we can't reach are dropped.
+///
+/// This is the only thread that writes to the clients, so each one is sent the state and then
+/// every change after it, in order. The lock on the state isn't held while writing, so a slow
+/// client can't hold up the api.
+fn notify(state: Arc<Mutex<RemoteState>>, updates: Updates, rx_clients: channel::Receiver<Client>) {
+    let mut clients: Vec<Client> = Vec::new();
+    loop {
+        // wake up every so often to greet new clients, even if nothing is changing.
+        let msg = match updates.recv_timeout(POLL) {
+            Ok(msg) => Some(msg),
+            Err(channel::RecvTimeoutError::Timeout) => None,
+            Err(channel::RecvTimeoutError::Disconnected) => break,
+        };
+        for (peer, mut client) in rx_clients.try_iter() {
+            let text = {
+                let state = state.lock();
+                encode(&Event::State {
+                    channels: &state.channels,
+                })
+            };
+            match text.and_then(|text| send(&mut client, text)) {
+                Ok(()) => {
+                    log::info!("websocket client {} connected", peer);
+                    clients.push((peer, client));
+                }
+                Err(e) => log::warn!("error sending state to websocket client {}: {}", peer, e),
+            }
+        }
+        let msg = match msg {
+            Some(msg) => msg,
+            None => continue,
+        };
+        let text = {
+            let mut state = state.lock();
+            match msg {
+                Update::Change(msg) => {
+                    if !state.update(&msg) {
+                        continue;
+                    }
+                    match Event::from_msg(&msg) {
+                        Some(event) => encode(&event),
+                        None => continue,
+                    }
+                }
+                Update::Resync(latest) => {
+                    *state = latest;
+                    encode(&Event::State {
+                        channels: &state.channels,
+                    })
+                }
+            }
+        };
+        let text = match text {
+            Ok(text) => text,
+            Err(e) => {
+                log::error!("error encoding websocket event: {}", e);
+                continue;
+            }
+        };
+        let mut idx = 0;
+        while idx < clients.len() {
+            let (peer, client) = &mut clients[idx];
+            match send(client, text.clone()) {
+                Ok(()) => idx += 1,
+                Err(e) => {
+                    log::info!("dropping websocket client {}: {}", peer, e);
+                    clients.swap_remove(idx);
+                }
+            }
+        }
+    }
+}
+
+fn encode(event: &Event) -> Result<String> {
+    Ok(serde_json::to_string(event)?)
+}
+
+fn send(client: &mut WebSocket<TcpStream>, text: String) -> Result {
+    client
+        .write_message(Message::Text(text))
+        .map_err(|e| format_err!("{}", e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChannelUpdate, Event};
+    use crate::{data::ChannelMode, gui::UiMsg};
+
+    #[test]
+    fn update_json() {
+        let update: ChannelUpdate = serde_json::from_str(r#"{ "mode": "bypass" }"#).unwrap();
+        assert_eq!(update.gain, None);
+        assert_eq!(update.mode, Some(ChannelMode::Bypass));
+        assert!(serde_json::from_str::<ChannelUpdate>(r#"{ "volume": 1 }"#).is_err());
+    }
+
+    #[test]
+    fn event_json() {
+        let event = Event::from_msg(&UiMsg::Mode {
+            channel: 1,
+            mode: ChannelMode::Mute,
+        })
+        .unwrap();
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"type":"mode","channel":1,"mode":"mute"}"#
+        );
+    }
+}
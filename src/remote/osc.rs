@@ -0,0 +1,558 @@
+//! An OSC server, so the mixer can be controlled from apps like TouchOSC, or from scripts.
+//!
+//! Channels are numbered from 0, in the order they appear in the config. The addresses are
+//!
+//!  - `/mixjack/channel/<n>/gain`: the channel gain, from 0 to 1.
+//!  - `/mixjack/channel/<n>/mute` and `/mixjack/channel/<n>/bypass`: 1 if the channel is in that
+//!    mode, 0 otherwise. Any value over 0.5 (or `true`) switches the mode on.
+//!  - `/mixjack/channel/<n>/name`: the channel name (read only).
+//!  - `/mixjack/channel/<n>/eq/<b>/freq`, `.../gain` and `.../q`: the frequency (in Hz), gain
+//!    (in dB) and Q of EQ band `b`, which are clamped to the ranges the EQ allows.
+//!  - `/mixjack/channel/<n>/eq/<b>/type`: the shape of the band, one of `peaking`, `low_shelf`,
+//!    `high_shelf`, `low_pass`, `high_pass`, `notch` and `all_pass`. It can also be set by its
+//!    index in that list.
+//!  - `/mixjack/channel/<n>/eq/<b>/on`: 1 if the band is on, 0 if it is off.
+//!  - `/mixjack/channel/<n>/eq/low`, `.../mid` and `.../high`: the gains of bands 0, 1 and 3 (the
+//!    low shelf, the lower peak and the high shelf of the default EQ), in dB.
+//!
+//! A message with no arguments is a query, and we reply to the sender with the current value.
+//! Sending `/mixjack/subscribe` asks us to send a message whenever a value changes, and
+//! `/mixjack/unsubscribe` asks us to stop. If we fall behind and have to drop changes,
+//! subscribers are sent every value of every channel instead.
+use super::{timed_out, RemoteChannel, RemoteState, ServerHandle, Update, Updates, POLL};
+use crate::{
+    audio::{AudioMsg, AudioMsgKind},
+    data::ChannelMode,
+    effects::{Band, FilterKind},
+    gui::UiMsg,
+    Result,
+};
+use anyhow::format_err;
+use crossbeam_channel as channel;
+use parking_lot::Mutex;
+use rosc::{decoder, encoder, OscMessage, OscPacket, OscType};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+const PREFIX: &str = "mixjack";
+/// The names of the bands with their own gain address, and their indices.
+const EQ_ALIASES: [(&str, usize); 3] = [("low", 0), ("mid", 1), ("high", 3)];
+
+/// Start the server on `addr`.
+///
+/// Changes are sent to the RT thread on `tx`, and `updates` tells us about changes to the mixer.
+pub fn spawn(
+    addr: SocketAddr,
+    state: RemoteState,
+    tx: channel::Sender<AudioMsg>,
+    updates: Updates,
+) -> Result<ServerHandle> {
+    let socket = UdpSocket::bind(addr)?;
+    socket.set_read_timeout(Some(POLL))?;
+    log::info!("osc server listening on {}", addr);
+    let stopping = Arc::new(AtomicBool::new(false));
+    let shared = Arc::new(Mutex::new(Shared {
+        state,
+        subscribers: Vec::new(),
+    }));
+
+    let server = Server {
+        socket: socket.try_clone()?,
+        shared: shared.clone(),
+        tx,
+        stopping: stopping.clone(),
+    };
+    let threads = vec![
+        thread::spawn(move || server.run()),
+        thread::spawn(move || notify(socket, shared, updates)),
+    ];
+    Ok(ServerHandle::new(stopping, threads))
+}
+
+/// State shared between the thread that answers requests and the thread that sends
+/// notifications.
+struct Shared {
+    state: RemoteState,
+    subscribers: Vec<SocketAddr>,
+}
+
+struct Server {
+    socket: UdpSocket,
+    shared: Arc<Mutex<Shared>>,
+    tx: channel::Sender<AudioMsg>,
+    stopping: Arc<AtomicBool>,
+}
+
+impl Server {
+    fn run(self) {
+        let mut buf = [0; decoder::MTU];
+        while !self.stopping.load(Ordering::SeqCst) {
+            let (len, from) = match self.socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                // the read timed out, so we can check whether to stop.
+                Err(ref e) if timed_out(e) => continue,
+                Err(e) => {
+                    log::error!("error receiving osc packet: {}", e);
+                    continue;
+                }
+            };
+            let packet = match decoder::decode(&buf[..len]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    log::warn!("invalid osc packet from {}: {:?}", from, e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.packet(packet, from) {
+                log::warn!("error handling osc message from {}: {}", from, e);
+            }
+        }
+    }
+
+    fn packet(&self, packet: OscPacket, from: SocketAddr) -> Result {
+        match packet {
+            OscPacket::Message(msg) => self.message(msg, from),
+            OscPacket::Bundle(bundle) => {
+                for packet in bundle.content {
+                    self.packet(packet, from)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn message(&self, msg: OscMessage, from: SocketAddr) -> Result {
+        let path: Vec<&str> = msg.addr.split('/').collect();
+        match path.as_slice() {
+            ["", PREFIX, "subscribe"] => {
+                let mut shared = self.shared.lock();
+                if !shared.subscribers.contains(&from) {
+                    log::info!("osc client {} subscribed", from);
+                    shared.subscribers.push(from);
+                }
+                Ok(())
+            }
+            ["", PREFIX, "unsubscribe"] => {
+                self.shared.lock().subscribers.retain(|addr| *addr != from);
+                Ok(())
+            }
+            ["", PREFIX, "channel", idx, param] => {
+                let idx: usize = idx.parse()?;
+                let param: Param = param.parse()?;
+                let shared = self.shared.lock();
+                let channel = shared
+                    .state
+                    .channels
+                    .get(idx)
+                    .ok_or(format_err!("no channel {}", idx))?;
+                match msg.args.first() {
+                    None => send(&self.socket, from, &param.message(idx, channel))?,
+                    Some(arg) => {
+                        let value = arg_value(arg)
+                            .ok_or(format_err!("expected a number or bool, found {:?}", arg))?;
+                        if let Some(kind) = param.change(channel, value)? {
+                            self.tx.send(AudioMsg { channel: idx, kind })?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            ["", PREFIX, "channel", idx, "eq", alias] => {
+                let band_idx = EQ_ALIASES
+                    .iter()
+                    .find(|(name, _)| name == alias)
+                    .map(|(_, band_idx)| *band_idx)
+                    .ok_or(format_err!("unknown EQ band \"{}\"", alias))?;
+                self.band(&msg, from, idx, band_idx, BandParam::Gain, Some(*alias))
+            }
+            ["", PREFIX, "channel", idx, "eq", band_idx, param] => {
+                let band_idx: usize = band_idx.parse()?;
+                let param: BandParam = param.parse()?;
+                self.band(&msg, from, idx, band_idx, param, None)
+            }
+            _ => Err(format_err!("unknown address \"{}\"", msg.addr)),
+        }
+    }
+
+    /// Answer a query about a parameter of an EQ band, or change it. Queries through an alias are
+    /// answered on the alias.
+    fn band(
+        &self,
+        msg: &OscMessage,
+        from: SocketAddr,
+        idx: &str,
+        band_idx: usize,
+        param: BandParam,
+        alias: Option<&str>,
+    ) -> Result {
+        let idx: usize = idx.parse()?;
+        let shared = self.shared.lock();
+        let band = shared
+            .state
+            .channels
+            .get(idx)
+            .ok_or(format_err!("no channel {}", idx))?
+            .eq
+            .get(band_idx)
+            .ok_or(format_err!("no EQ band {}", band_idx))?;
+        match (msg.args.first(), alias) {
+            (None, None) => send(&self.socket, from, &param.message(idx, band_idx, band))?,
+            (None, Some(alias)) => send(&self.socket, from, &alias_message(idx, alias, band))?,
+            (Some(arg), _) => {
+                let kind = AudioMsgKind::Eq(band_idx, param.change(band_idx, *band, arg)?);
+                self.tx.send(AudioMsg { channel: idx, kind })?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tell subscribers about changes to the mixer.
+fn notify(socket: UdpSocket, shared: Arc<Mutex<Shared>>, updates: Updates) {
+    while let Some(update) = updates.recv() {
+        let mut shared = shared.lock();
+        let packets = match update {
+            Update::Change(msg) => {
+                if !shared.state.update(&msg) {
+                    continue;
+                }
+                change_packets(&shared.state, &msg)
+            }
+            // we've missed some changes, so send everything.
+            Update::Resync(state) => {
+                shared.state = state;
+                shared
+                    .state
+                    .channels
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(idx, channel)| channel_packets(idx, channel))
+                    .collect()
+            }
+        };
+        for packet in packets.iter() {
+            for addr in shared.subscribers.iter() {
+                if let Err(e) = send(&socket, *addr, packet) {
+                    log::warn!("error notifying osc client {}: {}", addr, e);
+                }
+            }
+        }
+    }
+}
+
+/// The messages telling subscribers about a change to `state`.
+fn change_packets(state: &RemoteState, msg: &UiMsg) -> Vec<OscPacket> {
+    let params = |idx: usize, params: &[Param]| -> Vec<OscPacket> {
+        let channel = &state.channels[idx];
+        params
+            .iter()
+            .map(|param| param.message(idx, channel))
+            .collect()
+    };
+    match *msg {
+        UiMsg::Levels { channel, .. } => params(channel, &[Param::Gain]),
+        UiMsg::Mode { channel, .. } => params(channel, &[Param::Mute, Param::Bypass]),
+        UiMsg::Eq {
+            channel,
+            band,
+            settings,
+        } => band_packets(channel, band, &settings),
+        _ => Vec::new(),
+    }
+}
+
+/// The messages with every value of a channel that can change.
+fn channel_packets(idx: usize, channel: &RemoteChannel) -> Vec<OscPacket> {
+    [Param::Gain, Param::Mute, Param::Bypass]
+        .iter()
+        .map(|param| param.message(idx, channel))
+        .chain(
+            channel
+                .eq
+                .iter()
+                .enumerate()
+                .flat_map(|(band_idx, band)| band_packets(idx, band_idx, band)),
+        )
+        .collect()
+}
+
+/// The messages with every parameter of an EQ band, including its alias if it has one.
+fn band_packets(idx: usize, band_idx: usize, band: &Band) -> Vec<OscPacket> {
+    BandParam::ALL
+        .iter()
+        .map(|param| param.message(idx, band_idx, band))
+        .chain(
+            EQ_ALIASES
+                .iter()
+                .filter(|(_, alias_idx)| *alias_idx == band_idx)
+                .map(|(alias, _)| alias_message(idx, alias, band)),
+        )
+        .collect()
+}
+
+/// The parameters of a channel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Param {
+    Gain,
+    Mute,
+    Bypass,
+    Name,
+}
+
+impl Param {
+    fn name(self) -> &'static str {
+        match self {
+            Param::Gain => "gain",
+            Param::Mute => "mute",
+            Param::Bypass => "bypass",
+            Param::Name => "name",
+        }
+    }
+
+    /// A message with the current value of this parameter.
+    fn message(self, idx: usize, channel: &RemoteChannel) -> OscPacket {
+        let mode_arg = |mode| OscType::Float(if channel.mode == mode { 1.0 } else { 0.0 });
+        let arg = match self {
+            Param::Gain => OscType::Float(channel.gain as f32),
+            Param::Mute => mode_arg(ChannelMode::Mute),
+            Param::Bypass => mode_arg(ChannelMode::Bypass),
+            Param::Name => OscType::String(channel.name.clone()),
+        };
+        OscPacket::Message(OscMessage {
+            addr: format!("/{}/channel/{}/{}", PREFIX, idx, self.name()),
+            args: vec![arg],
+        })
+    }
+
+    /// The change to make when a client sets this parameter to `value`, if any.
+    fn change(self, channel: &RemoteChannel, value: f64) -> Result<Option<AudioMsgKind>> {
+        let mode = match self {
+            Param::Gain => return Ok(Some(AudioMsgKind::Gain(value.clamp(0.0, 1.0)))),
+            Param::Mute => ChannelMode::Mute,
+            Param::Bypass => ChannelMode::Bypass,
+            Param::Name => return Err(format_err!("channel names can't be changed")),
+        };
+        let next = match (value > 0.5, channel.mode == mode) {
+            (true, false) => mode,
+            (false, true) => ChannelMode::Normal,
+            _ => return Ok(None),
+        };
+        Ok(Some(AudioMsgKind::Mode(next)))
+    }
+}
+
+impl FromStr for Param {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "gain" => Param::Gain,
+            "mute" => Param::Mute,
+            "bypass" => Param::Bypass,
+            "name" => Param::Name,
+            o => return Err(format_err!("unknown channel parameter \"{}\"", o)),
+        })
+    }
+}
+
+/// The parameters of an EQ band.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum BandParam {
+    Freq,
+    Gain,
+    Q,
+    Type,
+    On,
+}
+
+impl BandParam {
+    const ALL: [BandParam; 5] = [
+        BandParam::Freq,
+        BandParam::Gain,
+        BandParam::Q,
+        BandParam::Type,
+        BandParam::On,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            BandParam::Freq => "freq",
+            BandParam::Gain => "gain",
+            BandParam::Q => "q",
+            BandParam::Type => "type",
+            BandParam::On => "on",
+        }
+    }
+
+    /// A message with the current value of this parameter.
+    fn message(self, idx: usize, band_idx: usize, band: &Band) -> OscPacket {
+        let arg = match self {
+            BandParam::Freq => OscType::Float(band.freq as f32),
+            BandParam::Gain => OscType::Float(band.gain as f32),
+            BandParam::Q => OscType::Float(band.q as f32),
+            BandParam::Type => OscType::String(band.kind.name().into()),
+            BandParam::On => OscType::Float(if band.on { 1.0 } else { 0.0 }),
+        };
+        OscPacket::Message(OscMessage {
+            addr: format!(
+                "/{}/channel/{}/eq/{}/{}",
+                PREFIX,
+                idx,
+                band_idx,
+                self.name()
+            ),
+            args: vec![arg],
+        })
+    }
+
+    /// Band `idx` after a client sets this parameter to `arg`.
+    fn change(self, idx: usize, band: Band, arg: &OscType) -> Result<Band> {
+        if let (BandParam::Type, OscType::String(name)) = (self, arg) {
+            let kind = FilterKind::ALL
+                .iter()
+                .find(|kind| kind.name() == name.as_str())
+                .ok_or(format_err!("unknown EQ band type \"{}\"", name))?;
+            return Ok(Band {
+                kind: *kind,
+                ..band
+            });
+        }
+        let value =
+            arg_value(arg).ok_or(format_err!("expected a number or bool, found {:?}", arg))?;
+        let band = match self {
+            BandParam::Freq => Band {
+                freq: value,
+                ..band
+            },
+            BandParam::Gain => Band {
+                gain: value,
+                ..band
+            },
+            BandParam::Q => Band { q: value, ..band },
+            BandParam::Type => {
+                let kind = FilterKind::ALL
+                    .get(value as usize)
+                    .ok_or(format_err!("no EQ band type {}", value))?;
+                Band {
+                    kind: *kind,
+                    ..band
+                }
+            }
+            BandParam::On => Band {
+                on: value > 0.5,
+                ..band
+            },
+        };
+        Ok(band.clamped(idx))
+    }
+}
+
+impl FromStr for BandParam {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "freq" => BandParam::Freq,
+            "gain" => BandParam::Gain,
+            "q" => BandParam::Q,
+            "type" => BandParam::Type,
+            "on" => BandParam::On,
+            o => return Err(format_err!("unknown EQ band parameter \"{}\"", o)),
+        })
+    }
+}
+
+/// A message with the gain of a band, on its alias.
+fn alias_message(idx: usize, alias: &str, band: &Band) -> OscPacket {
+    OscPacket::Message(OscMessage {
+        addr: format!("/{}/channel/{}/eq/{}", PREFIX, idx, alias),
+        args: vec![OscType::Float(band.gain as f32)],
+    })
+}
+
+/// The number in `arg`, if it is one. NaN and infinities aren't, as they'd get past `clamp`.
+fn arg_value(arg: &OscType) -> Option<f64> {
+    let value = match arg {
+        OscType::Float(v) => *v as f64,
+        OscType::Double(v) => *v,
+        OscType::Int(v) => *v as f64,
+        OscType::Long(v) => *v as f64,
+        OscType::Bool(v) => *v as u8 as f64,
+        _ => return None,
+    };
+    Some(value).filter(|value| value.is_finite())
+}
+
+fn send(socket: &UdpSocket, addr: SocketAddr, packet: &OscPacket) -> Result {
+    let buf = encoder::encode(packet).map_err(|e| format_err!("could not encode osc: {:?}", e))?;
+    socket.send_to(&buf, addr)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BandParam, Param};
+    use crate::{
+        audio::AudioMsgKind,
+        data::ChannelMode,
+        effects::{Band, FilterKind},
+        remote::RemoteChannel,
+    };
+    use rosc::OscType;
+
+    #[test]
+    fn change_mode() {
+        let mut channel = RemoteChannel {
+            name: "left".into(),
+            gain: 0.5,
+            mode: ChannelMode::Bypass,
+            metering: Default::default(),
+            eq: Band::DEFAULTS.to_vec(),
+        };
+        // switching off mute doesn't leave bypass
+        assert!(Param::Mute.change(&channel, 0.0).unwrap().is_none());
+        assert!(matches!(
+            Param::Mute.change(&channel, 1.0).unwrap(),
+            Some(AudioMsgKind::Mode(ChannelMode::Mute))
+        ));
+        channel.mode = ChannelMode::Mute;
+        assert!(matches!(
+            Param::Mute.change(&channel, 0.0).unwrap(),
+            Some(AudioMsgKind::Mode(ChannelMode::Normal))
+        ));
+        assert!(Param::Name.change(&channel, 1.0).is_err());
+    }
+
+    #[test]
+    fn change_band() {
+        let band = Band::DEFAULTS[1];
+        let changed = BandParam::Gain
+            .change(1, band, &OscType::Float(6.0))
+            .unwrap();
+        assert_eq!(changed.gain, 6.0);
+        // out of range values are clamped.
+        let changed = BandParam::Freq.change(1, band, &OscType::Int(0)).unwrap();
+        assert_eq!(changed.freq, 20.0);
+        let changed = BandParam::Type
+            .change(1, band, &OscType::String("notch".into()))
+            .unwrap();
+        assert_eq!(changed.kind, FilterKind::Notch);
+        let changed = BandParam::Type.change(1, band, &OscType::Int(1)).unwrap();
+        assert_eq!(changed.kind, FilterKind::LowShelf);
+        assert!(
+            !BandParam::On
+                .change(1, band, &OscType::Bool(false))
+                .unwrap()
+                .on
+        );
+        assert!(BandParam::Type
+            .change(1, band, &OscType::String("bandpass".into()))
+            .is_err());
+        assert!(BandParam::Type.change(1, band, &OscType::Int(7)).is_err());
+    }
+}
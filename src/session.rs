@@ -0,0 +1,115 @@
+//! Saving the settings of the mixer when it stops, so they can be restored when it next starts.
+//!
+//! The settings are kept by channel name, so a session still loads after channels are added to
+//! or removed from the config. Channels that aren't in the session keep their defaults.
+use crate::{audio::State, cli::Config, data::ChannelMode, effects::Band, Result};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    channels: BTreeMap<String, SessionChannel>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct SessionChannel {
+    gain: f64,
+    mode: ChannelMode,
+    eq: Vec<Band>,
+}
+
+impl Session {
+    /// Load the session at `path`, or an empty one if there isn't one yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = match fs::read(path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Session::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let session = ron::de::from_bytes(&raw)
+            .with_context(|| format!("could not read session at \"{}\"", path.display()))?;
+        log::info!("restoring session from \"{}\"", path.display());
+        Ok(session)
+    }
+
+    pub fn save(&self, path: &Path) -> Result {
+        let raw = ron::ser::to_string_pretty(self, Default::default())?;
+        fs::write(path, raw)
+            .with_context(|| format!("could not write session to \"{}\"", path.display()))?;
+        log::info!("saved session to \"{}\"", path.display());
+        Ok(())
+    }
+
+    /// The settings of every channel in `state`.
+    pub fn from_state(config: &Config, state: &State) -> Self {
+        let channels = config
+            .channels
+            .keys()
+            .zip(state.channels.iter())
+            .map(|(name, channel)| {
+                let saved = SessionChannel {
+                    gain: channel.gain,
+                    mode: channel.mode,
+                    eq: channel.eq.to_vec(),
+                };
+                (name.clone(), saved)
+            })
+            .collect();
+        Session { channels }
+    }
+
+    /// Put the saved settings into `state`, keeping them in range in case the file was edited.
+    /// A gain that isn't a number is ignored.
+    pub fn restore(&self, config: &Config, state: &mut State) {
+        for (name, channel) in config.channels.keys().zip(state.channels.iter_mut()) {
+            let saved = match self.channels.get(name) {
+                Some(saved) => saved,
+                None => continue,
+            };
+            if saved.gain.is_finite() {
+                channel.gain = saved.gain.clamp(0.0, 1.0);
+            }
+            channel.mode = saved.mode;
+            for (idx, (band, saved)) in channel.eq.iter_mut().zip(&saved.eq).enumerate() {
+                *band = saved.clamped(idx);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Session;
+    use crate::{audio::State, cli::Config, data::ChannelMode, effects::Band};
+
+    #[test]
+    fn round_trip() {
+        let config = Config::default();
+        let mut state = State::new(&config);
+        state.channels[0].gain = 0.5;
+        state.channels[1].mode = ChannelMode::Bypass;
+        state.channels[1].eq[2].gain = 6.0;
+        let session = Session::from_state(&config, &state);
+        let raw = ron::ser::to_string(&session).unwrap();
+        assert_eq!(ron::de::from_str::<Session>(&raw).unwrap(), session);
+
+        let mut restored = State::new(&config);
+        session.restore(&config, &mut restored);
+        assert_eq!(restored, state);
+
+        // channels that weren't saved keep their defaults.
+        let mut session = session;
+        session.channels.remove("left");
+        let mut restored = State::new(&config);
+        session.restore(&config, &mut restored);
+        assert_eq!(restored.channels[0].gain, 0.0);
+        assert_eq!(
+            restored.channels[1].eq[2],
+            Band {
+                gain: 6.0,
+                ..Band::DEFAULTS[2]
+            }
+        );
+    }
+}
@@ -0,0 +1,341 @@
+//! A terminal frontend, for using the mixer over ssh.
+//!
+//! Like the gui, it is told about changes to the mixer with `UiMsg`s and makes changes by sending
+//! `AudioMsg`s. Log messages go to stderr, so redirect it (e.g. `2> mixjack.log`) to keep them
+//! from drawing over the display.
+use crate::{
+    audio::{AudioMsg, AudioMsgKind},
+    cli::Config,
+    data::{ChannelMode, Metering},
+    gui::UiMsg,
+    metering::{Loudness, TruePeak},
+    remote::{RemoteChannel, RemoteState},
+    Result,
+};
+use crossbeam_channel as channel;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    queue, style,
+    terminal::{self, ClearType},
+};
+use std::{
+    io::{self, Write},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// How often we redraw.
+const FRAME: Duration = Duration::from_millis(1000 / 30);
+/// How much the arrow keys change the gain (page up/down change it 10 times as much).
+const GAIN_STEP: f64 = 0.01;
+/// The width of the gain bar and meters, in characters.
+const BAR_WIDTH: usize = 20;
+/// The quietest level the meters show, in dB.
+const METER_FLOOR: f64 = -60.0;
+
+const HELP: &str = "up/down: select   left/right: gain   m: mute   b: bypass   tab: meters   \
+                    r: reset loudness   q: quit";
+
+/// Start the terminal ui in a new thread.
+///
+/// Returns a channel for telling the ui about changes, and the handle of the ui thread. We send on
+/// `shutdown_tx` when the user quits.
+pub fn run(
+    tx: channel::Sender<AudioMsg>,
+    shutdown_tx: channel::Sender<()>,
+    config: Arc<Config>,
+) -> Result<(channel::Sender<UiMsg>, JoinHandle<Result>)> {
+    let (tx_tui, rx) = channel::bounded(1024);
+    let mut tui = Tui {
+        state: RemoteState::new(&config),
+        selected: 0,
+        show_meters: true,
+        tx,
+        rx,
+    };
+    let handle = thread::spawn(move || {
+        let result = {
+            let _terminal = RawTerminal::enter()?;
+            tui.run()
+        };
+        shutdown_tx.send(())?;
+        result
+    });
+    Ok((tx_tui, handle))
+}
+
+struct Tui {
+    state: RemoteState,
+    /// The index of the channel that the keys control.
+    selected: usize,
+    show_meters: bool,
+    tx: channel::Sender<AudioMsg>,
+    rx: channel::Receiver<UiMsg>,
+}
+
+impl Tui {
+    fn run(&mut self) -> Result {
+        let mut out = io::stdout();
+        loop {
+            for msg in self.rx.try_iter() {
+                self.state.update(&msg);
+            }
+            self.draw(&mut out)?;
+            if event::poll(FRAME)? {
+                if let Event::Key(key) = event::read()? {
+                    if !self.key(key)? {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Handle a key press. Returns `false` if we should quit.
+    fn key(&mut self, key: KeyEvent) -> Result<bool> {
+        let len = self.state.channels.len();
+        let channel = match self.state.channels.get(self.selected) {
+            Some(channel) => channel,
+            None => return Ok(!is_quit(key)),
+        };
+        let kind = match key.code {
+            KeyCode::Up => {
+                self.selected = (self.selected + len - 1) % len;
+                None
+            }
+            KeyCode::Down => {
+                self.selected = (self.selected + 1) % len;
+                None
+            }
+            KeyCode::Left => Some(gain(channel, -GAIN_STEP)),
+            KeyCode::Right => Some(gain(channel, GAIN_STEP)),
+            KeyCode::PageDown => Some(gain(channel, -10.0 * GAIN_STEP)),
+            KeyCode::PageUp => Some(gain(channel, 10.0 * GAIN_STEP)),
+            KeyCode::Char('m') => Some(toggle(channel, ChannelMode::Mute)),
+            KeyCode::Char('b') => Some(toggle(channel, ChannelMode::Bypass)),
+            KeyCode::Tab => {
+                self.show_meters = !self.show_meters;
+                None
+            }
+            KeyCode::Char('r') => {
+                for idx in 0..len {
+                    self.tx.send(AudioMsg {
+                        channel: idx,
+                        kind: AudioMsgKind::ResetMeters,
+                    })?;
+                }
+                None
+            }
+            _ if is_quit(key) => return Ok(false),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            let msg = AudioMsg {
+                channel: self.selected,
+                kind,
+            };
+            // show the change straight away, rather than waiting to hear back from the RT thread.
+            self.state.update(&msg.into());
+            self.tx.send(msg)?;
+        }
+        Ok(true)
+    }
+
+    fn draw(&self, out: &mut impl Write) -> Result {
+        let name_width = self
+            .state
+            .channels
+            .iter()
+            .map(|channel| channel.name.chars().count())
+            .max()
+            .unwrap_or(0);
+        // we overwrite the previous frame rather than clearing the screen, to avoid flicker.
+        queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            style::Print("mixjack"),
+            terminal::Clear(ClearType::UntilNewLine),
+            cursor::MoveTo(0, 1),
+            style::Print(HELP),
+            terminal::Clear(ClearType::UntilNewLine)
+        )?;
+        for (idx, channel) in self.state.channels.iter().enumerate() {
+            let marker = if idx == self.selected { '>' } else { ' ' };
+            let mode = match channel.mode {
+                ChannelMode::Normal => "      ",
+                ChannelMode::Mute => "MUTE  ",
+                ChannelMode::Bypass => "BYPASS",
+            };
+            let mut line = format!(
+                "{} {:<width$}  [{}] {:.2}  {}",
+                marker,
+                channel.name,
+                bar(channel.gain),
+                channel.gain,
+                mode,
+                width = name_width
+            );
+            if self.show_meters {
+                line.push_str(&meters(&channel.metering));
+            }
+            queue!(
+                out,
+                cursor::MoveTo(0, idx as u16 + 3),
+                style::Print(line),
+                terminal::Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Puts the terminal in raw mode on the alternate screen, and puts it back when dropped.
+struct RawTerminal;
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        crossterm::execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(RawTerminal)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn is_quit(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+fn gain(channel: &RemoteChannel, delta: f64) -> AudioMsgKind {
+    AudioMsgKind::Gain((channel.gain + delta).clamp(0.0, 1.0))
+}
+
+/// Switch the channel into `mode`, or back to normal if it's already in it.
+fn toggle(channel: &RemoteChannel, mode: ChannelMode) -> AudioMsgKind {
+    if channel.mode == mode {
+        AudioMsgKind::Mode(ChannelMode::Normal)
+    } else {
+        AudioMsgKind::Mode(mode)
+    }
+}
+
+/// A bar showing `value` (from 0 to 1).
+fn bar(value: f64) -> String {
+    let filled = (value.clamp(0.0, 1.0) * BAR_WIDTH as f64).round() as usize;
+    format!("{}{}", "#".repeat(filled), "-".repeat(BAR_WIDTH - filled))
+}
+
+/// Text meters for the input and output levels, with the rms level as a bar, and the peak as a
+/// `|`, followed by the short-term and integrated loudness and loudness range of the output, and
+/// its highest true peak and number of overs.
+fn meters(metering: &Metering) -> String {
+    format!(
+        "  in {}  out {}  {}  {}",
+        meter(metering.rms_in, metering.max_in),
+        meter(metering.rms_out, metering.max_out),
+        loudness(&metering.loudness),
+        true_peak(&metering.true_peak_out)
+    )
+}
+
+fn loudness(loudness: &Loudness) -> String {
+    format!(
+        "S {}  I {} LUFS  LRA {:4.1} LU",
+        decibels(loudness.short_term),
+        decibels(loudness.integrated),
+        loudness.range
+    )
+}
+
+fn true_peak(true_peak: &TruePeak) -> String {
+    format!(
+        "TP {} dBTP  {} overs",
+        decibels(20.0 * true_peak.max.log10()),
+        true_peak.overs
+    )
+}
+
+/// A level in dB (or LUFS), which may be `-inf`.
+fn decibels(value: f64) -> String {
+    if value.is_finite() {
+        format!("{:5.1}", value)
+    } else {
+        " -inf".to_string()
+    }
+}
+
+fn meter(rms: f64, peak: f64) -> String {
+    let position = |level: f64| {
+        let db = 20.0 * level.log10();
+        let fraction = ((db - METER_FLOOR) / -METER_FLOOR).clamp(0.0, 1.0);
+        (fraction * BAR_WIDTH as f64).round() as usize
+    };
+    let rms = position(rms);
+    let peak = position(peak);
+    (0..BAR_WIDTH)
+        .map(|idx| {
+            if idx < rms {
+                '='
+            } else if idx + 1 == peak {
+                '|'
+            } else {
+                ' '
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{bar, loudness, meter, true_peak, BAR_WIDTH};
+    use crate::metering::{Loudness, TruePeak};
+
+    #[test]
+    fn bars() {
+        assert_eq!(bar(0.0), "-".repeat(BAR_WIDTH));
+        assert_eq!(bar(0.5), format!("{}{}", "#".repeat(10), "-".repeat(10)));
+        assert_eq!(bar(2.0), "#".repeat(BAR_WIDTH));
+    }
+
+    #[test]
+    fn meters() {
+        // silence
+        assert_eq!(meter(0.0, 0.0), " ".repeat(BAR_WIDTH));
+        // -30 dB rms, 0 dB peak
+        let meter = meter(10f64.powf(-1.5), 1.0);
+        assert_eq!(meter, format!("{}{}|", "=".repeat(10), " ".repeat(9)));
+    }
+
+    #[test]
+    fn readouts() {
+        assert_eq!(
+            loudness(&Loudness::default()),
+            "S  -inf  I  -inf LUFS  LRA  0.0 LU"
+        );
+        let reading = Loudness {
+            momentary: -20.0,
+            short_term: -22.04,
+            integrated: -23.0,
+            range: 12.34,
+        };
+        assert_eq!(loudness(&reading), "S -22.0  I -23.0 LUFS  LRA 12.3 LU");
+        let reading = TruePeak {
+            peak: 0.5,
+            max: 2.0,
+            overs: 3,
+        };
+        assert_eq!(true_peak(&reading), "TP   6.0 dBTP  3 overs");
+    }
+}